@@ -0,0 +1,90 @@
+//! Compares the stack machine with the register machine. Run with `cargo bench`.
+
+extern crate rasp;
+
+use std::time::{Duration, Instant};
+use rasp::Engine;
+use rasp::interpreter::{Backend, Limits};
+
+const RECURSION: &'static str = include_str!("recursion.rasp");
+const STRINGS: &'static str = include_str!("strings.rasp");
+const RUNS: usize = 5;
+
+struct Workload {
+    name: &'static str,
+    setup: String,
+    code: String,
+}
+
+fn workloads() -> Vec<Workload> {
+    // A string that's expensive to copy, so that copying it on every push shows up
+    let big = "rasp".repeat(16 * 1024);
+    vec![
+        Workload { name: "fib", setup: RECURSION.to_string(), code: "(fib 20)".to_string() },
+        Workload { name: "factorial", setup: RECURSION.to_string(), code: "(factorial 20)".to_string() },
+        Workload { name: "countdown", setup: RECURSION.to_string(), code: "(countdown 5000)".to_string() },
+        Workload {
+            name: "pass-along",
+            setup: STRINGS.to_string(),
+            code: format!("(pass-along \"{}\" 2000)", big),
+        },
+        Workload {
+            name: "compare",
+            setup: STRINGS.to_string(),
+            code: format!("(compare \"{}\"\n\"{}\" 2000)", big, big),
+        },
+        Workload {
+            name: "pick",
+            setup: STRINGS.to_string(),
+            code: format!("(pick \"{}\"\n\"{}\" 2000)", big, "short"),
+        },
+        Workload {
+            name: "reread",
+            setup: format!("{}\n(&def text \"{}\")", STRINGS, big),
+            code: "(reread 2000)".to_string(),
+        },
+        Workload {
+            name: "rewrite",
+            setup: format!("{}\n(&def text \"{}\")", STRINGS, big),
+            code: "(rewrite 2000)".to_string(),
+        },
+    ]
+}
+
+/**
+ * Runs a workload a few times on the given VM, returning the fastest time and the result.
+ */
+fn measure(workload: &Workload, backend: Backend) -> (Duration, String) {
+    let mut engine = Engine::new();
+    engine.set_backend(backend);
+    engine.set_limits(Limits::unlimited());
+    engine.eval_named(&workload.setup, workload.name).unwrap();
+    let program = engine.compile(&workload.code, workload.name).unwrap();
+    let mut best = None;
+    let mut result = String::new();
+    for _ in 0 .. RUNS {
+        let start = Instant::now();
+        let value = engine.run(&program).unwrap();
+        let elapsed = start.elapsed();
+        if best.map(|b| elapsed < b).unwrap_or(true) {
+            best = Some(elapsed);
+        }
+        result = value.map(|v| format!("{}", v)).unwrap_or_default();
+    }
+    (best.unwrap(), result)
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1000.0 + d.subsec_nanos() as f64 / 1_000_000.0
+}
+
+fn main() {
+    println!("{:<12} {:>12} {:>12} {:>8}", "workload", "stack (ms)", "register (ms)", "speedup");
+    for workload in workloads() {
+        let (stack, stack_result) = measure(&workload, Backend::Stack);
+        let (register, register_result) = measure(&workload, Backend::Register);
+        assert_eq!(stack_result, register_result, "the VMs disagree on {}", workload.name);
+        println!("{:<12} {:>12.3} {:>12.3} {:>7.2}x",
+                 workload.name, millis(stack), millis(register), millis(stack) / millis(register));
+    }
+}
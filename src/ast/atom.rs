@@ -1,4 +1,5 @@
 use std::fmt;
+use internal::HeapRef;
 
 #[derive(Clone, Debug)]
 pub enum Atom {
@@ -7,6 +8,7 @@ pub enum Atom {
     BoolLit(bool),
     StrLit(String),
     Identifier(String),
+    Ref(HeapRef), /* A compound value on the interpreter's heap */
 }
 
 impl Atom {
@@ -16,7 +18,19 @@ impl Atom {
             &Atom::DubLit(f) => f != 0.0,
             &Atom::BoolLit(b) => b,
             &Atom::StrLit(ref s) => s.len() > 0,
-            _ => panic!("Literals only"),
+            &Atom::Ref(_) => true,
+            // Symbols that `&read` gives back are values like any other
+            &Atom::Identifier(_) => true,
+        }
+    }
+
+    /**
+     * Gets the heap object this refers to, if it's a compound value.
+     */
+    pub fn heap_ref(&self) -> Option<HeapRef> {
+        match self {
+            &Atom::Ref(r) => Some(r),
+            _ => None,
         }
     }
 
@@ -64,7 +78,14 @@ impl Atom {
                 else {
                     Atom::BoolLit(false)
                 },
-            _ => Atom::BoolLit(false)
+            &Atom::Identifier(ref lhs) => if let &Atom::Identifier(ref rhs) = other {
+                    Atom::BoolLit(*lhs == *rhs)
+                }
+                else {
+                    Atom::BoolLit(false)
+                },
+            // Without the heap, compound values can only be compared by identity
+            &Atom::Ref(ref lhs) => Atom::BoolLit(other.heap_ref() == Some(*lhs)),
         }
     }
 
@@ -135,6 +156,7 @@ impl fmt::Display for Atom {
             &Atom::BoolLit(b) => write!(f, "{}", b),
             &Atom::StrLit(ref s) => write!(f, "{}", s),
             &Atom::Identifier(ref n) => write!(f, "{}", n),
+            &Atom::Ref(r) => write!(f, "<object {}>", r),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,66 @@
+use ast::Span;
+
+/**
+ * A comment in the source text, running from a `;` to the end of its line. The grammar doesn't know about comments,
+ * so they're taken out of the source before it's parsed, and kept here for tools that care about them.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub text: String, /* The comment, including its `;` */
+    pub span: Span,
+    pub trailing: bool, /* Whether there's code before the comment on its line */
+}
+
+/**
+ * Blanks out every comment in the source text, returning the blanked text and the comments that were in it. Comments
+ * are replaced by spaces byte-for-byte, so offsets into the blanked text are offsets into the original too.
+ */
+pub fn strip_comments(source: &str) -> (String, Vec<Comment>) {
+    let mut stripped = String::with_capacity(source.len());
+    let mut comments = vec![];
+    let mut in_string = false;
+    let mut comment_start = None;
+    let mut code_on_line = false;
+    for (offset, c) in source.char_indices() {
+        if c == '\n' {
+            if let Some(start) = comment_start.take() {
+                comments.push(Comment {
+                    text: source[start .. offset].trim_right().to_string(),
+                    span: Span::new(start, offset),
+                    trailing: code_on_line,
+                });
+            }
+            // Strings can't span lines, so an unclosed quote is the parser's problem
+            in_string = false;
+            code_on_line = false;
+            stripped.push(c);
+        }
+        else if comment_start.is_some() {
+            for _ in 0 .. c.len_utf8() {
+                stripped.push(' ');
+            }
+        }
+        else {
+            if c == '"' {
+                in_string = !in_string;
+            }
+            else if c == ';' && !in_string {
+                comment_start = Some(offset);
+                stripped.push(' ');
+                continue;
+            }
+            if !c.is_whitespace() {
+                code_on_line = true;
+            }
+            stripped.push(c);
+        }
+    }
+    if let Some(start) = comment_start {
+        comments.push(Comment {
+            text: source[start ..].trim_right().to_string(),
+            span: Span::new(start, source.len()),
+            trailing: code_on_line,
+        });
+    }
+    (stripped, comments)
+}
@@ -1,10 +1,37 @@
-use ast::{Atom,Function,IfElse};
+use ast::{Atom,ExternDecl,Function,IfElse,Span,VarDef};
 
 #[derive(Clone, Debug)]
 pub enum Expression {
-    Atom(Atom),
-    Children(Vec<Expression>),
-    Unit,
+    Atom(Atom, Span),
+    Children(Vec<Expression>, Span),
+    Unit(Span),
+    Dict(Vec<(Expression, Expression)>, Span), /* A dictionary literal, as keys and values */
+    Vector(Vec<Expression>, Span),
     FunDef(Function),
+    TestDef(Function), /* A test, named by the function's name. Tests only run under `rasp test` */
     IfElse(Box<IfElse>),
+    Extern(ExternDecl),
+    VarDef(Box<VarDef>),
+    VarSet(Box<VarDef>), /* An assignment to a variable that already exists, e.g. `(&set! x 5)` */
+}
+
+impl Expression {
+    /**
+     * Gets the part of the source text that this expression came from.
+     */
+    pub fn span(&self) -> Span {
+        match self {
+            &Expression::Atom(_, span) => span,
+            &Expression::Children(_, span) => span,
+            &Expression::Unit(span) => span,
+            &Expression::Dict(_, span) => span,
+            &Expression::Vector(_, span) => span,
+            &Expression::FunDef(ref f) => f.span,
+            &Expression::TestDef(ref t) => t.span,
+            &Expression::IfElse(ref i) => i.span,
+            &Expression::Extern(ref e) => e.span,
+            &Expression::VarDef(ref d) => d.span,
+            &Expression::VarSet(ref s) => s.span,
+        }
+    }
 }
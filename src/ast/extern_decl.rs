@@ -0,0 +1,26 @@
+use ast::Span;
+
+/**
+ * A declaration of a function that lives in a foreign shared library, e.g.
+ * `(&extern "libm.so.6" cos (double) double)`
+ */
+#[derive(Clone, Debug)]
+pub struct ExternDecl {
+    pub library: String,
+    pub name: String,
+    pub args: Vec<String>,
+    pub ret: String,
+    pub span: Span,
+}
+
+impl ExternDecl {
+    pub fn new(library: String, name: String, args: Vec<String>, ret: String, span: Span) -> ExternDecl {
+        ExternDecl {
+            library: library,
+            name: name,
+            args: args,
+            ret: ret,
+            span: span,
+        }
+    }
+}
@@ -1,18 +1,20 @@
-use ast::Expression;
+use ast::{Expression, Span};
 
 #[derive(Clone, Debug)]
 pub struct Function {
     pub name: String,
     pub args: Vec<String>,
     pub body: Vec<Expression>,
+    pub span: Span,
 }
 
 impl Function {
-    pub fn new(name: String, args: Vec<String>, body: Vec<Expression>) -> Function {
+    pub fn new(name: String, args: Vec<String>, body: Vec<Expression>, span: Span) -> Function {
         Function {
             name: name,
             args: args,
             body: body,
+            span: span,
         }
     }
 }
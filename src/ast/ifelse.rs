@@ -1,8 +1,9 @@
-use ast::Expression;
+use ast::{Expression, Span};
 
 #[derive(Clone, Debug)]
 pub struct IfElse { 
     pub condition: Expression, 
     pub if_true: Expression, 
-    pub if_false: Option<Expression>
+    pub if_false: Option<Expression>,
+    pub span: Span,
 }
@@ -1,10 +1,18 @@
 mod atom;
+mod comment;
 mod expression;
+mod extern_decl;
 mod function;
 mod ifelse;
+mod span;
+mod var_def;
 pub mod visitor;
 
 pub use ast::expression::*;
 pub use ast::atom::*;
+pub use ast::comment::*;
+pub use ast::extern_decl::*;
 pub use ast::function::*;
-pub use ast::ifelse::*;
\ No newline at end of file
+pub use ast::ifelse::*;
+pub use ast::span::*;
+pub use ast::var_def::*;
\ No newline at end of file
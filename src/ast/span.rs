@@ -0,0 +1,21 @@
+/**
+ * A range of byte offsets into the source text that an AST node came from.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span {
+            start: start,
+            end: end,
+        }
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+}
@@ -0,0 +1,12 @@
+use ast::{Expression, Span};
+
+/**
+ * A variable definition, e.g. `(&def x 5)`. Inside a function this defines a local variable; at the top level it
+ * defines a global.
+ */
+#[derive(Clone, Debug)]
+pub struct VarDef {
+    pub name: String,
+    pub value: Expression,
+    pub span: Span,
+}
@@ -0,0 +1,19 @@
+extern crate rasp;
+
+use std::io::{self, BufReader, Write};
+use rasp::lsp;
+
+/**
+ * The language server on its own, for editors that want a single command to run. It's the same as `rasp lsp`.
+ */
+fn main() {
+    // The protocol runs over stdin and stdout, so nothing else can be printed to stdout
+    match lsp::serve(BufReader::new(io::stdin()), io::stdout()) {
+        Ok(true) => {},
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            let _ = writeln!(&mut io::stderr(), "rasp-lsp: {}", err);
+            std::process::exit(1);
+        },
+    }
+}
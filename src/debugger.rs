@@ -0,0 +1,311 @@
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use ast::visitor::MutVisitor;
+use internal::{Bytecode, CodeObject};
+use interpreter::{BytecodeGen, Hook, Interpreter, RuntimeError};
+use engine::parse;
+
+/**
+ * A place for the debugger to stop.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Breakpoint {
+    Line(usize),
+    Function(String),
+}
+
+/**
+ * What the program is doing until it next stops.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    /** Stop at the next line, even if it's in another function */
+    Step,
+    /** Stop at the next line in this function or one that called it */
+    Next(usize),
+    /** Stop once this function has returned */
+    Finish(usize),
+    /** Only stop at breakpoints */
+    Continue,
+}
+
+/**
+ * A function that's running, as far as the debugger knows.
+ */
+struct Frame {
+    name: String,
+    line: usize, /* The line that the function is on, or 0 if it hasn't started a line yet */
+}
+
+const HELP: &'static str = "\
+Commands:
+  s, step             run to the next line, stepping into calls
+  n, next             run to the next line, stepping over calls
+  f, finish           run until the current function returns
+  c, continue         run until the next breakpoint
+  b, break LINE|NAME  stop at a line, or when a function is called
+  d, delete N         remove breakpoint number N
+  i, info             list breakpoints
+  l, list             show the source around the current line
+  bt, where           show the functions that are running
+  stack               show the value stack
+  locals              show the current function's variables
+  globals             show the global variables
+  p, print EXPR       evaluate an expression in the current frame
+  q, quit             stop the program
+An empty line repeats the last command. Only the program's own code is stepped through and stopped in; code from
+the prelude, `&load` and `&eval` runs without stopping.";
+
+/**
+ * An interactive debugger for one program. It only stops in code from the program it was made for, so stepping goes
+ * over calls into the prelude and other files.
+ */
+pub struct Debugger {
+    source_name: String,
+    source: Vec<String>,
+    code: HashSet<*const CodeObject>, /* The program's code objects */
+    breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+    frames: Vec<Frame>,
+    last_command: String,
+    input: Box<BufRead>,
+    output: Box<Write>,
+}
+
+impl Debugger {
+    /**
+     * Creates a debugger for a program, reading commands from `input` and writing to `output`. It starts out paused at
+     * the program's first line.
+     */
+    pub fn new<R, W>(program: &CodeObject, source_name: &str, source: &str, input: R, output: W) -> Debugger
+        where R: BufRead + 'static, W: Write + 'static
+    {
+        let mut code = HashSet::new();
+        add_code(program, &mut code);
+        Debugger {
+            source_name: source_name.to_string(),
+            source: source.lines().map(|l| l.to_string()).collect(),
+            code: code,
+            breakpoints: vec![],
+            mode: Mode::Step,
+            frames: vec![Frame { name: source_name.to_string(), line: 0 }],
+            last_command: String::new(),
+            input: Box::new(input),
+            output: Box::new(output),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    fn depth(&self) -> usize {
+        self.frames.len() - 1
+    }
+
+    fn source_line(&self, line: usize) -> &str {
+        if line > 0 && line <= self.source.len() { &self.source[line - 1] } else { "" }
+    }
+
+    /**
+     * Decides whether to stop before an instruction.
+     */
+    fn should_stop(&mut self, code: &CodeObject, index: usize) -> bool {
+        let depth = self.depth();
+        // Functions start by storing their arguments, and there's nothing to look at until they have
+        if depth > 0 && index < code.args.len() {
+            return false;
+        }
+        let line = code.lines[index];
+        let ours = self.code.contains(&(code as *const CodeObject));
+        let new_line = {
+            let frame = self.frames.last_mut().unwrap();
+            let new_line = line != 0 && line != frame.line;
+            if line != 0 {
+                frame.line = line;
+            }
+            new_line
+        };
+        if !ours || !new_line {
+            return false;
+        }
+        let at_breakpoint = self.breakpoints.iter().any(|b| match b {
+            &Breakpoint::Line(l) => l == line,
+            &Breakpoint::Function(ref name) => depth > 0 && index == code.args.len() && *name == code.name,
+        });
+        at_breakpoint || match self.mode {
+            Mode::Step => true,
+            Mode::Next(d) => depth <= d,
+            Mode::Finish(d) => depth < d,
+            Mode::Continue => false,
+        }
+    }
+
+    /**
+     * Reads and runs commands until one of them resumes the program.
+     */
+    fn pause(&mut self, interp: &mut Interpreter, code: &CodeObject) -> Result<(), RuntimeError> {
+        let line = self.frames.last().unwrap().line;
+        let text = self.source_line(line).to_string();
+        let _ = writeln!(self.output, "{}:{} in {}: {}", self.source_name, line, code.name, text);
+        loop {
+            let _ = write!(self.output, "(rasp) ");
+            let _ = self.output.flush();
+            let mut command = String::new();
+            match self.input.read_line(&mut command) {
+                Ok(0) | Err(_) => return Err("Stopped by the debugger".to_string().into()),
+                Ok(_) => {},
+            }
+            let mut command = command.trim().to_string();
+            if command.is_empty() {
+                command = self.last_command.clone();
+            }
+            self.last_command = command.clone();
+            let (name, arg) = match command.find(' ') {
+                Some(space) => (&command[.. space], command[space + 1 ..].trim()),
+                None => (&command[..], ""),
+            };
+            let depth = self.depth();
+            match name {
+                "s" | "step" => { self.mode = Mode::Step; return Ok(()); },
+                "n" | "next" => { self.mode = Mode::Next(depth); return Ok(()); },
+                "f" | "finish" => { self.mode = Mode::Finish(depth); return Ok(()); },
+                "c" | "continue" => { self.mode = Mode::Continue; return Ok(()); },
+                "q" | "quit" => return Err("Stopped by the debugger".to_string().into()),
+                "b" | "break" => self.break_command(arg),
+                "d" | "delete" => self.delete_command(arg),
+                "i" | "info" => for (i, b) in self.breakpoints.iter().enumerate() {
+                    let _ = match b {
+                        &Breakpoint::Line(l) => writeln!(self.output, "{}: line {}", i + 1, l),
+                        &Breakpoint::Function(ref name) => writeln!(self.output, "{}: function {}", i + 1, name),
+                    };
+                },
+                "l" | "list" => {
+                    let first = if line > 5 { line - 5 } else { 1 };
+                    for l in first .. line + 6 {
+                        if l <= self.source.len() {
+                            let marker = if l == line { "->" } else { "  " };
+                            let text = self.source_line(l).to_string();
+                            let _ = writeln!(self.output, "{} {:4} {}", marker, l, text);
+                        }
+                    }
+                },
+                "bt" | "where" => for (depth, frame) in self.frames.iter().enumerate().rev() {
+                    let _ = writeln!(self.output, "#{} {} at line {}", depth, frame.name, frame.line);
+                },
+                "stack" => for (i, atom) in interp.stack().iter().enumerate().rev() {
+                    let _ = writeln!(self.output, "{:4}: {}", i, interp.heap().format(&atom));
+                },
+                "locals" => match interp.frame() {
+                    Some(frame) if depth > 0 => for (name, value) in code.locals.iter().zip(frame) {
+                        let value = match value {
+                            &Some(ref atom) => interp.heap().format(atom),
+                            &None => "<undefined>".to_string(),
+                        };
+                        let _ = writeln!(self.output, "{} = {}", name, value);
+                    },
+                    _ => { let _ = writeln!(self.output, "Not in a function"); },
+                },
+                "globals" => for (name, atom) in interp.globals() {
+                    let _ = writeln!(self.output, "{} = {}", name, interp.heap().format(&atom));
+                },
+                "p" | "print" => {
+                    let result = self.eval(interp, code, arg);
+                    let _ = match result {
+                        Ok(Some(atom)) => writeln!(self.output, "{}", interp.heap().format(&atom)),
+                        Ok(None) => writeln!(self.output, "(no value)"),
+                        Err(err) => writeln!(self.output, "Error: {}", err),
+                    };
+                },
+                "h" | "help" => { let _ = writeln!(self.output, "{}", HELP); },
+                _ => { let _ = writeln!(self.output, "Unknown command `{}'; try `help'", name); },
+            }
+        }
+    }
+
+    fn break_command(&mut self, arg: &str) {
+        if arg.is_empty() {
+            let _ = writeln!(self.output, "break needs a line number or a function name");
+            return;
+        }
+        let breakpoint = match arg.parse() {
+            Ok(line) => Breakpoint::Line(line),
+            Err(_) => Breakpoint::Function(arg.to_string()),
+        };
+        self.breakpoints.push(breakpoint);
+        let _ = writeln!(self.output, "Breakpoint {} set", self.breakpoints.len());
+    }
+
+    fn delete_command(&mut self, arg: &str) {
+        match arg.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= self.breakpoints.len() => {
+                self.breakpoints.remove(n - 1);
+            },
+            _ => { let _ = writeln!(self.output, "No breakpoint number `{}'", arg); },
+        }
+    }
+
+    /**
+     * Compiles an expression against the paused function's variables, and runs it in that function's frame.
+     */
+    fn eval(&mut self, interp: &mut Interpreter, code: &CodeObject, source: &str)
+        -> Result<Option<::ast::Atom>, String>
+    {
+        let exprs = try!(parse(source, "<eval>").map_err(|err| format!("{}", err)));
+        let in_function = self.depth() > 0;
+        let compiled = {
+            let symbols = interp.symbols_mut();
+            let mut gen = if in_function {
+                BytecodeGen::for_function(&code.locals, symbols)
+            }
+            else {
+                BytecodeGen::new(symbols)
+            };
+            for expr in &exprs {
+                gen.visit_expression(expr);
+            }
+            if gen.was_err() {
+                return Err(gen.errors.join("; "));
+            }
+            try!(gen.finish("<eval>", &code.args))
+        };
+        interp.run_in_frame(&compiled).map_err(|err| format!("{}", err))
+    }
+}
+
+impl Hook for Debugger {
+    fn instruction(&mut self, interp: &mut Interpreter, code: &CodeObject, index: usize) -> Result<(), RuntimeError> {
+        if self.should_stop(code, index) {
+            self.pause(interp, code)
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    fn call(&mut self, _interp: &mut Interpreter, function: &CodeObject) {
+        self.frames.push(Frame { name: function.name.clone(), line: 0 });
+    }
+
+    fn ret(&mut self, _interp: &mut Interpreter, _function: &CodeObject) {
+        self.frames.pop();
+        // Finishing stops in the caller as soon as it gets control back, even though it's still on the same line
+        if let Mode::Finish(depth) = self.mode {
+            if self.depth() < depth {
+                self.frames.last_mut().unwrap().line = 0;
+            }
+        }
+    }
+}
+
+/**
+ * Adds a code object to the set, along with every function that it defines, however deeply.
+ */
+fn add_code(code: &CodeObject, set: &mut HashSet<*const CodeObject>) {
+    set.insert(code as *const CodeObject);
+    for b in &code.code {
+        if let &Bytecode::FunDef(ref fun) = b {
+            add_code(fun, set);
+        }
+    }
+}
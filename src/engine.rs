@@ -0,0 +1,368 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+use ast::{Atom, Expression, strip_comments};
+use ast::visitor::MutVisitor;
+use internal::{Capabilities, CodeFileError, CodeObject, GcStats, NativeFn, read_code_file, write_code_file};
+use interpreter::{Backend, BytecodeGen, Interpreter, LimitExceeded, Limits, Linter, Optimizer, RuntimeError,
+                  SymbolTable};
+use rasp;
+use util::RaspParseError;
+
+/**
+ * The standard prelude, loaded into every engine before any user code unless it's created with `Engine::bare`.
+ */
+pub const PRELUDE: &'static str = include_str!("prelude.rasp");
+
+/**
+ * Everything that can go wrong when running rasp code through an engine.
+ */
+#[derive(Clone, Debug)]
+pub enum Error {
+    Parse(String),
+    Compile(Vec<String>),
+    Runtime(String),
+    Limit(LimitExceeded), /* The script was stopped for going over one of the interpreter's limits */
+    Load(CodeFileError),
+}
+
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Error {
+        match err {
+            RuntimeError::Error(msg) => Error::Runtime(msg),
+            RuntimeError::Limit(limit) => Error::Limit(limit),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::Parse(ref msg) => write!(f, "{}", msg),
+            &Error::Compile(ref errors) => write!(f, "{}", errors.join("\n")),
+            &Error::Runtime(ref msg) => write!(f, "{}", msg),
+            &Error::Limit(ref limit) => write!(f, "{}", limit),
+            &Error::Load(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/**
+ * Source text that's been parsed, compiled and linked, but not run yet. The top-level code is a code object named after
+ * the source it came from.
+ */
+pub struct Program {
+    pub code: Rc<CodeObject>,
+    pub warnings: Vec<String>,
+}
+
+/**
+ * Parses source text, skipping comments. `source_name` is used for error messages.
+ */
+pub fn parse(source: &str, source_name: &str) -> Result<Vec<Expression>, Error> {
+    let (stripped, _) = strip_comments(source);
+    match rasp::parse_CompileUnit(&stripped) {
+        Ok(exprs) => Ok(exprs),
+        Err(err) => Err(Error::Parse(format!("{}", RaspParseError::new(err, source, source_name)))),
+    }
+}
+
+/**
+ * Parses and compiles source text against a symbol table for global variables. `source_name` is used for error
+ * messages. If an optimizer is given, the code is optimized too; functions and externs defined by the source are
+ * added to the optimizer's shadowed names. If a linter is given, its warnings come before the compiler's.
+ */
+pub fn compile(source: &str, source_name: &str, symbols: &mut SymbolTable, optimizer: Option<Optimizer>,
+               linter: Option<&mut Linter>) -> Result<Program, Error>
+{
+    let expr_list = try!(parse(source, source_name));
+    compile_exprs(&expr_list, source, source_name, symbols, optimizer, linter)
+}
+
+/**
+ * Compiles expressions that were parsed from `source`, like `compile`. The expressions don't have to be all of the
+ * source; the source is only used to work out line numbers and for messages.
+ */
+pub fn compile_exprs(expr_list: &[Expression], source: &str, source_name: &str, symbols: &mut SymbolTable,
+                     optimizer: Option<Optimizer>, linter: Option<&mut Linter>) -> Result<Program, Error>
+{
+    let mut warnings = match linter {
+        Some(linter) => linter.lint(expr_list, source, source_name),
+        None => vec![],
+    };
+    let mut gen = BytecodeGen::new(symbols);
+    gen.set_source(source);
+    if let Some(mut optimizer) = optimizer {
+        for ast in expr_list {
+            match ast {
+                &Expression::FunDef(ref f) => optimizer.shadow(&f.name),
+                &Expression::Extern(ref e) => optimizer.shadow(&e.name),
+                _ => {},
+            }
+        }
+        gen.set_optimizer(optimizer);
+    }
+    for ast in expr_list {
+        gen.visit_expression(ast);
+    }
+    if gen.was_err() {
+        return Err(Error::Compile(gen.errors));
+    }
+    match gen.finish(source_name, &vec![]) {
+        Ok(code) => {
+            warnings.extend(gen.warnings);
+            Ok(Program { code: Rc::new(code), warnings: warnings })
+        },
+        Err(err) => Err(Error::Compile(vec![err])),
+    }
+}
+
+/**
+ * A cloneable output sink that collects everything written to it in memory.
+ */
+#[derive(Clone)]
+pub struct OutputBuffer {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl OutputBuffer {
+    pub fn new() -> OutputBuffer {
+        OutputBuffer { buffer: Rc::new(RefCell::new(vec![])) }
+    }
+
+    /**
+     * Gets everything written so far as a string.
+     */
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.borrow()).into_owned()
+    }
+
+    /**
+     * Gets everything written so far, and empties the buffer.
+     */
+    pub fn take(&self) -> String {
+        let contents = self.contents();
+        self.buffer.borrow_mut().clear();
+        contents
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/**
+ * A rasp interpreter along with everything needed to run source text in it.
+ *
+ * ```ignore
+ * let mut engine = Engine::new();
+ * engine.eval("(&fun twice (x) (* 2 x))").unwrap();
+ * let result = engine.call("twice", vec![Atom::IntLit(21)]).unwrap();
+ * ```
+ */
+pub struct Engine {
+    interp: Interpreter,
+    warnings: Vec<String>,
+    optimize: bool,
+}
+
+impl Engine {
+    /**
+     * Creates an engine with the standard prelude loaded.
+     */
+    pub fn new() -> Engine {
+        let mut engine = Engine::bare();
+        if let Err(err) = engine.eval_named(PRELUDE, "<prelude>") {
+            panic!("the standard prelude failed to load: {}", err);
+        }
+        engine
+    }
+
+    /**
+     * Creates an engine without the standard prelude.
+     */
+    pub fn bare() -> Engine {
+        Engine {
+            interp: Interpreter::new(),
+            warnings: vec![],
+            optimize: false,
+        }
+    }
+
+    /**
+     * Turns the bytecode optimizer on or off for everything compiled from now on. It's off by default.
+     */
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /**
+     * Forces a garbage collection, returning the number of heap objects that were freed. Values that the embedder is
+     * holding on to aren't roots, so any heap references among them may be freed.
+     */
+    pub fn collect_garbage(&mut self) -> usize {
+        self.interp.collect_garbage()
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.interp.gc_stats()
+    }
+
+    /**
+     * Sets the limits that scripts are stopped at. See `Interpreter::set_limits`.
+     */
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interp.set_limits(limits);
+    }
+
+    /**
+     * Sets what scripts are allowed to do outside of the interpreter. Use `Capabilities::none()` for untrusted code.
+     */
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.interp.set_capabilities(capabilities);
+    }
+
+    /**
+     * Chooses the virtual machine that code runs on. See `Interpreter::set_backend`.
+     */
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.interp.set_backend(backend);
+    }
+
+    /**
+     * Evaluates source text, returning the value of the last top-level expression if it had one.
+     */
+    pub fn eval(&mut self, source: &str) -> Result<Option<Atom>, Error> {
+        self.eval_named(source, "<eval>")
+    }
+
+    /**
+     * Evaluates source text, using `source_name` in error messages.
+     */
+    pub fn eval_named(&mut self, source: &str, source_name: &str) -> Result<Option<Atom>, Error> {
+        let program = try!(self.compile(source, source_name));
+        self.warnings.extend(program.warnings.iter().cloned());
+        self.run(&program)
+    }
+
+    /**
+     * Compiles source text so that it can be run in this engine.
+     */
+    pub fn compile(&mut self, source: &str, source_name: &str) -> Result<Program, Error> {
+        let exprs = try!(parse(source, source_name));
+        self.compile_exprs(&exprs, source, source_name)
+    }
+
+    /**
+     * Compiles expressions that were parsed from `source` so that they can be run in this engine. See
+     * `compile_exprs`.
+     */
+    pub fn compile_exprs(&mut self, exprs: &[Expression], source: &str, source_name: &str)
+        -> Result<Program, Error>
+    {
+        let optimizer = if self.optimize {
+            Some(Optimizer::new(self.interp.function_names().into_iter().collect()))
+        }
+        else {
+            None
+        };
+        let mut linter = self.interp.linter();
+        compile_exprs(exprs, source, source_name, self.interp.symbols_mut(), optimizer, Some(&mut linter))
+    }
+
+    /**
+     * Writes a program out in the compiled file format, so that it can be loaded again without reparsing it.
+     */
+    pub fn write_compiled(&self, program: &Program, out: &mut Write) -> io::Result<()> {
+        write_code_file(&program.code, self.interp.symbols(), out)
+    }
+
+    /**
+     * Loads a program from the bytes of a compiled file.
+     */
+    pub fn load_compiled(&mut self, bytes: &[u8]) -> Result<Program, Error> {
+        match read_code_file(bytes, self.interp.symbols_mut()) {
+            Ok(code) => Ok(Program { code: Rc::new(code), warnings: vec![] }),
+            Err(err) => Err(Error::Load(err)),
+        }
+    }
+
+    /**
+     * Gets a listing of a program's bytecode, including every function it defines.
+     */
+    pub fn disassemble(&self, program: &Program) -> String {
+        format!("{}", program.code.disassemble(self.interp.symbols()))
+    }
+
+    /**
+     * Runs a program that was compiled for this engine.
+     */
+    pub fn run(&mut self, program: &Program) -> Result<Option<Atom>, Error> {
+        self.interp
+            .run(&program.code)
+            .map_err(Error::from)
+    }
+
+    /**
+     * Calls a rasp function by name.
+     */
+    pub fn call(&mut self, name: &str, args: Vec<Atom>) -> Result<Atom, Error> {
+        self.interp
+            .call(name, args)
+            .map_err(Error::from)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Atom> {
+        self.interp.get_global(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Atom) {
+        self.interp.set_global(name, value);
+    }
+
+    /**
+     * Registers a Rust function or closure that can be called from rasp code. See
+     * `Interpreter::register_function`.
+     */
+    pub fn register_function<F, Args>(&mut self, name: &str, fun: F)
+        where F: NativeFn<Args> + 'static, Args: 'static
+    {
+        self.interp.register_function(name, fun);
+    }
+
+    /**
+     * Redirects printed output into a buffer, which is returned so it can be read later.
+     */
+    pub fn capture_output(&mut self) -> OutputBuffer {
+        let buffer = OutputBuffer::new();
+        self.interp.set_output(buffer.clone());
+        buffer
+    }
+
+    /**
+     * Redirects error output into a buffer, which is returned so it can be read later.
+     */
+    pub fn capture_errors(&mut self) -> OutputBuffer {
+        let buffer = OutputBuffer::new();
+        self.interp.set_error(buffer.clone());
+        buffer
+    }
+
+    /**
+     * Gets the warnings from everything evaluated so far, and clears them.
+     */
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        self.warnings.drain(..).collect()
+    }
+
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interp
+    }
+}
@@ -0,0 +1,347 @@
+use ast::{Comment, Expression, Span, strip_comments};
+use engine::{Error, parse};
+
+/**
+ * How formatted code should be laid out.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FormatOptions {
+    pub indent: usize, /* Spaces to indent function bodies by */
+    pub width: usize, /* Lines are kept under this many columns where possible */
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions { indent: 4, width: 80 }
+    }
+}
+
+/**
+ * Formats rasp source text in the house style, keeping its comments:
+ *
+ * * a form that fits on the rest of its line is written on one line;
+ * * function bodies always start on a new line, indented;
+ * * `&else` always starts a new line, lined up with its `&if`;
+ * * a call that doesn't fit puts each argument on its own line, lined up with the first one;
+ * * one blank line is kept wherever the source had one or more.
+ */
+pub fn format_source(source: &str, source_name: &str, options: &FormatOptions) -> Result<String, Error> {
+    let exprs = try!(parse(source, source_name));
+    let (_, comments) = strip_comments(source);
+    let mut formatter = Formatter {
+        source: source,
+        options: *options,
+        comments: comments,
+        next_comment: 0,
+        out: String::new(),
+        col: 0,
+        line_start: true,
+        last_offset: 0,
+    };
+    for expr in &exprs {
+        formatter.separate(expr.span().start, 0);
+        formatter.expr(expr);
+    }
+    formatter.flush_comments(source.len(), 0);
+    formatter.finish_line();
+    Ok(formatter.out)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    options: FormatOptions,
+    comments: Vec<Comment>,
+    next_comment: usize, /* The first comment that hasn't been written yet */
+    out: String,
+    col: usize,
+    line_start: bool, /* Whether nothing has been written on the current line yet */
+    last_offset: usize, /* Where in the source the last thing that was written ended */
+}
+
+impl<'a> Formatter<'a> {
+    fn write(&mut self, text: &str) {
+        self.out.push_str(text);
+        self.col += text.chars().count();
+        self.line_start = false;
+    }
+
+    /**
+     * Ends the current line, without any trailing whitespace.
+     */
+    fn finish_line(&mut self) {
+        let trimmed = self.out.trim_right_matches(' ').len();
+        self.out.truncate(trimmed);
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.finish_line();
+        for _ in 0 .. indent {
+            self.out.push(' ');
+        }
+        self.col = indent;
+        self.line_start = true;
+    }
+
+    /**
+     * Makes sure that the next thing is written at the start of a line, keeping one blank line before it if the
+     * source had any before `offset`.
+     */
+    fn start_line(&mut self, offset: usize, indent: usize) {
+        if !self.line_start {
+            self.newline(indent);
+        }
+        let blank = offset > self.last_offset && self.source[self.last_offset .. offset].matches('\n').count() > 1;
+        let written = self.out.trim_right_matches(' ');
+        if blank && !written.is_empty() && !written.ends_with("\n\n") {
+            self.finish_line();
+            self.out.push('\n');
+            self.newline(indent);
+        }
+    }
+
+    /**
+     * Writes every comment that comes before `offset`. Trailing comments stay at the end of the line they were on,
+     * and the rest get lines of their own.
+     */
+    fn flush_comments(&mut self, offset: usize, indent: usize) {
+        while self.next_comment < self.comments.len() && self.comments[self.next_comment].span.start < offset {
+            let comment = self.comments[self.next_comment].clone();
+            self.next_comment += 1;
+            if comment.trailing && !self.line_start {
+                self.write(" ");
+            }
+            else {
+                self.start_line(comment.span.start, indent);
+            }
+            self.write(&comment.text);
+            self.last_offset = comment.span.end;
+            // Nothing else can go on a line after a comment
+            self.newline(indent);
+        }
+    }
+
+    /**
+     * Gets ready to write something that starts at `offset` on a new line, writing any comments before it first.
+     */
+    fn separate(&mut self, offset: usize, indent: usize) {
+        self.flush_comments(offset, indent);
+        self.start_line(offset, indent);
+    }
+
+    fn has_comments(&self, span: Span) -> bool {
+        self.comments[self.next_comment ..].iter().any(|c| c.span.start >= span.start && c.span.start < span.end)
+    }
+
+    /**
+     * Writes an expression on one line, if it can be.
+     */
+    fn flat(&self, expr: &Expression) -> Option<String> {
+        if self.has_comments(expr.span()) {
+            return None;
+        }
+        match expr {
+            &Expression::Atom(_, span) => Some(self.source[span.start .. span.end].to_string()),
+            &Expression::Unit(_) => Some("()".to_string()),
+            &Expression::Dict(ref entries, _) => {
+                let mut parts = vec![];
+                for &(ref key, ref value) in entries {
+                    parts.push(format!("{} {}", try_opt!(self.flat(key)), try_opt!(self.flat(value))));
+                }
+                Some(format!("{{{}}}", parts.join(" ")))
+            },
+            &Expression::Vector(ref items, _) => {
+                let mut parts = vec![];
+                for item in items {
+                    parts.push(try_opt!(self.flat(item)));
+                }
+                Some(format!("[{}]", parts.join(" ")))
+            },
+            &Expression::Children(ref children, _) => {
+                let mut parts = vec![];
+                for child in children {
+                    parts.push(try_opt!(self.flat(child)));
+                }
+                Some(format!("({})", parts.join(" ")))
+            },
+            &Expression::VarDef(ref def) => Some(format!("(&def {} {})", def.name, try_opt!(self.flat(&def.value)))),
+            &Expression::VarSet(ref set) => Some(format!("(&set! {} {})", set.name, try_opt!(self.flat(&set.value)))),
+            &Expression::IfElse(ref ifelse) => match ifelse.if_false {
+                Some(_) => None,
+                None => Some(format!("(&if {} {})", try_opt!(self.flat(&ifelse.condition)),
+                                     try_opt!(self.flat(&ifelse.if_true)))),
+            },
+            &Expression::Extern(ref decl) =>
+                Some(format!("(&extern \"{}\" {} ({}) {})", decl.library, decl.name, decl.args.join(" "), decl.ret)),
+            &Expression::FunDef(_) | &Expression::TestDef(_) => None,
+        }
+    }
+
+    /**
+     * Whether `text` fits on the current line after a space.
+     */
+    fn fits_after_space(&self, text: &str) -> bool {
+        self.col + 1 + text.chars().count() <= self.options.width
+    }
+
+    /**
+     * Writes an expression starting at the current column.
+     */
+    fn expr(&mut self, expr: &Expression) {
+        let col = self.col;
+        let span = expr.span();
+        if let Some(text) = self.flat(expr) {
+            if col + text.chars().count() <= self.options.width || is_atom(expr) {
+                self.write(&text);
+                self.last_offset = span.end;
+                return;
+            }
+        }
+        self.last_offset = span.start;
+        match expr {
+            // Atoms always have a flat form, so only a unit with a comment in it can get here
+            &Expression::Atom(_, _) => unreachable!(),
+            &Expression::Unit(_) => self.write("("),
+            &Expression::Vector(ref items, _) => {
+                // One item to a line, lined up with the first
+                self.write("[");
+                for (i, item) in items.iter().enumerate() {
+                    if i == 0 {
+                        self.flush_comments(item.span().start, col + 1);
+                    }
+                    else {
+                        self.separate(item.span().start, col + 1);
+                    }
+                    self.expr(item);
+                }
+            },
+            &Expression::Dict(ref entries, _) => {
+                // One entry to a line, with values that don't fit after their keys lined up under them
+                self.write("{");
+                for (i, &(ref key, ref value)) in entries.iter().enumerate() {
+                    if i == 0 {
+                        self.flush_comments(key.span().start, col + 1);
+                    }
+                    else {
+                        self.separate(key.span().start, col + 1);
+                    }
+                    self.expr(key);
+                    let flat = self.flat(value);
+                    match flat {
+                        Some(ref text) if self.fits_after_space(text) && !self.line_start
+                            && !self.has_comments(Span::new(self.last_offset, value.span().start)) => {
+                            self.write(" ");
+                            self.write(text);
+                            self.last_offset = value.span().end;
+                        },
+                        _ => {
+                            self.separate(value.span().start, col + 1);
+                            self.expr(value);
+                        },
+                    }
+                }
+            },
+            &Expression::Children(ref children, _) => {
+                self.write("(");
+                self.flush_comments(children[0].span().start, col + 1);
+                self.expr(&children[0]);
+                // Arguments line up with the first one, unless the function is something complicated
+                let hanging = is_atom(&children[0]) && children.len() > 1 && !self.line_start
+                    && !self.has_comments(Span::new(children[0].span().end, children[1].span().start));
+                let (arg_col, rest) = if hanging {
+                    self.write(" ");
+                    let arg_col = self.col;
+                    self.expr(&children[1]);
+                    (arg_col, 2)
+                }
+                else {
+                    (col + 1, 1)
+                };
+                for child in children.iter().skip(rest) {
+                    self.separate(child.span().start, arg_col);
+                    self.expr(child);
+                }
+            },
+            &Expression::FunDef(ref fun) => {
+                self.write(&format!("(&fun {} ({})", fun.name, fun.args.join(" ")));
+                let body_col = col + self.options.indent;
+                for body in &fun.body {
+                    self.separate(body.span().start, body_col);
+                    self.expr(body);
+                }
+            },
+            &Expression::TestDef(ref test) => {
+                self.write(&format!("(&deftest \"{}\"", test.name));
+                let body_col = col + self.options.indent;
+                for body in &test.body {
+                    self.separate(body.span().start, body_col);
+                    self.expr(body);
+                }
+            },
+            &Expression::IfElse(ref ifelse) => {
+                self.write("(&if ");
+                let cond_col = self.col;
+                self.expr(&ifelse.condition);
+                let then = self.flat(&ifelse.if_true);
+                let start = ifelse.if_true.span().start;
+                match then {
+                    Some(ref text) if self.fits_after_space(text) && !self.line_start
+                        && !self.has_comments(Span::new(self.last_offset, start)) => {
+                        self.write(" ");
+                        self.write(text);
+                        self.last_offset = ifelse.if_true.span().end;
+                    },
+                    _ => {
+                        self.separate(start, cond_col);
+                        self.expr(&ifelse.if_true);
+                    },
+                }
+                if let Some(ref if_false) = ifelse.if_false {
+                    self.separate(if_false.span().start, col + 1);
+                    self.write("&else ");
+                    self.expr(if_false);
+                }
+            },
+            &Expression::VarDef(ref def) | &Expression::VarSet(ref def) => {
+                let keyword = match expr {
+                    &Expression::VarSet(_) => "&set!",
+                    _ => "&def",
+                };
+                self.write(&format!("({} {}", keyword, def.name));
+                let value = self.flat(&def.value);
+                match value {
+                    Some(ref text) if self.fits_after_space(text) => {
+                        self.write(" ");
+                        self.write(text);
+                    },
+                    _ => {
+                        let indent = col + self.options.indent;
+                        self.separate(def.value.span().start, indent);
+                        self.expr(&def.value);
+                    },
+                }
+            },
+            &Expression::Extern(ref decl) => {
+                let text = format!("(&extern \"{}\" {} ({}) {}", decl.library, decl.name, decl.args.join(" "), decl.ret);
+                self.write(&text);
+            },
+        }
+        // Comments after the last part of the form have to be written before it's closed
+        self.flush_comments(span.end, col + 1);
+        self.write(match expr {
+            &Expression::Dict(_, _) => "}",
+            &Expression::Vector(_, _) => "]",
+            _ => ")",
+        });
+        self.last_offset = span.end;
+    }
+}
+
+fn is_atom(expr: &Expression) -> bool {
+    match expr {
+        &Expression::Atom(_, _) | &Expression::Unit(_) => true,
+        _ => false,
+    }
+}
@@ -1,8 +1,10 @@
 use ast::Atom;
-use std::collections::HashMap;
-use interpreter::VarDecls;
+use internal::{Capability, DictKey, Heap, HeapRef, Object, Place};
+use interpreter::{Interpreter, RuntimeError, eval_builtins, sequence_builtins};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
 
-pub type BuiltinImp = fn(BuiltinContext) -> Result<Atom, String>; 
+pub type BuiltinImp = fn(BuiltinContext) -> Result<Atom, RuntimeError>;
 
 /**
  * Builtin functions
@@ -11,161 +13,530 @@ pub struct BuiltinFunction {
     pub name: &'static str,
     pub args: Vec<&'static str>,
     pub fun: BuiltinImp,
+    pub capability: Option<Capability>, /* What the builtin needs to be allowed to do, if it reaches outside */
+    pub calls_back: bool, /* Whether the builtin runs rasp code, which can collect garbage before it returns */
+}
+
+impl BuiltinFunction {
+    pub fn new(name: &'static str, args: Vec<&'static str>, fun: BuiltinImp) -> BuiltinFunction {
+        BuiltinFunction { name: name, args: args, fun: fun, capability: None, calls_back: false }
+    }
+
+    /**
+     * Marks the builtin as needing a capability.
+     */
+    pub fn needs(mut self, capability: Capability) -> BuiltinFunction {
+        self.capability = Some(capability);
+        self
+    }
+
+    /**
+     * Marks the builtin as one that runs rasp code. Anything it holds on to across a call has to be pinned with
+     * `BuiltinContext::with_pinned`, or a garbage collection could free it.
+     */
+    pub fn calls_back(mut self) -> BuiltinFunction {
+        self.calls_back = true;
+        self
+    }
 }
 
 /**
- * Context for builtin functions to use
+ * Context for builtin functions to use: their arguments, in the order they were passed, and the interpreter that's
+ * running them.
  */
 pub struct BuiltinContext<'a> {
     pub args: Vec<Atom>,
-    pub global_decls: &'a VarDecls,
-    pub var_decls: Option<&'a VarDecls>,
+    pub interp: &'a mut Interpreter,
 }
 
 impl<'a> BuiltinContext<'a> {
-    fn get_var(&self, label: &str) -> Option<&Atom> {
-        if let Some(decls) = self.var_decls {
-            match decls.get(label) {
-                Some(ref atom) => Some(atom),
-                None => self.global_decls.get(label), 
-            }
-        }
-        else {
-            self.global_decls.get(label)
+    pub fn heap(&self) -> &Heap {
+        self.interp.heap()
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        self.interp.heap_mut()
+    }
+
+    /**
+     * Puts an object on the heap, returning a reference to it.
+     */
+    pub fn alloc(&mut self, object: Object) -> Atom {
+        Atom::Ref(self.interp.heap_mut().alloc(object))
+    }
+
+    /**
+     * Gets the writer that &print writes to.
+     */
+    pub fn output(&mut self) -> &mut Write {
+        self.interp.output()
+    }
+
+    /**
+     * Gets the writer that &eprint writes to.
+     */
+    pub fn error(&mut self) -> &mut Write {
+        self.interp.error()
+    }
+
+    /**
+     * Calls a function, named by a string, with the given arguments. See `Interpreter::call_value`.
+     */
+    pub fn call(&mut self, function: &Atom, args: Vec<Atom>) -> Result<Atom, RuntimeError> {
+        self.interp.call_value(function, args)
+    }
+
+    /**
+     * Runs `f` with the builtin's arguments, and anything passed to `BuiltinContext::pin` while it runs, kept alive
+     * through garbage collections.
+     */
+    pub fn with_pinned<F, T>(&mut self, f: F) -> T
+        where F: FnOnce(&mut BuiltinContext<'a>) -> T
+    {
+        let mark = self.interp.pin_mark();
+        for arg in &self.args {
+            self.interp.pin(arg);
         }
+        let result = f(self);
+        self.interp.unpin_to(mark);
+        result
+    }
+
+    /**
+     * Keeps a value alive until `with_pinned` returns.
+     */
+    pub fn pin(&mut self, value: &Atom) {
+        self.interp.pin(value);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Atom> {
+        self.interp.get_global(name)
+    }
+
+    /**
+     * Sets a global variable, defining it if it isn't already.
+     */
+    pub fn set_global(&mut self, name: &str, value: Atom) {
+        self.interp.set_global(name, value);
     }
 }
 
+/**
+ * Collects garbage. This one can't be an ordinary builtin, since it needs to see the interpreter's roots, so the
+ * interpreter handles calls to it itself.
+ */
+pub const GC_BUILTIN: &'static str = "&gc";
+
 /**
  * Creates the list of builtin functions.
  */
 pub fn make_builtin_functions() -> HashMap<&'static str, BuiltinFunction> {
+    let builtins = vec![
+        BuiltinFunction::new("&print", vec!["fmt"], print_builtin),
+        BuiltinFunction::new("&eprint", vec!["fmt"], eprint_builtin),
+        BuiltinFunction::new("==", vec!["lhs", "rhs"], equals_builtin),
+        BuiltinFunction::new("-", vec!["lhs", "rhs"], minus_builtin),
+        BuiltinFunction::new("*", vec!["lhs", "rhs"], times_builtin),
+        BuiltinFunction::new("&cons", vec!["car", "cdr"], cons_builtin),
+        BuiltinFunction::new("&car", vec!["pair"], car_builtin),
+        BuiltinFunction::new("&cdr", vec!["pair"], cdr_builtin),
+        BuiltinFunction::new("&set-car!", vec!["pair", "car"], set_car_builtin),
+        BuiltinFunction::new("&set-cdr!", vec!["pair", "cdr"], set_cdr_builtin),
+        BuiltinFunction::new("&pair?", vec!["value"], is_pair_builtin),
+        BuiltinFunction::new("&dict", vec![], dict_builtin),
+        BuiltinFunction::new("&get", vec!["dict", "key"], get_builtin),
+        BuiltinFunction::new("&put", vec!["dict", "key", "value"], put_builtin),
+        BuiltinFunction::new("&remove", vec!["dict", "key"], remove_builtin),
+        BuiltinFunction::new("&keys", vec!["dict"], keys_builtin),
+        BuiltinFunction::new("&values", vec!["dict"], values_builtin),
+        BuiltinFunction::new("&has-key?", vec!["dict", "key"], has_key_builtin),
+        BuiltinFunction::new("&map-size", vec!["dict"], map_size_builtin),
+        BuiltinFunction::new("&dict?", vec!["value"], is_dict_builtin),
+        BuiltinFunction::new("&vec", vec![], vec_builtin),
+        BuiltinFunction::new("&vec-ref", vec!["vec", "index"], vec_ref_builtin),
+        BuiltinFunction::new("&vec-set!", vec!["vec", "index", "value"], vec_set_builtin),
+        BuiltinFunction::new("&vec-push!", vec!["vec", "value"], vec_push_builtin),
+        BuiltinFunction::new("&vec-pop!", vec!["vec"], vec_pop_builtin),
+        BuiltinFunction::new("&vec-len", vec!["vec"], vec_len_builtin),
+        BuiltinFunction::new("&vec-slice", vec!["vec", "start", "end"], vec_slice_builtin),
+        BuiltinFunction::new("&vec?", vec!["value"], is_vec_builtin),
+        BuiltinFunction::new("&box", vec!["value"], box_builtin),
+        BuiltinFunction::new("&unbox", vec!["box"], unbox_builtin),
+        BuiltinFunction::new("&set-box!", vec!["box", "value"], set_box_builtin),
+        BuiltinFunction::new("&box?", vec!["value"], is_box_builtin),
+        BuiltinFunction::new(GC_BUILTIN, vec![], gc_builtin),
+        BuiltinFunction::new("&assert", vec!["condition"], assert_builtin),
+        BuiltinFunction::new("&assert-equal", vec!["expected", "actual"], assert_equal_builtin),
+    ];
     let mut the_map = HashMap::new();
-
-    the_map.insert("&print", BuiltinFunction { name: "&print", args: vec!["fmt"], fun: print_builtin });
-    the_map.insert("==", BuiltinFunction { name: "==", args: vec!["lhs", "rhs"], fun: equals_builtin });
-    the_map.insert("-", BuiltinFunction { name: "-", args: vec!["lhs", "rhs"], fun: minus_builtin });
-    the_map.insert("*", BuiltinFunction { name: "*", args: vec!["lhs", "rhs"], fun: times_builtin });
+    for builtin in builtins.into_iter().chain(sequence_builtins()).chain(eval_builtins()) {
+        the_map.insert(builtin.name, builtin);
+    }
     the_map
 }
 
-fn print_builtin(context: BuiltinContext) -> Result<Atom, String> {
-    let args = context.args;
-    if args.len() == 0 {
-        println!("");
-        Ok(Atom::IntLit(0))
-    }
-    else {
-        let mut count = 0;
-        print!("{}", args[0]);
-        for a in args.iter().skip(1) {
-            print!(" {}", a);
-            count += 1;
-        }
-        println!("");
-        Ok(Atom::IntLit(count))
-    }
+fn print_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    print_to(&mut context, false)
 }
 
-fn unwrap_arg<'a>(context: &'a BuiltinContext, n: usize) -> Result<&'a Atom, String> {
-    let ref args = context.args;
-    let ref var = args[n];
-    if let &Atom::Identifier(ref s) = var {
-        if let Some(atom) = context.get_var(s) {
-            Ok(atom)
-        }
-        else {
-            Err(format!("Undefined variable: {}", s))
-        }
-    }
-    else {
-        Ok(var)
+fn eprint_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    print_to(&mut context, true)
+}
+
+/**
+ * Writes its arguments separated by spaces, followed by a newline, to the output or to the error output. Shared by
+ * &print and &eprint.
+ */
+fn print_to(context: &mut BuiltinContext, error: bool) -> Result<Atom, RuntimeError> {
+    let line = {
+        let heap = context.heap();
+        context.args.iter().map(|a| heap.format(a)).collect::<Vec<_>>().join(" ")
+    };
+    let count = if context.args.len() == 0 { 0 } else { context.args.len() as i64 - 1 };
+    let out = if error { context.error() } else { context.output() };
+    match writeln!(out, "{}", line) {
+        Ok(_) => Ok(Atom::IntLit(count)),
+        Err(err) => Err(format!("Could not write output: {}", err).into()),
     }
 }
 
-fn equals_builtin(context: BuiltinContext) -> Result<Atom, String> {
+fn equals_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
     let ref args = context.args;
     if args.len() != 2 {
-        Err(format!("Invalid number of arguments for `=='; got {} but expected exactly 2", args.len()))
+        Err(format!("Invalid number of arguments for `=='; got {} but expected exactly 2", args.len()).into())
     }
     else {
-        let lhs_result = unwrap_arg(&context, 1);
-        let rhs_result = unwrap_arg(&context, 0);
-        
-        if lhs_result.is_err() {
-            Err(lhs_result.unwrap_err())
-        }
-        else if rhs_result.is_err() {
-            Err(rhs_result.unwrap_err())
-        }
-        else {
-            let lhs = lhs_result.unwrap();
-            let rhs = rhs_result.unwrap();
-            Ok(lhs.equals(rhs))
-        }
+        let lhs = &args[0];
+        let rhs = &args[1];
+        Ok(Atom::BoolLit(try!(context.heap().equals(lhs, rhs))))
     }
 }
 
-fn minus_builtin(context: BuiltinContext) -> Result<Atom, String> {
+fn minus_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
     // This expects either one or two arguments
     let ref args = context.args;
     if args.len() == 1 {
-        match args[0] {
-            Atom::Identifier(ref s) => {
-                let result = context.get_var(s);
-                if result.is_some() {
-                    result.unwrap().neg()
-                }
-                else {
-                    Err(format!("Undefined variable: {}", s))
-                }
-            }
-            _ => Ok(Atom::IntLit(0i64))
-        }
+        Ok(try!(args[0].neg()))
     }
     else if args.len() == 2 {
-        // do this in reverse because stacks
-        let lhs_result = unwrap_arg(&context, 1);
-        let rhs_result = unwrap_arg(&context, 0);
-        // TODO : look up error chain
-        if lhs_result.is_err() {
-            Err(lhs_result.unwrap_err())
-        }
-        else if rhs_result.is_err() {
-            Err(rhs_result.unwrap_err())
-        }
-        else {
-            let lhs = lhs_result.unwrap();
-            let rhs = rhs_result.unwrap();
-            lhs.minus(rhs)
-        }
+        let lhs = &args[0];
+        let rhs = &args[1];
+        Ok(try!(lhs.minus(rhs)))
     }
     else {
-        Err(format!("Invalid number of arguments for `-'; got {} but expected either 1 or 2", args.len()))
+        Err(format!("Invalid number of arguments for `-'; got {} but expected either 1 or 2", args.len()).into())
     }
 }
 
-fn times_builtin(context: BuiltinContext) -> Result<Atom, String> {
-    // This expects either one or two arguments
+fn times_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
     let ref args = context.args;
     if args.len() == 2 {
-        // do this in reverse because stacks
-        let lhs_result = unwrap_arg(&context, 1);
-        let rhs_result = unwrap_arg(&context, 0);
-        
-        if lhs_result.is_err() {
-            Err(lhs_result.unwrap_err())
-        }
-        else if rhs_result.is_err() {
-            Err(rhs_result.unwrap_err())
-        }
-        else {
-            let lhs = lhs_result.unwrap();
-            let rhs = rhs_result.unwrap();
-            lhs.times(rhs)
+        let lhs = &args[0];
+        let rhs = &args[1];
+        Ok(try!(lhs.times(rhs)))
+    }
+    else {
+        Err(format!("Invalid number of arguments for `*'; got {} but expected exactly 2", args.len()).into())
+    }
+}
+
+fn cons_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let car = context.args[0].clone();
+    let cdr = context.args[1].clone();
+    Ok(context.alloc(Object::Pair(car, cdr)))
+}
+
+/**
+ * Gets the heap object that an argument refers to, as long as it's the kind of object the builtin expected.
+ */
+fn object_arg(context: &BuiltinContext, n: usize, kind: &str) -> Result<HeapRef, String> {
+    if let Atom::Ref(r) = context.args[n] {
+        if try!(context.heap().get(r)).kind() == kind {
+            return Ok(r);
         }
     }
+    Err(format!("Expected {}, but got `{}'", kind, context.heap().format(&context.args[n])))
+}
+
+/**
+ * Gets the pair that an argument refers to.
+ */
+fn pair_arg<'a>(context: &'a BuiltinContext, n: usize) -> Result<(&'a Atom, &'a Atom), String> {
+    let r = try!(object_arg(context, n, "a pair"));
+    match try!(context.heap().get(r)) {
+        &Object::Pair(ref car, ref cdr) => Ok((car, cdr)),
+        _ => unreachable!(),
+    }
+}
+
+fn car_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    Ok(try!(pair_arg(&context, 0)).0.clone())
+}
+
+fn cdr_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    Ok(try!(pair_arg(&context, 0)).1.clone())
+}
+
+/**
+ * Stores a value in part of the object that the first argument refers to, returning the value.
+ */
+fn store(context: &mut BuiltinContext, kind: &str, place: Place, value: Atom) -> Result<Atom, RuntimeError> {
+    let r = try!(object_arg(context, 0, kind));
+    try!(context.heap_mut().store(r, place, value.clone()));
+    Ok(value)
+}
+
+fn set_car_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let value = context.args[1].clone();
+    store(&mut context, "a pair", Place::Car, value)
+}
+
+fn set_cdr_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let value = context.args[1].clone();
+    store(&mut context, "a pair", Place::Cdr, value)
+}
+
+/**
+ * Checks whether an argument is a heap object of a particular kind.
+ */
+fn is_object<F>(context: &BuiltinContext, n: usize, kind: F) -> Result<Atom, RuntimeError>
+    where F: Fn(&Object) -> bool
+{
+    match context.args[n] {
+        Atom::Ref(r) => Ok(Atom::BoolLit(context.heap().get(r).map(kind).unwrap_or(false))),
+        _ => Ok(Atom::BoolLit(false)),
+    }
+}
+
+fn is_pair_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    is_object(&context, 0, |object| match object {
+        &Object::Pair(_, _) => true,
+        _ => false,
+    })
+}
+
+fn dict_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    Ok(context.alloc(Object::Dict(BTreeMap::new())))
+}
+
+/**
+ * Gets the dictionary that an argument refers to.
+ */
+fn dict_arg<'a>(context: &'a BuiltinContext, n: usize) -> Result<&'a BTreeMap<DictKey, Atom>, String> {
+    let r = try!(object_arg(context, n, "a dictionary"));
+    match try!(context.heap().get(r)) {
+        &Object::Dict(ref entries) => Ok(entries),
+        _ => unreachable!(),
+    }
+}
+
+fn key_arg(context: &BuiltinContext, n: usize) -> Result<DictKey, String> {
+    DictKey::from_atom(&context.args[n], context.heap())
+}
+
+/**
+ * Builds a list out of values, ending it with the empty list, `@nah`.
+ */
+pub fn make_list(heap: &mut Heap, values: Vec<Atom>) -> Atom {
+    values.into_iter()
+        .rev()
+        .fold(Atom::BoolLit(false), |rest, value| Atom::Ref(heap.alloc(Object::Pair(value, rest))))
+}
+
+fn get_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let key = try!(key_arg(&context, 1));
+    match try!(dict_arg(&context, 0)).get(&key) {
+        Some(value) => Ok(value.clone()),
+        None => Err(format!("Key `{}' is not in the dictionary", key.to_atom()).into()),
+    }
+}
+
+/**
+ * Adds an entry to a dictionary, or replaces it, returning the dictionary.
+ */
+fn put_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let key = try!(key_arg(&context, 1));
+    let value = context.args[2].clone();
+    try!(store(&mut context, "a dictionary", Place::Entry(key), value));
+    Ok(context.args[0].clone())
+}
+
+/**
+ * Removes an entry from a dictionary if it's there, returning the dictionary.
+ */
+fn remove_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let key = try!(key_arg(&context, 1));
+    let r = try!(object_arg(&context, 0, "a dictionary"));
+    try!(context.heap_mut().remove(r, Place::Entry(key)));
+    Ok(context.args[0].clone())
+}
+
+fn keys_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let keys = try!(dict_arg(&context, 0)).keys().map(DictKey::to_atom).collect();
+    Ok(make_list(context.heap_mut(), keys))
+}
+
+fn values_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let values = try!(dict_arg(&context, 0)).values().cloned().collect();
+    Ok(make_list(context.heap_mut(), values))
+}
+
+fn has_key_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let key = try!(key_arg(&context, 1));
+    Ok(Atom::BoolLit(try!(dict_arg(&context, 0)).contains_key(&key)))
+}
+
+fn map_size_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    Ok(Atom::IntLit(try!(dict_arg(&context, 0)).len() as i64))
+}
+
+fn is_dict_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    is_object(&context, 0, |object| match object {
+        &Object::Dict(_) => true,
+        _ => false,
+    })
+}
+
+fn gc_builtin(_: BuiltinContext) -> Result<Atom, RuntimeError> {
+    Err(format!("{} has to be called by the interpreter", GC_BUILTIN).into())
+}
+
+fn assert_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    if context.args[0].is_true() {
+        Ok(Atom::BoolLit(true))
+    }
     else {
-        Err(format!("Invalid number of arguments for `-'; got {} but expected either 1 or 2", args.len()))
+        Err("Assertion failed".to_string().into())
+    }
+}
+
+/**
+ * Fails unless both arguments are equal, the same way `==` compares them.
+ */
+fn assert_equal_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let expected = &context.args[0];
+    let actual = &context.args[1];
+    let heap = context.heap();
+    if try!(heap.equals(expected, actual)) {
+        Ok(Atom::BoolLit(true))
+    }
+    else {
+        Err(format!("Expected {}, but got {}", heap.format(expected), heap.format(actual)).into())
+    }
+}
+
+fn vec_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    Ok(context.alloc(Object::Vector(vec![])))
+}
+
+/**
+ * Gets the vector that an argument refers to.
+ */
+fn vec_arg<'a>(context: &'a BuiltinContext, n: usize) -> Result<&'a Vec<Atom>, String> {
+    let r = try!(object_arg(context, n, "a vector"));
+    match try!(context.heap().get(r)) {
+        &Object::Vector(ref items) => Ok(items),
+        _ => unreachable!(),
+    }
+}
+
+/**
+ * Gets an argument that has to be an index into a vector of length `len`. If `past_end` is set, the index can point
+ * just past the last item, as the end of a slice can.
+ */
+fn index_arg(context: &BuiltinContext, n: usize, len: usize, past_end: bool) -> Result<usize, String> {
+    let limit = if past_end { len + 1 } else { len };
+    match &context.args[n] {
+        &Atom::IntLit(i) if i >= 0 && (i as usize) < limit => Ok(i as usize),
+        &Atom::IntLit(i) => Err(format!("Index {} is out of range for a vector of length {}", i, len)),
+        other => Err(format!("Expected an index, but got `{}'", context.heap().format(other))),
     }
-}
\ No newline at end of file
+}
+
+fn vec_ref_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let len = try!(vec_arg(&context, 0)).len();
+    let index = try!(index_arg(&context, 1, len, false));
+    Ok(try!(vec_arg(&context, 0))[index].clone())
+}
+
+fn vec_set_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let value = context.args[2].clone();
+    let len = try!(vec_arg(&context, 0)).len();
+    let index = try!(index_arg(&context, 1, len, false));
+    store(&mut context, "a vector", Place::Item(index), value)
+}
+
+/**
+ * Adds a value to the end of a vector, returning the vector.
+ */
+fn vec_push_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let value = context.args[1].clone();
+    try!(store(&mut context, "a vector", Place::End, value));
+    Ok(context.args[0].clone())
+}
+
+fn vec_pop_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let r = try!(object_arg(&context, 0, "a vector"));
+    match try!(context.heap_mut().remove(r, Place::End)) {
+        Some(value) => Ok(value),
+        None => Err("Can't pop from an empty vector".to_string().into()),
+    }
+}
+
+fn vec_len_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    Ok(Atom::IntLit(try!(vec_arg(&context, 0)).len() as i64))
+}
+
+/**
+ * Copies the items from `start` up to but not including `end` into a new vector.
+ */
+fn vec_slice_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let len = try!(vec_arg(&context, 0)).len();
+    let start = try!(index_arg(&context, 1, len, true));
+    let end = try!(index_arg(&context, 2, len, true));
+    if start > end {
+        return Err(format!("Slice start {} is after its end {}", start, end).into());
+    }
+    let items = try!(vec_arg(&context, 0))[start .. end].to_vec();
+    Ok(context.alloc(Object::Vector(items)))
+}
+
+fn is_vec_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    is_object(&context, 0, |object| match object {
+        &Object::Vector(_) => true,
+        _ => false,
+    })
+}
+
+fn box_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let value = context.args[0].clone();
+    Ok(context.alloc(Object::Box(value)))
+}
+
+/**
+ * Gets the value in the box that an argument refers to.
+ */
+fn box_arg<'a>(context: &'a BuiltinContext, n: usize) -> Result<&'a Atom, String> {
+    let r = try!(object_arg(context, n, "a box"));
+    match try!(context.heap().get(r)) {
+        &Object::Box(ref value) => Ok(value),
+        _ => unreachable!(),
+    }
+}
+
+fn unbox_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    Ok(try!(box_arg(&context, 0)).clone())
+}
+
+fn set_box_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let value = context.args[1].clone();
+    store(&mut context, "a box", Place::Contents, value)
+}
+
+fn is_box_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    is_object(&context, 0, |object| match object {
+        &Object::Box(_) => true,
+        _ => false,
+    })
+}
+
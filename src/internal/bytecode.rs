@@ -1,13 +1,47 @@
 use ast::*;
+use internal::CodeObject;
+use std::rc::Rc;
 
 #[derive(Clone, Debug)]
 pub enum Bytecode {
     Nop,
     Call(String),           // Calls a function, using the current working stack for the args
     Push(Atom),             // Pushes a value to the working stack
-    Pop(Option<String>),    // Pops a value off of the stack, optionally into an identifier (or register?)
-    FunDef(Function),       // Defines a function that will be compiled upon its first use
-    Label(u64),             // Defines a location that can be jumped to
-    Jump(u64),              // Jumps to a label that's been defined
+    Pop,                    // Pops a value off of the stack and throws it away
+    LoadLocal(usize),       // Pushes the value of a local variable, by slot number
+    StoreLocal(usize),      // Pops a value off of the stack into a local variable, by slot number
+    LoadGlobal(usize),      // Pushes the value of a global variable, by symbol ID
+    StoreGlobal(usize),     // Pops a value off of the stack into a global variable, by symbol ID
+    SetLocal(usize),        // Like StoreLocal, but fails if the local hasn't been defined yet
+    SetGlobal(usize),       // Like StoreGlobal, but fails if the global hasn't been defined yet
+    FunDef(Rc<CodeObject>), // Defines a function from its compiled code
+    Extern(ExternDecl),     // Loads a function from a shared library
+    Label(u64),             // Defines a location that can be jumped to; removed by the linker
+    Jump(u64),              // Jumps to a label that's been defined, or to an instruction index once linked
     JumpTrue(u64),          // Pops an expression off of the stack and jumps if that value can be considered "true"
 }
+
+impl Bytecode {
+    /**
+     * Gets the name of this instruction's opcode, without its operands.
+     */
+    pub fn opcode(&self) -> &'static str {
+        match self {
+            &Bytecode::Nop => "Nop",
+            &Bytecode::Call(_) => "Call",
+            &Bytecode::Push(_) => "Push",
+            &Bytecode::Pop => "Pop",
+            &Bytecode::LoadLocal(_) => "LoadLocal",
+            &Bytecode::StoreLocal(_) => "StoreLocal",
+            &Bytecode::LoadGlobal(_) => "LoadGlobal",
+            &Bytecode::StoreGlobal(_) => "StoreGlobal",
+            &Bytecode::SetLocal(_) => "SetLocal",
+            &Bytecode::SetGlobal(_) => "SetGlobal",
+            &Bytecode::FunDef(_) => "FunDef",
+            &Bytecode::Extern(_) => "Extern",
+            &Bytecode::Label(_) => "Label",
+            &Bytecode::Jump(_) => "Jump",
+            &Bytecode::JumpTrue(_) => "JumpTrue",
+        }
+    }
+}
@@ -0,0 +1,46 @@
+/**
+ * Something that a builtin can do outside of the interpreter, which an embedder might not trust a script with.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Capability {
+    /** Reading source files with `&load` */
+    Files,
+    /** Loading and calling C functions with `&extern`, which can do anything at all */
+    Foreign,
+}
+
+/**
+ * The capabilities that scripts in an interpreter are allowed to use. Builtins that need a missing capability fail
+ * when they're called.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Capabilities {
+    pub files: bool,
+    pub foreign: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Capabilities {
+        Capabilities { files: true, foreign: true }
+    }
+
+    /**
+     * Nothing but pure computation and printing, for running untrusted scripts.
+     */
+    pub fn none() -> Capabilities {
+        Capabilities { files: false, foreign: false }
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Files => self.files,
+            Capability::Foreign => self.foreign,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Capabilities {
+        Capabilities::all()
+    }
+}
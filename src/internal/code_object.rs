@@ -0,0 +1,29 @@
+use internal::Bytecode;
+
+/**
+ * A compiled and linked function. Code objects are immutable once they're created, and are shared by reference
+ * between the bytecode that defines them and the interpreter that calls them.
+ */
+#[derive(Debug)]
+pub struct CodeObject {
+    pub name: String,
+    pub args: Vec<String>,
+    pub locals: Vec<String>, /* Names of the local variable slots; the arguments come first */
+    pub code: Vec<Bytecode>,
+    pub lines: Vec<usize>, /* The source line each instruction came from, or 0 if it's unknown */
+}
+
+impl CodeObject {
+    pub fn new(name: String, args: Vec<String>, locals: Vec<String>, code: Vec<Bytecode>, lines: Vec<usize>)
+        -> CodeObject
+    {
+        assert_eq!(code.len(), lines.len());
+        CodeObject {
+            name: name,
+            args: args,
+            locals: locals,
+            code: code,
+            lines: lines,
+        }
+    }
+}
@@ -0,0 +1,473 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+use ast::{Atom, ExternDecl, Span};
+use internal::{Bytecode, CodeObject};
+use interpreter::SymbolTable;
+
+/**
+ * Every compiled rasp file starts with these bytes.
+ */
+pub const MAGIC: &'static [u8] = b"RASPC\0";
+
+/**
+ * The version of the compiled file format. This must be bumped whenever the format or the instruction set changes,
+ * since files written by one version can't be read by another.
+ */
+pub const FORMAT_VERSION: u16 = 3;
+
+/**
+ * How deeply function definitions may be nested in a compiled file. The compiler never nests them more than one
+ * deep, and this keeps a crafted file from overflowing the stack while it's read.
+ */
+pub const MAX_FUNCTION_DEPTH: usize = 32;
+
+/*
+ * File layout. All integers are little-endian, and strings are a u32 byte length followed by UTF-8.
+ *
+ *   magic        6 bytes, "RASPC\0"
+ *   version      u16
+ *   length       u32, the length of the payload
+ *   checksum     u32, FNV-1a hash of the payload
+ *   payload:
+ *     symbols    u32 count, then that many strings; the global variable names used by the code
+ *     code       the top-level code object
+ *
+ * A code object is:
+ *   name, args, locals       a string, then two string lists (u32 count, then strings)
+ *   constants                u32 count, then tagged atoms
+ *   functions                u32 count, then nested code objects
+ *   instructions             u32 count, then an opcode byte and its operands for each; `&extern` declarations
+ *                            include the start and end of their source span
+ *   lines                    one u32 per instruction; the debug line table
+ */
+
+/**
+ * What can go wrong when loading a compiled file.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum CodeFileError {
+    NotCompiled,
+    VersionMismatch { found: u16, expected: u16 },
+    Truncated,
+    ChecksumMismatch,
+    Corrupt(String),
+}
+
+impl fmt::Display for CodeFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &CodeFileError::NotCompiled => write!(f, "not a compiled rasp file"),
+            &CodeFileError::VersionMismatch { found, expected } =>
+                write!(f, "compiled with format version {}, but this version of rasp reads version {}; recompile it",
+                    found, expected),
+            &CodeFileError::Truncated => write!(f, "compiled file is truncated"),
+            &CodeFileError::ChecksumMismatch => write!(f, "compiled file is corrupt (checksum mismatch)"),
+            &CodeFileError::Corrupt(ref msg) => write!(f, "compiled file is corrupt: {}", msg),
+        }
+    }
+}
+
+mod opcode {
+    pub const NOP: u8 = 0;
+    pub const CALL: u8 = 1;
+    pub const PUSH: u8 = 2;
+    pub const POP: u8 = 3;
+    pub const LOAD_LOCAL: u8 = 4;
+    pub const STORE_LOCAL: u8 = 5;
+    pub const LOAD_GLOBAL: u8 = 6;
+    pub const STORE_GLOBAL: u8 = 7;
+    pub const FUN_DEF: u8 = 8;
+    pub const EXTERN: u8 = 9;
+    pub const LABEL: u8 = 10;
+    pub const JUMP: u8 = 11;
+    pub const JUMP_TRUE: u8 = 12;
+    pub const SET_LOCAL: u8 = 13;
+    pub const SET_GLOBAL: u8 = 14;
+}
+
+mod tag {
+    pub const INT: u8 = 0;
+    pub const DUB: u8 = 1;
+    pub const BOOL: u8 = 2;
+    pub const STR: u8 = 3;
+    pub const IDENTIFIER: u8 = 4;
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut hash = 0x811c9dc5u32;
+    for b in bytes {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+/**
+ * Builds up the bytes of a compiled file.
+ */
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, v: u8) {
+        self.bytes.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        for i in 0 .. 2 {
+            self.bytes.push((v >> (i * 8)) as u8);
+        }
+    }
+
+    fn u32(&mut self, v: u32) {
+        for i in 0 .. 4 {
+            self.bytes.push((v >> (i * 8)) as u8);
+        }
+    }
+
+    fn u64(&mut self, v: u64) {
+        for i in 0 .. 8 {
+            self.bytes.push((v >> (i * 8)) as u8);
+        }
+    }
+
+    fn usize(&mut self, v: usize) {
+        assert!(v <= u32::max_value() as usize, "value too large for a compiled file");
+        self.u32(v as u32);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn strs(&mut self, strs: &Vec<String>) {
+        self.usize(strs.len());
+        for s in strs {
+            self.str(s);
+        }
+    }
+
+    fn atom(&mut self, atom: &Atom) {
+        match atom {
+            &Atom::IntLit(i) => { self.u8(tag::INT); self.u64(i as u64); },
+            &Atom::DubLit(d) => { self.u8(tag::DUB); self.u64(d.to_bits()); },
+            &Atom::BoolLit(b) => { self.u8(tag::BOOL); self.u8(b as u8); },
+            &Atom::StrLit(ref s) => { self.u8(tag::STR); self.str(s); },
+            &Atom::Identifier(ref s) => { self.u8(tag::IDENTIFIER); self.str(s); },
+            &Atom::Ref(_) => unreachable!("heap references are never constants"),
+        }
+    }
+
+    fn code_object(&mut self, code: &CodeObject) {
+        self.str(&code.name);
+        self.strs(&code.args);
+        self.strs(&code.locals);
+
+        // Constants and functions get pulled out into tables, and instructions refer to them by index
+        let mut constants = vec![];
+        let mut functions = vec![];
+        for b in &code.code {
+            match b {
+                &Bytecode::Push(ref atom) => constants.push(atom),
+                &Bytecode::FunDef(ref fun) => functions.push(fun),
+                _ => {},
+            }
+        }
+        self.usize(constants.len());
+        for c in &constants {
+            self.atom(c);
+        }
+        self.usize(functions.len());
+        for f in &functions {
+            self.code_object(f);
+        }
+
+        let mut next_constant = 0;
+        let mut next_function = 0;
+        self.usize(code.code.len());
+        for b in &code.code {
+            match b {
+                &Bytecode::Nop => self.u8(opcode::NOP),
+                &Bytecode::Call(ref name) => { self.u8(opcode::CALL); self.str(name); },
+                &Bytecode::Push(_) => {
+                    self.u8(opcode::PUSH);
+                    self.usize(next_constant);
+                    next_constant += 1;
+                },
+                &Bytecode::Pop => self.u8(opcode::POP),
+                &Bytecode::LoadLocal(slot) => { self.u8(opcode::LOAD_LOCAL); self.usize(slot); },
+                &Bytecode::StoreLocal(slot) => { self.u8(opcode::STORE_LOCAL); self.usize(slot); },
+                &Bytecode::LoadGlobal(id) => { self.u8(opcode::LOAD_GLOBAL); self.usize(id); },
+                &Bytecode::StoreGlobal(id) => { self.u8(opcode::STORE_GLOBAL); self.usize(id); },
+                &Bytecode::SetLocal(slot) => { self.u8(opcode::SET_LOCAL); self.usize(slot); },
+                &Bytecode::SetGlobal(id) => { self.u8(opcode::SET_GLOBAL); self.usize(id); },
+                &Bytecode::FunDef(_) => {
+                    self.u8(opcode::FUN_DEF);
+                    self.usize(next_function);
+                    next_function += 1;
+                },
+                &Bytecode::Extern(ref decl) => {
+                    self.u8(opcode::EXTERN);
+                    self.str(&decl.library);
+                    self.str(&decl.name);
+                    self.strs(&decl.args);
+                    self.str(&decl.ret);
+                    self.usize(decl.span.start);
+                    self.usize(decl.span.end);
+                },
+                &Bytecode::Label(lnum) => { self.u8(opcode::LABEL); self.u64(lnum); },
+                &Bytecode::Jump(target) => { self.u8(opcode::JUMP); self.u64(target); },
+                &Bytecode::JumpTrue(target) => { self.u8(opcode::JUMP_TRUE); self.u64(target); },
+            }
+        }
+        for line in &code.lines {
+            self.usize(*line);
+        }
+    }
+}
+
+/**
+ * Writes a compiled program. `symbols` is the table that the program's global variable IDs came from.
+ */
+pub fn write_code_file(code: &CodeObject, symbols: &SymbolTable, out: &mut Write) -> io::Result<()> {
+    let mut payload = Encoder { bytes: vec![] };
+    payload.usize(symbols.len());
+    for id in 0 .. symbols.len() {
+        payload.str(symbols.name(id));
+    }
+    payload.code_object(code);
+
+    let mut header = Encoder { bytes: MAGIC.to_vec() };
+    header.u16(FORMAT_VERSION);
+    header.usize(payload.bytes.len());
+    header.u32(checksum(&payload.bytes));
+    try!(out.write_all(&header.bytes));
+    out.write_all(&payload.bytes)
+}
+
+/**
+ * Reads the bytes of a compiled file back into code objects.
+ */
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /* Maps the global IDs in the file to IDs in the symbol table that we're loading into */
+    globals: Vec<usize>,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], CodeFileError> {
+        if self.bytes.len() - self.pos < count {
+            return Err(CodeFileError::Truncated);
+        }
+        let slice = &self.bytes[self.pos .. self.pos + count];
+        self.pos += count;
+        Ok(slice)
+    }
+
+    fn uint(&mut self, size: usize) -> Result<u64, CodeFileError> {
+        let bytes = try!(self.take(size));
+        let mut v = 0u64;
+        for (i, b) in bytes.iter().enumerate() {
+            v |= (*b as u64) << (i * 8);
+        }
+        Ok(v)
+    }
+
+    fn u8(&mut self) -> Result<u8, CodeFileError> {
+        self.uint(1).map(|v| v as u8)
+    }
+
+    fn u32(&mut self) -> Result<u32, CodeFileError> {
+        self.uint(4).map(|v| v as u32)
+    }
+
+    fn u64(&mut self) -> Result<u64, CodeFileError> {
+        self.uint(8)
+    }
+
+    fn usize(&mut self) -> Result<usize, CodeFileError> {
+        self.u32().map(|v| v as usize)
+    }
+
+    /**
+     * Reads a count, making sure that there are at least `min_size` bytes left for each item so that a corrupt count
+     * can't make us allocate something huge.
+     */
+    fn count(&mut self, min_size: usize) -> Result<usize, CodeFileError> {
+        let count = try!(self.usize());
+        if count.saturating_mul(min_size) > self.bytes.len() - self.pos {
+            Err(CodeFileError::Truncated)
+        }
+        else {
+            Ok(count)
+        }
+    }
+
+    fn str(&mut self) -> Result<String, CodeFileError> {
+        let len = try!(self.usize());
+        let bytes = try!(self.take(len));
+        match String::from_utf8(bytes.to_vec()) {
+            Ok(s) => Ok(s),
+            Err(_) => Err(CodeFileError::Corrupt("invalid UTF-8 in a string".to_string())),
+        }
+    }
+
+    fn strs(&mut self) -> Result<Vec<String>, CodeFileError> {
+        let count = try!(self.count(4));
+        let mut strs = Vec::with_capacity(count);
+        for _ in 0 .. count {
+            strs.push(try!(self.str()));
+        }
+        Ok(strs)
+    }
+
+    fn atom(&mut self) -> Result<Atom, CodeFileError> {
+        match try!(self.u8()) {
+            tag::INT => self.u64().map(|v| Atom::IntLit(v as i64)),
+            tag::DUB => self.u64().map(|v| Atom::DubLit(f64::from_bits(v))),
+            tag::BOOL => self.u8().map(|v| Atom::BoolLit(v != 0)),
+            tag::STR => self.str().map(Atom::StrLit),
+            tag::IDENTIFIER => self.str().map(Atom::Identifier),
+            t => Err(CodeFileError::Corrupt(format!("unknown constant type {}", t))),
+        }
+    }
+
+    fn global(&mut self) -> Result<usize, CodeFileError> {
+        let id = try!(self.usize());
+        match self.globals.get(id) {
+            Some(mapped) => Ok(*mapped),
+            None => Err(CodeFileError::Corrupt(format!("global variable {} is out of range", id))),
+        }
+    }
+
+    /**
+     * Reads a code object, which is `depth` functions deep.
+     */
+    fn code_object(&mut self, depth: usize) -> Result<CodeObject, CodeFileError> {
+        if depth > MAX_FUNCTION_DEPTH {
+            return Err(CodeFileError::Corrupt(format!("functions are nested more than {} deep", MAX_FUNCTION_DEPTH)));
+        }
+        let name = try!(self.str());
+        let args = try!(self.strs());
+        let locals = try!(self.strs());
+
+        let constant_count = try!(self.count(2));
+        let mut constants = Vec::with_capacity(constant_count);
+        for _ in 0 .. constant_count {
+            constants.push(try!(self.atom()));
+        }
+        let function_count = try!(self.count(4));
+        let mut functions = Vec::with_capacity(function_count);
+        for _ in 0 .. function_count {
+            functions.push(Rc::new(try!(self.code_object(depth + 1))));
+        }
+
+        let code_len = try!(self.count(1));
+        let mut code = Vec::with_capacity(code_len);
+        for _ in 0 .. code_len {
+            let b = match try!(self.u8()) {
+                opcode::NOP => Bytecode::Nop,
+                opcode::CALL => Bytecode::Call(try!(self.str())),
+                opcode::PUSH => match constants.get(try!(self.usize())) {
+                    Some(atom) => Bytecode::Push(atom.clone()),
+                    None => return Err(CodeFileError::Corrupt("constant index out of range".to_string())),
+                },
+                opcode::POP => Bytecode::Pop,
+                opcode::LOAD_LOCAL => Bytecode::LoadLocal(try!(self.usize())),
+                opcode::STORE_LOCAL => Bytecode::StoreLocal(try!(self.usize())),
+                opcode::LOAD_GLOBAL => Bytecode::LoadGlobal(try!(self.global())),
+                opcode::STORE_GLOBAL => Bytecode::StoreGlobal(try!(self.global())),
+                opcode::SET_LOCAL => Bytecode::SetLocal(try!(self.usize())),
+                opcode::SET_GLOBAL => Bytecode::SetGlobal(try!(self.global())),
+                opcode::FUN_DEF => match functions.get(try!(self.usize())) {
+                    Some(fun) => Bytecode::FunDef(fun.clone()),
+                    None => return Err(CodeFileError::Corrupt("function index out of range".to_string())),
+                },
+                opcode::EXTERN => {
+                    let library = try!(self.str());
+                    let name = try!(self.str());
+                    let args = try!(self.strs());
+                    let ret = try!(self.str());
+                    let start = try!(self.usize());
+                    let end = try!(self.usize());
+                    Bytecode::Extern(ExternDecl::new(library, name, args, ret, Span::new(start, end)))
+                },
+                opcode::LABEL => Bytecode::Label(try!(self.u64())),
+                opcode::JUMP => Bytecode::Jump(try!(self.u64())),
+                opcode::JUMP_TRUE => Bytecode::JumpTrue(try!(self.u64())),
+                op => return Err(CodeFileError::Corrupt(format!("unknown opcode {}", op))),
+            };
+            code.push(b);
+        }
+
+        let mut lines = Vec::with_capacity(code_len);
+        for _ in 0 .. code_len {
+            lines.push(try!(self.usize()));
+        }
+
+        // Make sure the code can't index outside of itself once it's running
+        for b in &code {
+            match b {
+                &Bytecode::LoadLocal(slot) | &Bytecode::StoreLocal(slot) | &Bytecode::SetLocal(slot)
+                    if slot >= locals.len() =>
+                    return Err(CodeFileError::Corrupt(format!("local slot {} is out of range in {}", slot, name))),
+                &Bytecode::Jump(target) | &Bytecode::JumpTrue(target) if target as usize > code.len() =>
+                    return Err(CodeFileError::Corrupt(format!("jump target {} is out of range in {}", target, name))),
+                _ => {},
+            }
+        }
+        if args.len() > locals.len() {
+            return Err(CodeFileError::Corrupt(format!("{} has more arguments than local slots", name)));
+        }
+        Ok(CodeObject::new(name, args, locals, code, lines))
+    }
+}
+
+/**
+ * Checks whether some bytes look like a compiled file.
+ */
+pub fn is_code_file(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/**
+ * Reads a compiled program, interning its global variable names into `symbols` so that it can be run by the
+ * interpreter that owns them.
+ */
+pub fn read_code_file(bytes: &[u8], symbols: &mut SymbolTable) -> Result<CodeObject, CodeFileError> {
+    if !is_code_file(bytes) {
+        return Err(CodeFileError::NotCompiled);
+    }
+    let mut header = Decoder { bytes: bytes, pos: MAGIC.len(), globals: vec![] };
+    let version = try!(header.uint(2)) as u16;
+    if version != FORMAT_VERSION {
+        return Err(CodeFileError::VersionMismatch { found: version, expected: FORMAT_VERSION });
+    }
+    let length = try!(header.usize());
+    let expected_checksum = try!(header.u32());
+    let payload = &bytes[header.pos ..];
+    if payload.len() < length {
+        return Err(CodeFileError::Truncated);
+    }
+    if payload.len() > length {
+        return Err(CodeFileError::Corrupt("trailing data after the end of the file".to_string()));
+    }
+    if checksum(payload) != expected_checksum {
+        return Err(CodeFileError::ChecksumMismatch);
+    }
+
+    let mut decoder = Decoder { bytes: payload, pos: 0, globals: vec![] };
+    let names = try!(decoder.strs());
+    decoder.globals = names.iter()
+        .map(|name| symbols.intern(name))
+        .collect();
+    let code = try!(decoder.code_object(0));
+    if decoder.pos != payload.len() {
+        return Err(CodeFileError::Corrupt("trailing data after the end of the code".to_string()));
+    }
+    Ok(code)
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+use ast::Atom;
+use internal::{Bytecode, CodeObject};
+use interpreter::SymbolTable;
+
+/**
+ * A printable listing of a code object, followed by listings of every function it defines.
+ */
+pub struct Disassembly<'a> {
+    code: &'a CodeObject,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl CodeObject {
+    /**
+     * Gets a listing of this code object that shows global variables by name.
+     */
+    pub fn disassemble<'a>(&'a self, symbols: &'a SymbolTable) -> Disassembly<'a> {
+        Disassembly { code: self, symbols: Some(symbols) }
+    }
+
+    /**
+     * Formats one of this code object's instructions the way a listing would.
+     */
+    pub fn format_instruction(&self, index: usize, symbols: &SymbolTable) -> String {
+        Disassembly { code: self, symbols: Some(symbols) }.instruction(&self.code[index])
+    }
+}
+
+impl<'a> Disassembly<'a> {
+    fn constant(atom: &Atom) -> String {
+        match atom {
+            &Atom::StrLit(ref s) => format!("{:?}", s),
+            _ => format!("{}", atom),
+        }
+    }
+
+    fn global_name(&self, id: usize) -> String {
+        match self.symbols {
+            Some(symbols) if id < symbols.len() => symbols.name(id).to_string(),
+            _ => format!("#{}", id),
+        }
+    }
+
+    fn local_name(&self, slot: usize) -> &str {
+        match self.code.locals.get(slot) {
+            Some(name) => name,
+            None => "?",
+        }
+    }
+
+    /**
+     * Formats a single instruction, with its operands resolved to something readable.
+     */
+    fn instruction(&self, code: &Bytecode) -> String {
+        match code {
+            &Bytecode::Nop => "Nop".to_string(),
+            &Bytecode::Call(ref name) => format!("Call {}", name),
+            &Bytecode::Push(ref atom) => format!("Push {}", Disassembly::constant(atom)),
+            &Bytecode::Pop => "Pop".to_string(),
+            &Bytecode::LoadLocal(slot) => format!("LoadLocal {:<10} ; {}", slot, self.local_name(slot)),
+            &Bytecode::StoreLocal(slot) => format!("StoreLocal {:<9} ; {}", slot, self.local_name(slot)),
+            &Bytecode::LoadGlobal(id) => format!("LoadGlobal {:<9} ; {}", id, self.global_name(id)),
+            &Bytecode::StoreGlobal(id) => format!("StoreGlobal {:<8} ; {}", id, self.global_name(id)),
+            &Bytecode::SetLocal(slot) => format!("SetLocal {:<11} ; {}", slot, self.local_name(slot)),
+            &Bytecode::SetGlobal(id) => format!("SetGlobal {:<10} ; {}", id, self.global_name(id)),
+            &Bytecode::FunDef(ref fun) => format!("FunDef {}", fun.name),
+            &Bytecode::Extern(ref decl) => format!("Extern {} from {:?}", decl.name, decl.library),
+            &Bytecode::Label(lnum) => format!("Label {}", lnum),
+            &Bytecode::Jump(target) => format!("Jump -> {}", target),
+            &Bytecode::JumpTrue(target) => format!("JumpTrue -> {}", target),
+        }
+    }
+}
+
+impl<'a> fmt::Display for Disassembly<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = self.code;
+        try!(writeln!(f, "== {} ({}) ==", code.name, code.args.join(" ")));
+        if code.locals.len() > 0 {
+            try!(writeln!(f, "locals: {}", code.locals.join(" ")));
+        }
+        try!(writeln!(f, "{:>6} {:>5}  {}", "offset", "line", "instruction"));
+        for (index, (b, line)) in code.code.iter().zip(&code.lines).enumerate() {
+            let line = if *line == 0 { "-".to_string() } else { line.to_string() };
+            try!(writeln!(f, "{:>6} {:>5}  {}", index, line, self.instruction(b)));
+        }
+        // Every function defined here gets its own listing
+        for b in &code.code {
+            if let &Bytecode::FunDef(ref fun) = b {
+                try!(writeln!(f, ""));
+                try!(write!(f, "{}", Disassembly { code: fun, symbols: self.symbols }));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CodeObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Disassembly { code: self, symbols: None })
+    }
+}
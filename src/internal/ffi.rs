@@ -0,0 +1,221 @@
+use ast::{Atom, ExternDecl};
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::os::raw::{c_char, c_int, c_long, c_void};
+use std::rc::Rc;
+
+const RTLD_NOW: c_int = 2;
+
+#[link(name = "dl")]
+extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlclose(handle: *mut c_void) -> c_int;
+    fn dlerror() -> *mut c_char;
+}
+
+/**
+ * Gets the last error reported by the dynamic loader.
+ */
+fn last_dl_error() -> String {
+    unsafe {
+        let err = dlerror();
+        if err.is_null() {
+            "unknown error".to_string()
+        }
+        else {
+            CStr::from_ptr(err).to_string_lossy().into_owned()
+        }
+    }
+}
+
+/**
+ * A shared library opened with dlopen. The library is closed when the last function referring to it goes away.
+ */
+pub struct Library {
+    path: String,
+    handle: *mut c_void,
+}
+
+impl Library {
+    pub fn open(path: &str) -> Result<Library, String> {
+        let c_path = match CString::new(path) {
+            Ok(p) => p,
+            Err(_) => return Err(format!("Invalid library path: {}", path)),
+        };
+        let handle = unsafe { dlopen(c_path.as_ptr(), RTLD_NOW) };
+        if handle.is_null() {
+            Err(format!("Could not open library {}: {}", path, last_dl_error()))
+        }
+        else {
+            Ok(Library { path: path.to_string(), handle: handle })
+        }
+    }
+
+    fn symbol(&self, name: &str) -> Result<*mut c_void, String> {
+        let c_name = match CString::new(name) {
+            Ok(n) => n,
+            Err(_) => return Err(format!("Invalid symbol name: {}", name)),
+        };
+        let sym = unsafe { dlsym(self.handle, c_name.as_ptr()) };
+        if sym.is_null() {
+            Err(format!("Could not find symbol {} in {}: {}", name, self.path, last_dl_error()))
+        }
+        else {
+            Ok(sym)
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe { dlclose(self.handle); }
+    }
+}
+
+/**
+ * The C types that can show up in an `&extern` signature.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CType {
+    Int,    // C `int`, which is 32 bits on the platforms we support
+    Long,   // C `long`; rasp ints are 64 bits
+    Double, // C `double`
+    Void,   // only valid as a return type
+}
+
+impl CType {
+    pub fn from_name(name: &str) -> Result<CType, String> {
+        match name {
+            "int" => Ok(CType::Int),
+            "long" => Ok(CType::Long),
+            "double" => Ok(CType::Double),
+            "void" => Ok(CType::Void),
+            _ => Err(format!("Unknown C type `{}'; expected one of int, long, double or void", name)),
+        }
+    }
+}
+
+/**
+ * The largest number of arguments a foreign function may take.
+ */
+pub const MAX_FOREIGN_ARGS: usize = 4;
+
+/**
+ * A function loaded from a shared library through a declared signature.
+ *
+ * Without a proper FFI library, the only calls we can make are ones whose shape is known at compile time, so every
+ * argument must be of the same type (all ints, all longs or all doubles), up to `MAX_FOREIGN_ARGS` of them.
+ */
+pub struct ForeignFunction {
+    name: String,
+    ptr: *mut c_void,
+    args: Vec<CType>,
+    ret: CType,
+    _library: Rc<Library>,
+}
+
+macro_rules! call_foreign {
+    ($ptr:expr, $ret:ty, $arg:ty, $args:expr; $($idx:expr),*) => {{
+        let fun: extern "C" fn($(call_foreign!(@ty $idx, $arg)),*) -> $ret = mem::transmute($ptr);
+        fun($($args[$idx]),*)
+    }};
+    (@ty $idx:expr, $arg:ty) => { $arg };
+}
+
+macro_rules! call_by_arity {
+    ($ptr:expr, $ret:ty, $arg:ty, $args:expr) => {
+        match $args.len() {
+            0 => call_foreign!($ptr, $ret, $arg, $args; ),
+            1 => call_foreign!($ptr, $ret, $arg, $args; 0),
+            2 => call_foreign!($ptr, $ret, $arg, $args; 0, 1),
+            3 => call_foreign!($ptr, $ret, $arg, $args; 0, 1, 2),
+            4 => call_foreign!($ptr, $ret, $arg, $args; 0, 1, 2, 3),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/**
+ * The arguments of a foreign call, converted to the C type they were declared as.
+ */
+enum ForeignArgs {
+    Int(Vec<c_int>),
+    Long(Vec<c_long>),
+    Double(Vec<f64>),
+}
+
+macro_rules! call_with_args {
+    ($ptr:expr, $ret:ty, $args:expr) => {
+        match $args {
+            ForeignArgs::Int(ref args) => call_by_arity!($ptr, $ret, c_int, args),
+            ForeignArgs::Long(ref args) => call_by_arity!($ptr, $ret, c_long, args),
+            ForeignArgs::Double(ref args) => call_by_arity!($ptr, $ret, f64, args),
+        }
+    }
+}
+
+impl ForeignFunction {
+    pub fn load(decl: &ExternDecl, library: Rc<Library>) -> Result<ForeignFunction, String> {
+        let mut args = vec![];
+        for a in &decl.args {
+            let ctype = try!(CType::from_name(a));
+            if ctype == CType::Void {
+                return Err(format!("Argument types of {} may not be void", decl.name));
+            }
+            args.push(ctype);
+        }
+        if args.len() > MAX_FOREIGN_ARGS {
+            return Err(format!("{} takes {} arguments, but foreign functions may take at most {}", decl.name,
+                args.len(), MAX_FOREIGN_ARGS));
+        }
+        if args.iter().any(|a| *a != args[0]) {
+            return Err(format!("Arguments of {} must all be the same type", decl.name));
+        }
+        let ret = try!(CType::from_name(&decl.ret));
+        let ptr = try!(library.symbol(&decl.name));
+        Ok(ForeignFunction {
+            name: decl.name.clone(),
+            ptr: ptr,
+            args: args,
+            ret: ret,
+            _library: library,
+        })
+    }
+
+    pub fn arity(&self) -> usize {
+        self.args.len()
+    }
+
+    pub fn call(&self, args: &[Atom]) -> Result<Atom, String> {
+        assert_eq!(args.len(), self.args.len());
+        let mut foreign_args = match self.args.first() {
+            Some(&CType::Int) => ForeignArgs::Int(vec![]),
+            Some(&CType::Double) => ForeignArgs::Double(vec![]),
+            _ => ForeignArgs::Long(vec![]),
+        };
+        for a in args {
+            match (a, &mut foreign_args) {
+                (&Atom::IntLit(i), &mut ForeignArgs::Int(ref mut v)) if i as c_int as i64 == i => v.push(i as c_int),
+                (&Atom::IntLit(i), &mut ForeignArgs::Int(_)) =>
+                    return Err(format!("Argument {} of foreign function {} doesn't fit in a C int", i, self.name)),
+                (&Atom::IntLit(i), &mut ForeignArgs::Long(ref mut v)) => v.push(i as c_long),
+                (&Atom::IntLit(i), &mut ForeignArgs::Double(ref mut v)) => v.push(i as f64),
+                (&Atom::DubLit(d), &mut ForeignArgs::Double(ref mut v)) => v.push(d),
+                _ => return Err(format!("Invalid argument `{}' for foreign function {}", a, self.name)),
+            }
+        }
+        let ptr = self.ptr;
+        unsafe {
+            match self.ret {
+                CType::Int => Ok(Atom::IntLit(call_with_args!(ptr, c_int, foreign_args) as i64)),
+                CType::Long => Ok(Atom::IntLit(call_with_args!(ptr, c_long, foreign_args) as i64)),
+                CType::Double => Ok(Atom::DubLit(call_with_args!(ptr, f64, foreign_args))),
+                CType::Void => {
+                    call_with_args!(ptr, (), foreign_args);
+                    Ok(Atom::IntLit(0))
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,583 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::mem;
+use ast::Atom;
+
+/**
+ * A handle to an object on the heap. The generation is bumped every time a slot is reused, so a handle that outlives
+ * its object is caught instead of quietly pointing at whatever was allocated there next.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HeapRef {
+    index: usize,
+    generation: u64,
+}
+
+impl fmt::Display for HeapRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.index)
+    }
+}
+
+/**
+ * A value that can be used as a dictionary key. Only values that can't change and compare exactly can be keys, so
+ * floats and compound values can't.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DictKey {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl DictKey {
+    pub fn from_atom(atom: &Atom, heap: &Heap) -> Result<DictKey, String> {
+        match atom {
+            &Atom::BoolLit(b) => Ok(DictKey::Bool(b)),
+            &Atom::IntLit(i) => Ok(DictKey::Int(i)),
+            &Atom::StrLit(ref s) => Ok(DictKey::Str(s.clone())),
+            other => {
+                let other = heap.format(other);
+                Err(format!("Dictionary keys must be integers, booleans or strings, but got `{}'", other))
+            },
+        }
+    }
+
+    pub fn to_atom(&self) -> Atom {
+        match self {
+            &DictKey::Bool(b) => Atom::BoolLit(b),
+            &DictKey::Int(i) => Atom::IntLit(i),
+            &DictKey::Str(ref s) => Atom::StrLit(s.clone()),
+        }
+    }
+}
+
+/**
+ * A compound value that lives on the heap.
+ */
+#[derive(Clone, Debug)]
+pub enum Object {
+    Pair(Atom, Atom),
+    Dict(BTreeMap<DictKey, Atom>), /* Kept in key order, so that dictionaries print and iterate the same every time */
+    Vector(Vec<Atom>),
+    Box(Atom), /* A mutable cell, for state that's shared between the places that hold it */
+}
+
+impl Object {
+    /**
+     * Names the kind of object this is, the way error messages do.
+     */
+    pub fn kind(&self) -> &'static str {
+        match self {
+            &Object::Pair(_, _) => "a pair",
+            &Object::Dict(_) => "a dictionary",
+            &Object::Vector(_) => "a vector",
+            &Object::Box(_) => "a box",
+        }
+    }
+
+    /**
+     * Gets the heap objects that this object refers to directly.
+     */
+    fn refs(&self) -> Vec<HeapRef> {
+        match self {
+            &Object::Pair(ref car, ref cdr) => car.heap_ref().into_iter().chain(cdr.heap_ref()).collect(),
+            &Object::Dict(ref entries) => entries.values().filter_map(Atom::heap_ref).collect(),
+            &Object::Vector(ref items) => items.iter().filter_map(Atom::heap_ref).collect(),
+            &Object::Box(ref value) => value.heap_ref().into_iter().collect(),
+        }
+    }
+
+    /**
+     * Roughly how many bytes this object takes up, including its slot and any strings it owns.
+     */
+    fn size(&self) -> usize {
+        mem::size_of::<Slot>() + match self {
+            &Object::Pair(ref car, ref cdr) => owned(car) + owned(cdr),
+            &Object::Dict(ref entries) => entries.iter().map(|(key, value)| entry_size(key, value)).sum(),
+            &Object::Vector(ref items) =>
+                items.capacity() * mem::size_of::<Atom>() + items.iter().map(owned).sum::<usize>(),
+            &Object::Box(ref value) => owned(value),
+        }
+    }
+}
+
+/**
+ * The bytes an atom owns outside of itself.
+ */
+fn owned(atom: &Atom) -> usize {
+    match atom {
+        &Atom::StrLit(ref s) | &Atom::Identifier(ref s) => s.capacity(),
+        _ => 0,
+    }
+}
+
+/**
+ * Roughly how many bytes a dictionary entry takes up.
+ */
+fn entry_size(key: &DictKey, value: &Atom) -> usize {
+    let key_size = match key {
+        &DictKey::Str(ref s) => s.capacity(),
+        _ => 0,
+    };
+    mem::size_of::<(DictKey, Atom)>() + key_size + owned(value)
+}
+
+/**
+ * A place in a heap object that a value can be stored in or removed from.
+ */
+#[derive(Clone, Debug)]
+pub enum Place {
+    Car,
+    Cdr,
+    Item(usize), /* An item of a vector that's already there */
+    End, /* The end of a vector: storing there pushes a value, and removing from there pops one */
+    Entry(DictKey),
+    Contents, /* What's in a box */
+}
+
+/**
+ * Counters kept by the collector.
+ */
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GcStats {
+    pub collections: usize,
+    pub allocated: usize, /* Objects allocated over the heap's lifetime */
+    pub freed: usize, /* Objects freed over the heap's lifetime */
+    pub live: usize, /* Objects alive right now */
+    pub bytes: usize, /* Approximate size of the objects alive right now */
+    pub last_freed: usize, /* Objects freed by the most recent collection */
+}
+
+impl fmt::Display for GcStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} collections, {} allocated, {} freed, {} live ({} bytes)",
+               self.collections, self.allocated, self.freed, self.live, self.bytes)
+    }
+}
+
+/**
+ * The objects that a walk over the heap is in the middle of, so that it can tell when it comes back around to one.
+ * They're kept in order, so that a walk can go back to where it was with `truncate`, and in a set, so that checking
+ * for one doesn't have to look at every object on the way down a long list.
+ */
+pub(crate) struct Visiting<T> {
+    stack: Vec<T>,
+    set: HashSet<T>,
+}
+
+impl<T: Copy + Eq + Hash> Visiting<T> {
+    pub fn new() -> Visiting<T> {
+        Visiting {
+            stack: vec![],
+            set: HashSet::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn contains(&self, item: &T) -> bool {
+        self.set.contains(item)
+    }
+
+    /**
+     * Starts visiting something. It mustn't already be being visited.
+     */
+    pub fn push(&mut self, item: T) {
+        let added = self.set.insert(item);
+        debug_assert!(added);
+        self.stack.push(item);
+    }
+
+    /**
+     * Stops visiting everything after the first `len` things.
+     */
+    pub fn truncate(&mut self, len: usize) {
+        while self.stack.len() > len {
+            let item = self.stack.pop().unwrap();
+            self.set.remove(&item);
+        }
+    }
+}
+
+struct Slot {
+    generation: u64,
+    marked: bool,
+    size: usize, /* The object's size, kept up to date as it changes */
+    object: Option<Object>,
+}
+
+/**
+ * The number of allocations before the first automatic collection.
+ */
+const INITIAL_THRESHOLD: usize = 1024;
+
+/**
+ * A heap of compound values, managed by a tracing mark-and-sweep collector. The heap doesn't know what its roots are;
+ * whoever owns it passes them to `collect`.
+ */
+pub struct Heap {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    threshold: usize, /* Collect once this many objects are live */
+    byte_limit: Option<usize>, /* Collect whenever the heap is bigger than this */
+    stats: GcStats,
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap {
+            slots: vec![],
+            free: vec![],
+            threshold: INITIAL_THRESHOLD,
+            byte_limit: None,
+            stats: GcStats::default(),
+        }
+    }
+
+    pub fn alloc(&mut self, object: Object) -> HeapRef {
+        let size = object.size();
+        self.stats.allocated += 1;
+        self.stats.live += 1;
+        self.stats.bytes += size;
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.generation += 1;
+                slot.size = size;
+                slot.object = Some(object);
+                HeapRef { index: index, generation: slot.generation }
+            },
+            None => {
+                self.slots.push(Slot { generation: 0, marked: false, size: size, object: Some(object) });
+                HeapRef { index: self.slots.len() - 1, generation: 0 }
+            },
+        }
+    }
+
+    pub fn get(&self, r: HeapRef) -> Result<&Object, String> {
+        match self.slots.get(r.index) {
+            Some(&Slot { generation, object: Some(ref object), .. }) if generation == r.generation => Ok(object),
+            _ => Err(format!("Reference to freed object {}", r)),
+        }
+    }
+
+    /**
+     * Stores a value in an object, returning the value that it replaced, if there was one. Objects are only changed
+     * through here and `remove`, so that the heap's size stays right as they grow.
+     */
+    pub fn store(&mut self, r: HeapRef, place: Place, value: Atom) -> Result<Option<Atom>, String> {
+        let added = owned(&value);
+        let (old, grown) = {
+            let object = try!(self.get_mut(r));
+            match (object, place) {
+                (&mut Object::Pair(ref mut car, _), Place::Car) => (Some(mem::replace(car, value)), 0),
+                (&mut Object::Pair(_, ref mut cdr), Place::Cdr) => (Some(mem::replace(cdr, value)), 0),
+                (&mut Object::Vector(ref mut items), Place::Item(index)) if index < items.len() =>
+                    (Some(mem::replace(&mut items[index], value)), 0),
+                (&mut Object::Vector(ref mut items), Place::End) => {
+                    let capacity = items.capacity();
+                    items.push(value);
+                    (None, (items.capacity() - capacity) * mem::size_of::<Atom>())
+                },
+                (&mut Object::Dict(ref mut entries), Place::Entry(key)) => {
+                    let entry = entry_size(&key, &Atom::BoolLit(false));
+                    match entries.insert(key, value) {
+                        Some(old) => (Some(old), 0),
+                        None => (None, entry),
+                    }
+                },
+                (&mut Object::Box(ref mut contents), Place::Contents) => (Some(mem::replace(contents, value)), 0),
+                (object, place) => return Err(format!("Can't store into {:?} of {}", place, object.kind())),
+            }
+        };
+        let removed = old.as_ref().map(owned).unwrap_or(0);
+        self.resize(r, added + grown, removed);
+        Ok(old)
+    }
+
+    /**
+     * Removes a dictionary entry or the last item of a vector, returning it if it was there.
+     */
+    pub fn remove(&mut self, r: HeapRef, place: Place) -> Result<Option<Atom>, String> {
+        let (old, removed) = {
+            let object = try!(self.get_mut(r));
+            match (object, place) {
+                (&mut Object::Vector(ref mut items), Place::End) => {
+                    let old = items.pop();
+                    let removed = old.as_ref().map(owned).unwrap_or(0);
+                    (old, removed)
+                },
+                (&mut Object::Dict(ref mut entries), Place::Entry(key)) => match entries.remove_entry(&key) {
+                    Some((key, old)) => {
+                        let removed = entry_size(&key, &old);
+                        (Some(old), removed)
+                    },
+                    None => (None, 0),
+                },
+                (object, place) => return Err(format!("Can't remove {:?} from {}", place, object.kind())),
+            }
+        };
+        self.resize(r, 0, removed);
+        Ok(old)
+    }
+
+    fn get_mut(&mut self, r: HeapRef) -> Result<&mut Object, String> {
+        match self.slots.get_mut(r.index) {
+            Some(&mut Slot { generation, object: Some(ref mut object), .. }) if generation == r.generation =>
+                Ok(object),
+            _ => Err(format!("Reference to freed object {}", r)),
+        }
+    }
+
+    /**
+     * Changes an object's recorded size, and the heap's along with it.
+     */
+    fn resize(&mut self, r: HeapRef, added: usize, removed: usize) {
+        let slot = &mut self.slots[r.index];
+        slot.size = slot.size + added - removed;
+        self.stats.bytes = self.stats.bytes + added - removed;
+    }
+
+    /**
+     * Whether enough has been allocated since the last collection that it's worth collecting again.
+     */
+    pub fn should_collect(&self) -> bool {
+        self.stats.live >= self.threshold || self.over_limit()
+    }
+
+    /**
+     * Sets a size that the heap should be collected down to whenever it grows past it.
+     */
+    pub fn set_byte_limit(&mut self, limit: Option<usize>) {
+        self.byte_limit = limit;
+    }
+
+    /**
+     * Roughly how many bytes a list of `length` numbers takes up, for builtins that check a list fits before they make
+     * it.
+     */
+    pub fn list_size(length: u64) -> u64 {
+        length.saturating_mul(mem::size_of::<Slot>() as u64)
+    }
+
+    /**
+     * Whether the heap is bigger than its byte limit.
+     */
+    pub fn over_limit(&self) -> bool {
+        self.byte_limit.map(|limit| self.stats.bytes > limit).unwrap_or(false)
+    }
+
+    /**
+     * Frees every object that can't be reached from the given roots, returning how many were freed.
+     */
+    pub fn collect<I>(&mut self, roots: I) -> usize
+        where I: IntoIterator<Item=HeapRef>
+    {
+        // Mark
+        let mut pending: Vec<HeapRef> = roots.into_iter().collect();
+        while let Some(r) = pending.pop() {
+            let refs = match self.slots.get_mut(r.index) {
+                Some(slot) if slot.generation == r.generation && !slot.marked => {
+                    slot.marked = true;
+                    match slot.object {
+                        Some(ref object) => object.refs(),
+                        None => continue,
+                    }
+                },
+                _ => continue,
+            };
+            pending.extend(refs);
+        }
+
+        // Sweep
+        let mut freed = 0;
+        let mut freed_bytes = 0;
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.marked {
+                slot.marked = false;
+            }
+            else if slot.object.is_some() {
+                slot.object = None;
+                self.free.push(index);
+                freed += 1;
+                freed_bytes += slot.size;
+            }
+        }
+
+        self.stats.collections += 1;
+        self.stats.freed += freed;
+        self.stats.live -= freed;
+        self.stats.bytes -= freed_bytes;
+        self.stats.last_freed = freed;
+        // Let the heap grow before the next collection, so that big live sets aren't traced over and over
+        self.threshold = if self.stats.live * 2 > INITIAL_THRESHOLD { self.stats.live * 2 } else { INITIAL_THRESHOLD };
+        freed
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /**
+     * Compares two values structurally, following heap references. Values that contain themselves are equal when
+     * they have the same shape.
+     */
+    pub fn equals(&self, lhs: &Atom, rhs: &Atom) -> Result<bool, String> {
+        self.equals_visiting(lhs, rhs, &mut Visiting::new())
+    }
+
+    fn equals_visiting(&self, lhs: &Atom, rhs: &Atom, visiting: &mut Visiting<(HeapRef, HeapRef)>)
+        -> Result<bool, String>
+    {
+        let (l, r) = match (lhs, rhs) {
+            (&Atom::Ref(l), &Atom::Ref(r)) => (l, r),
+            _ => return Ok(lhs.equals(rhs).is_true()),
+        };
+        // Coming back to a pair of objects that's already being compared means the rest of them matched on the way
+        if l == r || visiting.contains(&(l, r)) {
+            return Ok(true);
+        }
+        let mark = visiting.len();
+        visiting.push((l, r));
+        let equal = match (try!(self.get(l)), try!(self.get(r))) {
+            (&Object::Pair(ref lcar, ref lcdr), &Object::Pair(ref rcar, ref rcdr)) => {
+                let mut equal = try!(self.equals_visiting(lcar, rcar, visiting));
+                let (mut lrest, mut rrest) = (lcdr.clone(), rcdr.clone());
+                // Walk down both lists together instead of recursing on the tails, so long lists don't run deep
+                while equal {
+                    let (lnext, rnext) = match (&lrest, &rrest) {
+                        (&Atom::Ref(lnext), &Atom::Ref(rnext)) if lnext != rnext
+                            && !visiting.contains(&(lnext, rnext)) => (lnext, rnext),
+                        _ => break,
+                    };
+                    let (lcar, lcdr, rcar, rcdr) = match (try!(self.get(lnext)), try!(self.get(rnext))) {
+                        (&Object::Pair(ref lcar, ref lcdr), &Object::Pair(ref rcar, ref rcdr)) =>
+                            (lcar.clone(), lcdr.clone(), rcar.clone(), rcdr.clone()),
+                        _ => break,
+                    };
+                    visiting.push((lnext, rnext));
+                    equal = try!(self.equals_visiting(&lcar, &rcar, visiting));
+                    lrest = lcdr;
+                    rrest = rcdr;
+                }
+                equal && try!(self.equals_visiting(&lrest, &rrest, visiting))
+            },
+            (&Object::Dict(ref lhs), &Object::Dict(ref rhs)) => {
+                let mut equal = lhs.len() == rhs.len();
+                for ((lkey, lvalue), (rkey, rvalue)) in lhs.iter().zip(rhs) {
+                    if !equal {
+                        break;
+                    }
+                    equal = lkey == rkey && try!(self.equals_visiting(lvalue, rvalue, visiting));
+                }
+                equal
+            },
+            (&Object::Vector(ref lhs), &Object::Vector(ref rhs)) => {
+                let mut equal = lhs.len() == rhs.len();
+                for (lvalue, rvalue) in lhs.iter().zip(rhs) {
+                    if !equal {
+                        break;
+                    }
+                    equal = try!(self.equals_visiting(lvalue, rvalue, visiting));
+                }
+                equal
+            },
+            (&Object::Box(ref lhs), &Object::Box(ref rhs)) => try!(self.equals_visiting(lhs, rhs, visiting)),
+            _ => false,
+        };
+        visiting.truncate(mark);
+        Ok(equal)
+    }
+
+    /**
+     * Formats a value for printing, following heap references. Lists are printed the LISP way, with `@nah` as the
+     * empty list, dictionaries and vectors are printed like their literals, boxes are printed as `<box value>`, and
+     * objects that contain themselves are cut off with `...`.
+     */
+    pub fn format(&self, atom: &Atom) -> String {
+        let mut out = String::new();
+        self.format_into(atom, &mut out, &mut Visiting::new());
+        out
+    }
+
+    fn format_into(&self, atom: &Atom, out: &mut String, visiting: &mut Visiting<HeapRef>) {
+        let r = match atom {
+            &Atom::Ref(r) => r,
+            _ => {
+                out.push_str(&format!("{}", atom));
+                return;
+            },
+        };
+        if visiting.contains(&r) {
+            out.push_str("...");
+            return;
+        }
+        let mark = visiting.len();
+        visiting.push(r);
+        match self.get(r) {
+            Ok(&Object::Pair(ref car, ref cdr)) => {
+                out.push('(');
+                self.format_into(car, out, visiting);
+                let mut rest = cdr.clone();
+                // Walk down the list as long as the tail is another pair that we haven't seen
+                loop {
+                    let next = match rest {
+                        Atom::Ref(next) if !visiting.contains(&next) => next,
+                        _ => break,
+                    };
+                    match self.get(next) {
+                        Ok(&Object::Pair(ref car, ref cdr)) => {
+                            visiting.push(next);
+                            out.push(' ');
+                            self.format_into(car, out, visiting);
+                            rest = cdr.clone();
+                        },
+                        _ => break,
+                    }
+                }
+                // A list that ends with the empty list is a proper one, and doesn't need its tail spelled out
+                match rest {
+                    Atom::BoolLit(false) => {},
+                    _ => {
+                        out.push_str(" . ");
+                        self.format_into(&rest, out, visiting);
+                    },
+                }
+                out.push(')');
+            },
+            Ok(&Object::Dict(ref entries)) => {
+                out.push('{');
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    self.format_into(&key.to_atom(), out, visiting);
+                    out.push(' ');
+                    self.format_into(value, out, visiting);
+                }
+                out.push('}');
+            },
+            Ok(&Object::Vector(ref items)) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    self.format_into(item, out, visiting);
+                }
+                out.push(']');
+            },
+            Ok(&Object::Box(ref value)) => {
+                out.push_str("<box ");
+                self.format_into(value, out, visiting);
+                out.push('>');
+            },
+            Err(err) => out.push_str(&format!("<{}>", err)),
+        }
+        visiting.truncate(mark);
+    }
+}
@@ -1,5 +1,19 @@
 mod bytecode;
+mod code_object;
+mod codefile;
+mod disassemble;
 mod builtin_function;
+mod capability;
+mod native_function;
+mod ffi;
+mod heap;
 
 pub use internal::bytecode::Bytecode;
-pub use internal::builtin_function::*;
\ No newline at end of file
+pub use internal::code_object::CodeObject;
+pub use internal::codefile::*;
+pub use internal::disassemble::Disassembly;
+pub use internal::builtin_function::*;
+pub use internal::capability::*;
+pub use internal::native_function::*;
+pub use internal::ffi::*;
+pub use internal::heap::*;
@@ -0,0 +1,166 @@
+use ast::Atom;
+
+/**
+ * Converts an atom into a Rust value, for passing arguments to native functions.
+ */
+pub trait FromAtom: Sized {
+    fn from_atom(atom: &Atom) -> Result<Self, String>;
+}
+
+/**
+ * Converts a Rust value into an atom, for returning values from native functions.
+ */
+pub trait IntoAtom {
+    fn into_atom(self) -> Result<Atom, String>;
+}
+
+impl FromAtom for Atom {
+    fn from_atom(atom: &Atom) -> Result<Atom, String> {
+        Ok(atom.clone())
+    }
+}
+
+impl FromAtom for i64 {
+    fn from_atom(atom: &Atom) -> Result<i64, String> {
+        match atom {
+            &Atom::IntLit(i) => Ok(i),
+            _ => Err(format!("Expected an int, but got `{}'", atom)),
+        }
+    }
+}
+
+impl FromAtom for f64 {
+    fn from_atom(atom: &Atom) -> Result<f64, String> {
+        match atom {
+            &Atom::IntLit(i) => Ok(i as f64),
+            &Atom::DubLit(d) => Ok(d),
+            _ => Err(format!("Expected a float, but got `{}'", atom)),
+        }
+    }
+}
+
+impl FromAtom for bool {
+    fn from_atom(atom: &Atom) -> Result<bool, String> {
+        match atom {
+            &Atom::BoolLit(b) => Ok(b),
+            _ => Err(format!("Expected a bool, but got `{}'", atom)),
+        }
+    }
+}
+
+impl FromAtom for String {
+    fn from_atom(atom: &Atom) -> Result<String, String> {
+        match atom {
+            &Atom::StrLit(ref s) => Ok(s.clone()),
+            _ => Err(format!("Expected a string, but got `{}'", atom)),
+        }
+    }
+}
+
+impl IntoAtom for Atom {
+    fn into_atom(self) -> Result<Atom, String> {
+        Ok(self)
+    }
+}
+
+impl IntoAtom for i64 {
+    fn into_atom(self) -> Result<Atom, String> {
+        Ok(Atom::IntLit(self))
+    }
+}
+
+impl IntoAtom for f64 {
+    fn into_atom(self) -> Result<Atom, String> {
+        Ok(Atom::DubLit(self))
+    }
+}
+
+impl IntoAtom for bool {
+    fn into_atom(self) -> Result<Atom, String> {
+        Ok(Atom::BoolLit(self))
+    }
+}
+
+impl IntoAtom for String {
+    fn into_atom(self) -> Result<Atom, String> {
+        Ok(Atom::StrLit(self))
+    }
+}
+
+impl<'a> IntoAtom for &'a str {
+    fn into_atom(self) -> Result<Atom, String> {
+        Ok(Atom::StrLit(self.to_string()))
+    }
+}
+
+/**
+ * Functions that don't return anything give back 0, the same as `&print`.
+ */
+impl IntoAtom for () {
+    fn into_atom(self) -> Result<Atom, String> {
+        Ok(Atom::IntLit(0))
+    }
+}
+
+impl<T: IntoAtom> IntoAtom for Result<T, String> {
+    fn into_atom(self) -> Result<Atom, String> {
+        match self {
+            Ok(value) => value.into_atom(),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/**
+ * A Rust function or closure that can be called from rasp. `Args` is a tuple of the argument types, which lets the
+ * same closure type be implemented for multiple arities.
+ */
+pub trait NativeFn<Args> {
+    fn arity(&self) -> usize;
+    fn call(&self, args: &[Atom]) -> Result<Atom, String>;
+}
+
+macro_rules! impl_native_fn {
+    ($count:expr; $($arg:ident : $idx:expr),*) => {
+        impl<Fun, Ret, $($arg),*> NativeFn<($($arg,)*)> for Fun
+            where Fun: Fn($($arg),*) -> Ret, Ret: IntoAtom, $($arg: FromAtom),*
+        {
+            fn arity(&self) -> usize {
+                $count
+            }
+
+            #[allow(unused_variables)]
+            fn call(&self, args: &[Atom]) -> Result<Atom, String> {
+                (self)($(try!($arg::from_atom(&args[$idx]))),*).into_atom()
+            }
+        }
+    }
+}
+
+impl_native_fn!(0; );
+impl_native_fn!(1; A: 0);
+impl_native_fn!(2; A: 0, B: 1);
+impl_native_fn!(3; A: 0, B: 1, C: 2);
+impl_native_fn!(4; A: 0, B: 1, C: 2, D: 3);
+impl_native_fn!(5; A: 0, B: 1, C: 2, D: 3, E: 4);
+
+/**
+ * A function implemented outside of rasp, either registered by an embedder or loaded with `&extern`.
+ */
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub fun: Box<Fn(&[Atom]) -> Result<Atom, String>>,
+}
+
+impl NativeFunction {
+    pub fn new<F, Args>(name: &str, fun: F) -> NativeFunction
+        where F: NativeFn<Args> + 'static
+    {
+        NativeFunction {
+            name: name.to_string(),
+            arity: fun.arity(),
+            fun: Box::new(move |args| fun.call(args)),
+        }
+    }
+}
@@ -1,29 +1,146 @@
 use ast::visitor::MutVisitor;
-use ast::{Expression,Atom,IfElse};
-use internal::Bytecode;
+use ast::{Expression,Atom,Function,IfElse,Span,VarDef};
+use internal::{Bytecode, CodeObject};
+use interpreter::{link, Optimizer, SymbolTable};
 use std::ops::Deref;
+use std::rc::Rc;
 
-pub struct BytecodeGen {
+pub struct BytecodeGen<'s> {
     pub errors: Vec<String>,
     pub warnings: Vec<String>,
     pub bytecode: Vec<Bytecode>,
+    pub lines: Vec<usize>, /* The source line that each instruction came from, or 0 if it's unknown */
     label_count: u64,
+    symbols: &'s mut SymbolTable,
+    locals: Option<Vec<String>>, /* Local slot names, if we're compiling a function body */
+    line_starts: Rc<Vec<usize>>, /* Byte offsets of the start of each source line */
+    line: usize, /* The line of the expression currently being compiled */
+    optimizer: Option<Rc<Optimizer>>,
 }
 
-impl BytecodeGen {
-    pub fn new(args: &Vec<String>) -> BytecodeGen {
-        let mut instructions = vec![];
-        for a in args {
-            instructions.push(Bytecode::Pop(Some(a.clone())));
-        }
+impl<'s> BytecodeGen<'s> {
+    /**
+     * Creates a generator for top-level code, where every variable is a global.
+     */
+    pub fn new(symbols: &'s mut SymbolTable) -> BytecodeGen<'s> {
         BytecodeGen { 
             errors: vec![],
             warnings: vec![],
-            bytecode: instructions,
+            bytecode: vec![],
+            lines: vec![],
             label_count: 0u64,
+            symbols: symbols,
+            locals: None,
+            line_starts: Rc::new(vec![]),
+            line: 0,
+            optimizer: None,
+        }
+    }
+
+    /**
+     * Sets the optimizer that the code, and the code of any functions it defines, is run through before it's linked.
+     */
+    pub fn set_optimizer(&mut self, optimizer: Optimizer) {
+        self.optimizer = Some(Rc::new(optimizer));
+    }
+
+    /**
+     * Sets the source text that the code is being compiled from, so that instructions can be tagged with the line
+     * they came from.
+     */
+    pub fn set_source(&mut self, source: &str) {
+        let mut line_starts = vec![0];
+        for (i, c) in source.char_indices() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        self.line_starts = Rc::new(line_starts);
+    }
+
+    /**
+     * Marks the expression at the given span as the one that instructions are being emitted for.
+     */
+    fn at(&mut self, span: Span) {
+        if self.line_starts.len() > 0 {
+            self.line = match self.line_starts.binary_search(&span.start) {
+                Ok(line) => line + 1,
+                Err(line) => line,
+            };
+        }
+    }
+
+    /**
+     * Adds an instruction, tagged with the current source line.
+     */
+    fn emit(&mut self, code: Bytecode) {
+        self.bytecode.push(code);
+        self.lines.push(self.line);
+    }
+
+    /**
+     * Creates a generator for a function body. The arguments get the first local slots, and are stored into them
+     * from the stack when the function starts.
+     */
+    pub fn for_function(args: &Vec<String>, symbols: &'s mut SymbolTable) -> BytecodeGen<'s> {
+        let mut gen = BytecodeGen::new(symbols);
+        gen.locals = Some(args.clone());
+        gen
+    }
+
+    /**
+     * Emits the function prologue, which moves the arguments from the stack into their slots.
+     */
+    fn store_args(&mut self, arg_count: usize) {
+        // The last argument is on top of the stack, so store them backwards
+        for slot in (0 .. arg_count).rev() {
+            self.emit(Bytecode::StoreLocal(slot));
+        }
+    }
+
+    /**
+     * Gets the slot that a local variable lives in, if it's a local.
+     */
+    fn local_slot(&self, name: &str) -> Option<usize> {
+        match self.locals {
+            Some(ref locals) => locals.iter().position(|l| l == name),
+            None => None,
         }
     }
 
+    /**
+     * Emits the instruction that loads a variable, local or global.
+     */
+    fn load_var(&mut self, name: &str) {
+        let code = match self.local_slot(name) {
+            Some(slot) => Bytecode::LoadLocal(slot),
+            None => Bytecode::LoadGlobal(self.symbols.intern(name)),
+        };
+        self.emit(code);
+    }
+
+    /**
+     * Emits the instruction that defines a variable. Inside a function this gives it a local slot if it doesn't have
+     * one yet; at the top level it's a global.
+     */
+    fn define_var(&mut self, name: &str) {
+        let slot = match self.locals {
+            Some(ref mut locals) => match locals.iter().position(|l| l == name) {
+                Some(slot) => Some(slot),
+                None => {
+                    locals.push(name.to_string());
+                    Some(locals.len() - 1)
+                },
+            },
+            None => None,
+        };
+        let code = match slot {
+            Some(slot) => Bytecode::StoreLocal(slot),
+            None => Bytecode::StoreGlobal(self.symbols.intern(name)),
+        };
+        self.emit(code);
+    }
+
     pub fn next_label(&mut self) -> u64 {
         self.label_count += 1;
         self.label_count - 1
@@ -37,6 +154,25 @@ impl BytecodeGen {
         self.errors.clear();
         self.warnings.clear();
         self.bytecode.clear();
+        self.lines.clear();
+    }
+
+    /**
+     * Optimizes the generated code, if there's an optimizer, and links it into a code object.
+     */
+    pub fn finish(&self, name: &str, args: &Vec<String>) -> Result<CodeObject, String> {
+        let (code, lines) = match self.optimizer {
+            Some(ref optimizer) => {
+                let (code, lines) = try!(optimizer.optimize(&self.bytecode, &self.lines));
+                try!(link(&code, &lines))
+            },
+            None => try!(link(&self.bytecode, &self.lines)),
+        };
+        let locals = match self.locals {
+            Some(ref locals) => locals.clone(),
+            None => vec![],
+        };
+        Ok(CodeObject::new(name.to_string(), args.clone(), locals, code, lines))
     }
 
     fn err(&mut self, msg: String) {
@@ -46,24 +182,87 @@ impl BytecodeGen {
         self.warnings.push(msg);
     }
 
-    fn handle_children(&mut self, children: &Vec<Expression>) {
+    /**
+     * Compiles a function definition into a code object. Any errors in the function body are reported here, when the
+     * function is defined, rather than when it's first called.
+     */
+    fn compile_function(&mut self, fun: &Function) {
+        let line_starts = self.line_starts.clone();
+        let optimizer = self.optimizer.clone();
+        let (errors, warnings, code_object) = {
+            let mut gen = BytecodeGen::for_function(&fun.args, self.symbols);
+            gen.line_starts = line_starts;
+            gen.optimizer = optimizer;
+            gen.at(fun.span);
+            gen.store_args(fun.args.len());
+            for body in &fun.body {
+                gen.visit_expression(body);
+            }
+            let code_object = gen.finish(&fun.name, &fun.args);
+            (gen.errors, gen.warnings, code_object)
+        };
+        for warn in warnings {
+            self.warn(format!("In function {}: {}", fun.name, warn));
+        }
+        if errors.len() > 0 {
+            for err in errors {
+                self.err(format!("In function {}: {}", fun.name, err));
+            }
+            return;
+        }
+        self.at(fun.span);
+        match code_object {
+            Ok(code_object) => self.emit(Bytecode::FunDef(Rc::new(code_object))),
+            Err(err) => self.err(format!("In function {}: {}", fun.name, err)),
+        }
+    }
+
+    /**
+     * Compiles a definition, which makes a new local inside a function or a new global outside of one. Like an
+     * assignment, the defined value is left on the stack.
+     */
+    fn handle_var_def(&mut self, def: &VarDef) {
+        self.visit_expression(&def.value);
+        self.at(def.span);
+        self.define_var(&def.name);
+        self.load_var(&def.name);
+    }
+
+    /**
+     * Compiles an assignment. Unlike a definition, this never makes a new variable: the name has to be one of the
+     * function's locals or a global that's already defined when it runs. The assigned value is left on the stack, so
+     * an assignment can be passed as an argument or returned.
+     */
+    fn handle_var_set(&mut self, set: &VarDef) {
+        self.visit_expression(&set.value);
+        self.at(set.span);
+        let code = match self.local_slot(&set.name) {
+            Some(slot) => Bytecode::SetLocal(slot),
+            None => Bytecode::SetGlobal(self.symbols.intern(&set.name)),
+        };
+        self.emit(code);
+        self.load_var(&set.name);
+    }
+
+    fn handle_children(&mut self, children: &Vec<Expression>, span: Span) {
         assert!(children.len() > 0);
+        self.at(span);
         let ref first = children[0];
         let mut fun_name: Option<String> = None;
 
         match first {
-            &Expression::Children(ref c) => if children.len() > 1 && c.len() > 1 {
+            &Expression::Children(ref c, span) => if children.len() > 1 && c.len() > 1 {
                 self.err("Dynamic function dispatch not yet supported".to_string());
                 return;
             }
-            else { self.handle_children(c) },
+            else { self.handle_children(c, span) },
             // Match the atom as a function with special cases
             // * If there's more than 1 argument, then it's treated as a function.
             //   * This will cause ints and floats to fail as the first argument
             //   * Strings will be resolved as identifiers
             // * If there's only 1 argument, then it's treated as a value.
             //   * Identifiers are the only values that break this rule. They are treated as function calls.
-            &Expression::Atom(ref a) => match a {
+            &Expression::Atom(ref a, _) => match a {
                 &Atom::IntLit(_) => 
                     if children.len() > 1 { self.err("Invalid function call with int literal".to_string()) }
                     else { self.visit_atom(a); },
@@ -77,54 +276,115 @@ impl BytecodeGen {
                     if children.len() > 1 { fun_name = Some(s.clone()); } // we can use strings to call functions 
                     else { self.visit_atom(a); },
                 &Atom::Identifier(ref i) => fun_name = Some(i.clone()), // function name every time
+                &Atom::Ref(_) => self.err("Heap references can't appear in source code".to_string()),
             },
-            &Expression::Unit => self.bytecode.push(Bytecode::Nop),
+            &Expression::Unit(_) => self.emit(Bytecode::Nop),
+            &Expression::Dict(ref entries, span) => self.visit_dict(entries, span),
+            &Expression::Vector(ref items, span) => self.visit_vector(items, span),
             &Expression::FunDef(_) => self.err("Function definitions are not yet allowed below top level.".to_string()),
-            &Expression::IfElse(ref b) => self.visit_ifelse(b.deref())
+            &Expression::TestDef(_) => self.err("Tests are only allowed at top level.".to_string()),
+            &Expression::IfElse(ref b) => self.visit_ifelse(b.deref()),
+            &Expression::Extern(_) => self.err("Extern declarations are only allowed at top level.".to_string()),
+            &Expression::VarDef(ref d) => self.handle_var_def(d),
+            &Expression::VarSet(ref s) => self.handle_var_set(s),
         }
 
         if let Some(fun_str) = fun_name {
             // function call
             for child in children.iter().skip(1) {
+                self.at(child.span());
                 match child {
-                    &Expression::Children(ref c) => self.handle_children(c),
-                    &Expression::Atom(ref a) => self.visit_atom(a),
-                    &Expression::Unit => self.bytecode.push(Bytecode::Nop),
-                    &Expression::FunDef(_) => self.err("Function definitions are not yet allowed below top level.".to_string()),
-                    &Expression::IfElse(ref b) => self.visit_ifelse(b.deref())
+                    &Expression::Children(ref c, span) => self.handle_children(c, span),
+                    &Expression::Atom(ref a, _) => self.visit_atom(a),
+                    &Expression::Unit(_) => self.emit(Bytecode::Nop),
+                    &Expression::Dict(ref entries, span) => self.visit_dict(entries, span),
+                    &Expression::Vector(ref items, span) => self.visit_vector(items, span),
+                    &Expression::FunDef(_) =>
+                        self.err("Function definitions are not yet allowed below top level.".to_string()),
+                    &Expression::TestDef(_) => self.err("Tests are only allowed at top level.".to_string()),
+                    &Expression::IfElse(ref b) => self.visit_ifelse(b.deref()),
+                    &Expression::Extern(_) =>
+                        self.err("Extern declarations are only allowed at top level.".to_string()),
+                    &Expression::VarDef(ref d) => self.handle_var_def(d),
+                    &Expression::VarSet(ref s) => self.handle_var_set(s),
                 }
             }
-            self.bytecode.push(Bytecode::Call(fun_str));
+            self.at(span);
+            self.emit(Bytecode::Call(fun_str));
+        }
+    }
+
+    /**
+     * Compiles a dictionary literal into a new dictionary with each entry put into it in turn.
+     */
+    fn visit_dict(&mut self, entries: &[(Expression, Expression)], span: Span) {
+        self.emit(Bytecode::Call("&dict".to_string()));
+        for &(ref key, ref value) in entries {
+            self.visit_expression(key);
+            self.visit_expression(value);
+            self.at(span);
+            self.emit(Bytecode::Call("&put".to_string()));
+        }
+    }
+
+    /**
+     * Compiles a vector literal into a new vector with each item pushed onto it in turn.
+     */
+    fn visit_vector(&mut self, items: &[Expression], span: Span) {
+        self.emit(Bytecode::Call("&vec".to_string()));
+        for item in items {
+            self.visit_expression(item);
+            self.at(span);
+            self.emit(Bytecode::Call("&vec-push!".to_string()));
         }
     }
 }
 
-impl MutVisitor<()> for BytecodeGen {
+impl<'s> MutVisitor<()> for BytecodeGen<'s> {
     fn visit_expression(&mut self, expr: &Expression) {
+        self.at(expr.span());
         match expr {
-            &Expression::Atom(ref a) => self.visit_atom(a),
-            &Expression::Children(ref c) => self.handle_children(c),
-            &Expression::Unit => self.bytecode.push(Bytecode::Nop),
-            &Expression::FunDef(ref f) => self.bytecode.push(Bytecode::FunDef(f.clone())),
-            &Expression::IfElse(ref b) => self.visit_ifelse(b.deref())
+            &Expression::Atom(ref a, _) => self.visit_atom(a),
+            &Expression::Children(ref c, span) => self.handle_children(c, span),
+            &Expression::Unit(_) => self.emit(Bytecode::Nop),
+            &Expression::Dict(ref entries, span) => self.visit_dict(entries, span),
+            &Expression::Vector(ref items, span) => self.visit_vector(items, span),
+            &Expression::FunDef(ref f) => if self.locals.is_some() {
+                self.err("Function definitions are not yet allowed below top level.".to_string());
+            }
+            else {
+                self.compile_function(f);
+            },
+            // Tests are only run by `rasp test`, which compiles them itself
+            &Expression::TestDef(_) => {},
+            &Expression::IfElse(ref b) => self.visit_ifelse(b.deref()),
+            &Expression::Extern(ref e) => self.emit(Bytecode::Extern(e.clone())),
+            &Expression::VarDef(ref d) => self.handle_var_def(d),
+            &Expression::VarSet(ref s) => self.handle_var_set(s),
         }
     }
 
     fn visit_atom(&mut self, atom: &Atom) {
-        self.bytecode.push(Bytecode::Push(atom.clone()));
+        match atom {
+            &Atom::Identifier(ref name) => self.load_var(name),
+            _ => self.emit(Bytecode::Push(atom.clone())),
+        }
     }
 
     fn visit_ifelse(&mut self, ifelse: &IfElse) {
         self.visit_expression(&ifelse.condition);
+        self.at(ifelse.span);
         let truelabel = self.next_label();
-        self.bytecode.push(Bytecode::JumpTrue(truelabel));
+        self.emit(Bytecode::JumpTrue(truelabel));
         if let Some(ref if_false) = ifelse.if_false {
             self.visit_expression(if_false);
+            self.at(ifelse.span);
         }
         let donelabel = self.next_label();
-        self.bytecode.push(Bytecode::Jump(donelabel));
-        self.bytecode.push(Bytecode::Label(truelabel));
+        self.emit(Bytecode::Jump(donelabel));
+        self.emit(Bytecode::Label(truelabel));
         self.visit_expression(&ifelse.if_true);
-        self.bytecode.push(Bytecode::Label(donelabel));
+        self.at(ifelse.span);
+        self.emit(Bytecode::Label(donelabel));
     }
 }
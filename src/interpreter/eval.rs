@@ -0,0 +1,332 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use ast::{Atom, Expression, ExternDecl, Function, IfElse, Span, VarDef};
+use ast::visitor::MutVisitor;
+use engine::parse;
+use internal::{BuiltinContext, BuiltinFunction, Capability, DictKey, Heap, HeapRef, Object, Visiting, make_list};
+use interpreter::{BytecodeGen, Interpreter, RuntimeError};
+
+/**
+ * The builtins that turn text and data into code at runtime. Code is read as data the way a list is printed: calls
+ * and special forms become lists, names become symbols, and everything else stays the literal it was.
+ */
+pub fn eval_builtins() -> Vec<BuiltinFunction> {
+    vec![
+        BuiltinFunction::new("&read", vec!["source"], read_builtin),
+        BuiltinFunction::new("&eval", vec!["data"], eval_builtin).calls_back(),
+        BuiltinFunction::new("&load", vec!["path"], load_builtin).calls_back().needs(Capability::Files),
+        BuiltinFunction::new("&try", vec!["fun", "handler"], try_builtin).calls_back(),
+    ]
+}
+
+/**
+ * Parses a string holding exactly one form into data.
+ */
+fn read_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let source = match context.args[0] {
+        Atom::StrLit(ref source) => source.clone(),
+        ref other => return Err(format!("Expected a string, but got `{}'", context.heap().format(other)).into()),
+    };
+    let exprs = try!(parse(&source, "<read>").map_err(|err| format!("{}", err)));
+    if exprs.len() != 1 {
+        return Err(format!("Expected exactly one form to read, but got {}", exprs.len()).into());
+    }
+    Ok(try!(to_data(context.heap_mut(), &exprs[0])))
+}
+
+/**
+ * Turns a parsed expression into the data that `&read` gives back.
+ */
+fn to_data(heap: &mut Heap, expr: &Expression) -> Result<Atom, String> {
+    let items = match expr {
+        &Expression::Atom(ref atom, _) => return Ok(atom.clone()),
+        // () and @nah are both the empty list
+        &Expression::Unit(_) => return Ok(Atom::BoolLit(false)),
+        &Expression::Children(ref children, _) => try!(all_data(heap, children)),
+        &Expression::Dict(ref entries, _) => {
+            let mut dict = BTreeMap::new();
+            for &(ref key, ref value) in entries {
+                let key = try!(to_data(heap, key));
+                let value = try!(to_data(heap, value));
+                dict.insert(try!(DictKey::from_atom(&key, heap)), value);
+            }
+            return Ok(Atom::Ref(heap.alloc(Object::Dict(dict))));
+        },
+        &Expression::Vector(ref items, _) => {
+            let items = try!(all_data(heap, items));
+            return Ok(Atom::Ref(heap.alloc(Object::Vector(items))));
+        },
+        &Expression::FunDef(ref fun) => {
+            let mut items = vec![symbol("&fun"), symbol(&fun.name), names(heap, &fun.args)];
+            items.extend(try!(all_data(heap, &fun.body)));
+            items
+        },
+        &Expression::TestDef(ref test) => {
+            let mut items = vec![symbol("&deftest"), Atom::StrLit(test.name.clone())];
+            items.extend(try!(all_data(heap, &test.body)));
+            items
+        },
+        &Expression::IfElse(ref ifelse) => {
+            let mut items = vec![
+                symbol("&if"),
+                try!(to_data(heap, &ifelse.condition)),
+                try!(to_data(heap, &ifelse.if_true)),
+            ];
+            if let Some(ref if_false) = ifelse.if_false {
+                items.push(symbol("&else"));
+                items.push(try!(to_data(heap, if_false)));
+            }
+            items
+        },
+        &Expression::Extern(ref decl) => vec![
+            symbol("&extern"),
+            Atom::StrLit(decl.library.clone()),
+            symbol(&decl.name),
+            names(heap, &decl.args),
+            symbol(&decl.ret),
+        ],
+        &Expression::VarDef(ref def) => vec![symbol("&def"), symbol(&def.name), try!(to_data(heap, &def.value))],
+        &Expression::VarSet(ref set) => vec![symbol("&set!"), symbol(&set.name), try!(to_data(heap, &set.value))],
+    };
+    Ok(make_list(heap, items))
+}
+
+fn all_data(heap: &mut Heap, exprs: &[Expression]) -> Result<Vec<Atom>, String> {
+    let mut items = vec![];
+    for expr in exprs {
+        items.push(try!(to_data(heap, expr)));
+    }
+    Ok(items)
+}
+
+fn symbol(name: &str) -> Atom {
+    Atom::Identifier(name.to_string())
+}
+
+fn names(heap: &mut Heap, names: &[String]) -> Atom {
+    let symbols = names.iter().map(|name| symbol(name)).collect();
+    make_list(heap, symbols)
+}
+
+/* The names that the parser takes as keywords. They only mean something at the head of their own forms */
+const KEYWORDS: &'static [&'static str] = &["&fun", "&deftest", "&if", "&else", "&extern", "&def", "&set!"];
+
+/**
+ * Turns data back into an expression, the same one the parser would give for the data written out as source.
+ */
+fn from_data(heap: &Heap, data: &Atom, visiting: &mut Visiting<HeapRef>) -> Result<Expression, String> {
+    let span = Span::default();
+    let r = match data {
+        &Atom::Identifier(ref name) if KEYWORDS.contains(&name.as_str()) =>
+            return Err(format!("Can't evaluate `{}' on its own, since it's a keyword", name)),
+        &Atom::Ref(r) => r,
+        other => return Ok(Expression::Atom(other.clone(), span)),
+    };
+    // Data that's shared is fine, but data that contains itself would never finish
+    if visiting.contains(&r) {
+        return Err("Can't evaluate data that contains itself".to_string());
+    }
+    let mark = visiting.len();
+    let expr = match try!(heap.get(r)) {
+        &Object::Pair(_, _) => {
+            let items = try!(list_from_data(heap, data, visiting));
+            try!(form_from_data(heap, data, &items, visiting))
+        },
+        &Object::Dict(ref entries) => {
+            visiting.push(r);
+            let mut exprs = vec![];
+            for (key, value) in entries {
+                exprs.push((Expression::Atom(key.to_atom(), span), try!(from_data(heap, value, visiting))));
+            }
+            Expression::Dict(exprs, span)
+        },
+        &Object::Vector(ref items) => {
+            visiting.push(r);
+            Expression::Vector(try!(all_from_data(heap, items, visiting)), span)
+        },
+        &Object::Box(_) => return Err(format!("Can't evaluate `{}', since boxes aren't code", heap.format(data))),
+    };
+    visiting.truncate(mark);
+    Ok(expr)
+}
+
+fn all_from_data(heap: &Heap, items: &[Atom], visiting: &mut Visiting<HeapRef>) -> Result<Vec<Expression>, String> {
+    let mut exprs = vec![];
+    for item in items {
+        exprs.push(try!(from_data(heap, item, visiting)));
+    }
+    Ok(exprs)
+}
+
+/**
+ * Gets the items of a proper list, marking each of its pairs as visited.
+ */
+fn list_from_data(heap: &Heap, data: &Atom, visiting: &mut Visiting<HeapRef>) -> Result<Vec<Atom>, String> {
+    let mut items = vec![];
+    let mut rest = data.clone();
+    while let Atom::Ref(next) = rest {
+        let (car, cdr) = match try!(heap.get(next)) {
+            &Object::Pair(ref car, ref cdr) => (car.clone(), cdr.clone()),
+            _ => break,
+        };
+        if visiting.contains(&next) {
+            return Err("Can't evaluate data that contains itself".to_string());
+        }
+        visiting.push(next);
+        items.push(car);
+        rest = cdr;
+    }
+    match rest {
+        Atom::BoolLit(false) => Ok(items),
+        _ => Err(format!("Can't evaluate `{}', since it isn't a proper list", heap.format(data))),
+    }
+}
+
+fn name_from_data(heap: &Heap, data: &Atom) -> Result<String, String> {
+    match data {
+        &Atom::Identifier(ref name) if !KEYWORDS.contains(&name.as_str()) => Ok(name.clone()),
+        other => Err(format!("Expected a name, but got `{}'", heap.format(other))),
+    }
+}
+
+/**
+ * Gets a list of names, like a function's parameters. The empty list is `@nah`, the same as `()`.
+ */
+fn names_from_data(heap: &Heap, data: &Atom, visiting: &mut Visiting<HeapRef>) -> Result<Vec<String>, String> {
+    let mark = visiting.len();
+    let items = match data {
+        &Atom::BoolLit(false) => vec![],
+        &Atom::Ref(_) => try!(list_from_data(heap, data, visiting)),
+        other => return Err(format!("Expected a list of names, but got `{}'", heap.format(other))),
+    };
+    visiting.truncate(mark);
+    items.iter().map(|item| name_from_data(heap, item)).collect()
+}
+
+/**
+ * Turns the items of a list into a call, or into the special form that its head names.
+ */
+fn form_from_data(heap: &Heap, data: &Atom, items: &[Atom], visiting: &mut Visiting<HeapRef>)
+                  -> Result<Expression, String>
+{
+    let span = Span::default();
+    let head = match items[0] {
+        Atom::Identifier(ref head) if KEYWORDS.contains(&head.as_str()) => head.as_str(),
+        _ => return Ok(Expression::Children(try!(all_from_data(heap, items, visiting)), span)),
+    };
+    let args = &items[1 ..];
+    let is_else = |atom: &Atom| match atom {
+        &Atom::Identifier(ref name) => name == "&else",
+        _ => false,
+    };
+    let expr = match head {
+        "&fun" if args.len() >= 2 => {
+            let name = try!(name_from_data(heap, &args[0]));
+            let params = try!(names_from_data(heap, &args[1], visiting));
+            Expression::FunDef(Function::new(name, params, try!(all_from_data(heap, &args[2 ..], visiting)), span))
+        },
+        "&deftest" if args.len() >= 1 => match args[0] {
+            Atom::StrLit(ref name) => {
+                let body = try!(all_from_data(heap, &args[1 ..], visiting));
+                Expression::TestDef(Function::new(name.clone(), vec![], body, span))
+            },
+            ref other => return Err(format!("Expected a test name, but got `{}'", heap.format(other))),
+        },
+        "&if" if args.len() == 2 || (args.len() == 4 && is_else(&args[2])) => {
+            let condition = try!(from_data(heap, &args[0], visiting));
+            let if_true = try!(from_data(heap, &args[1], visiting));
+            let if_false = if args.len() == 4 { Some(try!(from_data(heap, &args[3], visiting))) } else { None };
+            let ifelse = IfElse { condition: condition, if_true: if_true, if_false: if_false, span: span };
+            Expression::IfElse(Box::new(ifelse))
+        },
+        "&extern" if args.len() == 4 => {
+            let library = match args[0] {
+                Atom::StrLit(ref library) => library.clone(),
+                ref other => return Err(format!("Expected a library name, but got `{}'", heap.format(other))),
+            };
+            let name = try!(name_from_data(heap, &args[1]));
+            let params = try!(names_from_data(heap, &args[2], visiting));
+            let ret = try!(name_from_data(heap, &args[3]));
+            Expression::Extern(ExternDecl::new(library, name, params, ret, span))
+        },
+        "&def" | "&set!" if args.len() == 2 => {
+            let name = try!(name_from_data(heap, &args[0]));
+            let def = Box::new(VarDef { name: name, value: try!(from_data(heap, &args[1], visiting)), span: span });
+            if head == "&def" { Expression::VarDef(def) } else { Expression::VarSet(def) }
+        },
+        _ => return Err(format!("Can't evaluate `{}', since it isn't a well-formed {} form", heap.format(data), head)),
+    };
+    Ok(expr)
+}
+
+/**
+ * Compiles source text at the top level, against the interpreter's globals, and runs it, returning the value of the
+ * last expression or `@nah` if there wasn't one.
+ */
+fn run_source(interp: &mut Interpreter, source: &str, source_name: &str) -> Result<Atom, RuntimeError> {
+    let exprs = try!(parse(source, source_name).map_err(|err| format!("{}", err)));
+    run_exprs(interp, &exprs, Some(source), source_name)
+}
+
+/**
+ * Compiles expressions the way `run_source` does. `source` is the text they were parsed from, if there was any, which
+ * lets instructions be tagged with their lines.
+ */
+fn run_exprs(interp: &mut Interpreter, exprs: &[Expression], source: Option<&str>, source_name: &str)
+    -> Result<Atom, RuntimeError>
+{
+    let code = {
+        let mut gen = BytecodeGen::new(interp.symbols_mut());
+        if let Some(source) = source {
+            gen.set_source(source);
+        }
+        for expr in exprs {
+            gen.visit_expression(expr);
+        }
+        if gen.was_err() {
+            return Err(gen.errors.join("\n").into());
+        }
+        try!(gen.finish(source_name, &vec![]))
+    };
+    let value = try!(interp.run_nested(&code));
+    Ok(value.unwrap_or(Atom::BoolLit(false)))
+}
+
+/**
+ * Compiles and runs data as code in the global environment.
+ */
+fn eval_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let expr = try!(from_data(context.heap(), &context.args[0], &mut Visiting::new()));
+    run_exprs(context.interp, &[expr], None, "<eval>")
+}
+
+/**
+ * Runs a file in this interpreter, so that everything it defines can be used afterwards.
+ */
+fn load_builtin(context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let path = match context.args[0] {
+        Atom::StrLit(ref path) => path.clone(),
+        ref other => return Err(format!("Expected a string, but got `{}'", context.heap().format(other)).into()),
+    };
+    let mut source = String::new();
+    if let Err(err) = File::open(&path).and_then(|mut fp| fp.read_to_string(&mut source)) {
+        return Err(format!("Could not read {}: {}", path, err).into());
+    }
+    run_source(context.interp, &source, &path)
+}
+
+/**
+ * Calls a function with no arguments. If it fails, the handler is called with the error message instead, and its
+ * result is returned. Going over one of the interpreter's limits can't be caught.
+ */
+fn try_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (fun, handler) = (context.args[0].clone(), context.args[1].clone());
+    context.with_pinned(|context| match context.call(&fun, vec![]) {
+        Err(RuntimeError::Error(message)) => {
+            context.interp.clear_error_location();
+            context.call(&handler, vec![Atom::StrLit(message)])
+        },
+        result => result,
+    })
+}
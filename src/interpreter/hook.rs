@@ -0,0 +1,55 @@
+use internal::CodeObject;
+use interpreter::{Interpreter, RuntimeError};
+
+/**
+ * Something that watches code run on the stack machine, like a debugger or a profiler. Hooks are only called when one
+ * is attached with `Interpreter::set_hook`; otherwise the dispatch loop doesn't do anything extra.
+ *
+ * The hook is taken out of the interpreter while it's being called, so it's free to use the interpreter however it
+ * likes, including running more code, which won't be hooked.
+ */
+pub trait Hook {
+    /**
+     * Called before each instruction runs. `index` is the instruction's offset in `code`. Returning an error stops the
+     * program with that error.
+     */
+    fn instruction(&mut self, _interp: &mut Interpreter, _code: &CodeObject, _index: usize)
+        -> Result<(), RuntimeError>
+    {
+        Ok(())
+    }
+
+    /**
+     * Called when a user-defined function is called, after its frame has been pushed.
+     */
+    fn call(&mut self, _interp: &mut Interpreter, _function: &CodeObject) {}
+
+    /**
+     * Called when a user-defined function returns, or fails.
+     */
+    fn ret(&mut self, _interp: &mut Interpreter, _function: &CodeObject) {}
+}
+
+/**
+ * Several hooks at once, called in order. The first one to fail an instruction stops the program.
+ */
+impl Hook for Vec<Box<Hook>> {
+    fn instruction(&mut self, interp: &mut Interpreter, code: &CodeObject, index: usize) -> Result<(), RuntimeError> {
+        for hook in self.iter_mut() {
+            try!(hook.instruction(interp, code, index));
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, interp: &mut Interpreter, function: &CodeObject) {
+        for hook in self.iter_mut() {
+            hook.call(interp, function);
+        }
+    }
+
+    fn ret(&mut self, interp: &mut Interpreter, function: &CodeObject) {
+        for hook in self.iter_mut() {
+            hook.ret(interp, function);
+        }
+    }
+}
@@ -1,17 +1,34 @@
 use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
 use ast::*;
-use ast::visitor::MutVisitor;
 use internal::*;
-use interpreter::{BytecodeGen, VarDecls};
+use interpreter::{Backend, CallTarget, Hook, LimitExceeded, Limits, Linter, RegisterVm, RuntimeError, SymbolTable,
+                  Value, VarDecls};
 
 pub struct Interpreter {
     stack: Vec<Atom>,
-    functions: HashMap<String, Function>,
+    functions: HashMap<String, Rc<CodeObject>>,
     builtin_functions: HashMap<&'static str, BuiltinFunction>,
-    function_code: HashMap<String, Vec<Bytecode>>,
-    generator: BytecodeGen,
+    native_functions: HashMap<String, NativeFunction>,
+    libraries: HashMap<String, Rc<Library>>, /* Shared libraries opened by &extern */
     decl_stack: Vec<VarDecls>, /* This one holds variables declared in functions */
-    global_decls: VarDecls, /* A list of variables defined in the global scope */
+    /* A list of variables defined in the global scope, indexed by symbol ID. They're kept the register machine's
+     * way, so that it can load and store them without copying strings */
+    global_decls: Vec<Option<Value>>,
+    symbols: SymbolTable, /* Names of the global variables */
+    output: Box<Write>, /* Where &print and friends write to */
+    error: Box<Write>, /* Where &eprint and friends write to */
+    backend: Backend,
+    register_vm: RegisterVm,
+    heap: Heap, /* Compound values, which are garbage collected */
+    limits: Limits,
+    capabilities: Capabilities,
+    executed: u64, /* Instructions executed since the last call to `run` or `call` */
+    hook: Option<Box<Hook>>, /* A debugger or profiler, if one is attached */
+    error_location: Option<(String, usize)>, /* Where the last runtime error happened */
+    pinned: Vec<HeapRef>, /* Values that builtins are holding on to while they call back into rasp code */
 }
 
 impl Interpreter {
@@ -20,62 +37,613 @@ impl Interpreter {
             stack: vec![],
             functions: HashMap::new(),
             builtin_functions: make_builtin_functions(),
-            function_code: HashMap::new(),
-            generator: BytecodeGen::new(&vec![]),
-            decl_stack: vec![HashMap::new()],
-            global_decls: HashMap::new(),
+            native_functions: HashMap::new(),
+            libraries: HashMap::new(),
+            decl_stack: vec![],
+            global_decls: vec![],
+            symbols: SymbolTable::new(),
+            output: Box::new(io::stdout()),
+            error: Box::new(io::stderr()),
+            backend: Backend::Stack,
+            register_vm: RegisterVm::new(),
+            heap: Heap::new(),
+            limits: Limits::default(),
+            capabilities: Capabilities::all(),
+            executed: 0,
+            hook: None,
+            error_location: None,
+            pinned: vec![],
         }
     }
 
-    fn set_var(&mut self, label: &str, value: Atom) {
-        if self.decl_stack.len() == 0 {
-            self.global_decls.insert(label.to_string(), value);
+    /**
+     * Creates an interpreter whose output and error output go to the given writers instead of stdout and stderr.
+     */
+    pub fn with_writers<O, E>(output: O, error: E) -> Interpreter
+        where O: Write + 'static, E: Write + 'static
+    {
+        let mut interp = Interpreter::new();
+        interp.set_output(output);
+        interp.set_error(error);
+        interp
+    }
+
+    /**
+     * Sets where printed output goes, returning the old writer. This is stdout by default.
+     */
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) -> Box<Write> {
+        let _ = self.output.flush();
+        mem::replace(&mut self.output, Box::new(output))
+    }
+
+    /**
+     * Sets where error output goes, returning the old writer. This is stderr by default.
+     */
+    pub fn set_error<W: Write + 'static>(&mut self, error: W) -> Box<Write> {
+        let _ = self.error.flush();
+        mem::replace(&mut self.error, Box::new(error))
+    }
+
+    /**
+     * Gets the writer that printed output goes to.
+     */
+    pub fn output(&mut self) -> &mut Write {
+        &mut *self.output
+    }
+
+    /**
+     * Gets the writer that error output goes to.
+     */
+    pub fn error(&mut self) -> &mut Write {
+        &mut *self.error
+    }
+
+    /**
+     * Attaches a hook that's called as code runs, returning the old one. Hooks only work with the stack machine.
+     */
+    pub fn set_hook(&mut self, hook: Option<Box<Hook>>) -> Option<Box<Hook>> {
+        mem::replace(&mut self.hook, hook)
+    }
+
+    /**
+     * Gets the value stack, with the top of the stack last.
+     */
+    pub fn stack(&self) -> &[Atom] {
+        &self.stack
+    }
+
+    /**
+     * Gets the local variables of the function that's running, if there is one.
+     */
+    pub fn frame(&self) -> Option<&VarDecls> {
+        self.decl_stack.last()
+    }
+
+    /**
+     * Gets every global variable that has been defined, along with its name.
+     */
+    pub fn globals(&self) -> Vec<(&str, Atom)> {
+        self.global_decls.iter()
+            .enumerate()
+            .filter_map(|(id, value)| value.as_ref().map(|value| (self.symbols.name(id), value.to_atom())))
+            .collect()
+    }
+
+    /**
+     * Gets the value of a variable in the global scope.
+     */
+    pub fn get_global(&self, name: &str) -> Option<Atom> {
+        match self.symbols.lookup(name) {
+            Some(id) => self.global_var(id).map(Value::to_atom),
+            None => None,
+        }
+    }
+
+    /**
+     * Sets the value of a variable in the global scope, creating it if it doesn't already exist.
+     */
+    pub fn set_global(&mut self, name: &str, value: Atom) {
+        let id = self.symbols.intern(name);
+        self.set_global_var(id, Value::from_atom(value));
+    }
+
+    /**
+     * The symbol table that global variable IDs come from. Code that's going to run in this interpreter must be
+     * compiled against it.
+     */
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn symbols_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbols
+    }
+
+    /**
+     * Checks whether a function with the given name has been defined, registered or is builtin.
+     */
+    pub fn has_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+            || self.native_functions.contains_key(name)
+            || self.builtin_functions.contains_key(name)
+    }
+
+    /**
+     * Gets a linter that knows about every function defined so far.
+     */
+    pub fn linter(&self) -> Linter {
+        let mut linter = Linter::new();
+        for (name, fun) in &self.functions {
+            linter.define_function(name, fun.args.len());
+        }
+        for (name, native) in &self.native_functions {
+            linter.define_function(name, native.arity);
+        }
+        for (name, builtin) in &self.builtin_functions {
+            linter.define_builtin(name, builtin.args.len());
+        }
+        linter
+    }
+
+    /**
+     * Gets the names of every user-defined and registered function. These are looked up before the builtins, so a
+     * builtin with one of these names can't be assumed to be the one that gets called.
+     */
+    pub fn function_names(&self) -> Vec<String> {
+        self.functions.keys()
+            .chain(self.native_functions.keys())
+            .cloned()
+            .collect()
+    }
+
+    /**
+     * Chooses the virtual machine that `run` and `call` use. The stack machine is the default.
+     */
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /**
+     * Sets the limits that scripts are stopped at. Only the call depth is limited by default.
+     */
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.heap.set_byte_limit(limits.max_heap_bytes);
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /**
+     * Sets what scripts are allowed to do outside of the interpreter. Everything is allowed by default.
+     */
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /**
+     * Counts an instruction, and checks the limits that are checked between instructions. `stack_size` is however
+     * many values the VM is holding on to.
+     */
+    pub(crate) fn tick(&mut self, stack_size: usize) -> Result<(), LimitExceeded> {
+        self.executed += 1;
+        if let Some(max) = self.limits.max_instructions {
+            if self.executed > max {
+                return Err(LimitExceeded::Instructions(max));
+            }
+        }
+        if let Some(max) = self.limits.max_stack {
+            if stack_size > max {
+                return Err(LimitExceeded::Stack(max));
+            }
+        }
+        if let Some(max) = self.limits.max_heap_bytes {
+            // The collector has already had a chance to shrink the heap by now
+            if self.heap.over_limit() {
+                return Err(LimitExceeded::HeapBytes(max));
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Checks whether a call can go `depth` calls deep.
+     */
+    pub(crate) fn check_call_depth(&self, depth: usize) -> Result<(), LimitExceeded> {
+        match self.limits.max_call_depth {
+            Some(max) if depth > max => Err(LimitExceeded::CallDepth(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * Runs top-level bytecode, returning the value of the last expression if there was one. Unlike `interpret`, this
+     * leaves the value stack and the decl stack the way it found them, even when an error occurs.
+     */
+    pub fn run(&mut self, code: &CodeObject) -> Result<Option<Atom>, RuntimeError> {
+        self.executed = 0;
+        self.error_location = None;
+        if self.backend == Backend::Register {
+            let mut vm = mem::replace(&mut self.register_vm, RegisterVm::new());
+            let result = vm.run(self, code);
+            self.register_vm = vm;
+            return result;
+        }
+        let stack_len = self.stack.len();
+        let decl_len = self.decl_stack.len();
+        let result = self.interpret(code);
+        let value = if self.stack.len() > stack_len { self.stack.pop() } else { None };
+        self.stack.truncate(stack_len);
+        self.decl_stack.truncate(decl_len);
+        match result {
+            Ok(_) => Ok(value),
+            Err(err) => Err(err),
+        }
+    }
+
+    /**
+     * Runs code in the frame of the function that's running, rather than at the top level, returning the value of the
+     * last expression if there was one. The code has to have been compiled against that function's locals. This is
+     * for debuggers, to evaluate expressions while a program is paused.
+     */
+    pub fn run_in_frame(&mut self, code: &CodeObject) -> Result<Option<Atom>, RuntimeError> {
+        // The code might define locals that the paused function doesn't have room for yet
+        if let Some(frame) = self.decl_stack.last_mut() {
+            if frame.len() < code.locals.len() {
+                frame.resize(code.locals.len(), None);
+            }
+        }
+        let stack_len = self.stack.len();
+        let result = self.interpret(code);
+        let value = if self.stack.len() > stack_len { self.stack.pop() } else { None };
+        self.stack.truncate(stack_len);
+        result.map(|_| value)
+    }
+
+    /**
+     * Runs top-level code from inside a builtin that's already running, returning the value of the last expression if
+     * there was one. Like `call_value`, this carries on the run that called the builtin rather than starting a new one.
+     */
+    pub fn run_nested(&mut self, code: &CodeObject) -> Result<Option<Atom>, RuntimeError> {
+        let stack_len = self.stack.len();
+        let decl_len = self.decl_stack.len();
+        let result = self.interpret(code);
+        let value = if self.stack.len() > stack_len { self.stack.pop() } else { None };
+        self.stack.truncate(stack_len);
+        self.decl_stack.truncate(decl_len);
+        result.map(|_| value)
+    }
+
+    /**
+     * Calls a function by name with the given arguments, returning its result.
+     */
+    pub fn call(&mut self, fname: &str, args: Vec<Atom>) -> Result<Atom, RuntimeError> {
+        if !self.has_function(fname) {
+            return Err(format!("Function {} not found", fname).into());
+        }
+        self.executed = 0;
+        self.error_location = None;
+        if self.backend == Backend::Register {
+            let mut vm = mem::replace(&mut self.register_vm, RegisterVm::new());
+            let result = vm.call(self, fname, args);
+            self.register_vm = vm;
+            return result;
+        }
+        let stack_len = self.stack.len();
+        let decl_len = self.decl_stack.len();
+        for arg in args {
+            self.stack.push(arg);
+        }
+        let result = self.handle_call(fname);
+        let value = if self.stack.len() > stack_len { self.stack.pop() } else { None };
+        self.stack.truncate(stack_len);
+        self.decl_stack.truncate(decl_len);
+        match result {
+            Ok(_) => match value {
+                Some(atom) => Ok(atom),
+                None => Err(format!("Function {} did not return a value", fname).into()),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /**
+     * Registers a Rust function or closure so that it can be called from rasp code by name. Arguments are converted
+     * from atoms to the closure's parameter types with `FromAtom`, and the return value is converted back with
+     * `IntoAtom`.
+     *
+     * ```ignore
+     * interp.register_function("&hypot", |x: f64, y: f64| (x * x + y * y).sqrt());
+     * ```
+     */
+    pub fn register_function<F, Args>(&mut self, name: &str, fun: F)
+        where F: NativeFn<Args> + 'static, Args: 'static
+    {
+        self.native_functions.insert(name.to_string(), NativeFunction::new(name, fun));
+    }
+
+    /**
+     * Works out what a call to the named function will do, for translating calls to register code.
+     */
+    pub(crate) fn resolve_call(&self, fname: &str) -> Option<CallTarget> {
+        if let Some(fun) = self.functions.get(fname) {
+            Some(CallTarget::Function(fun.args.len()))
+        }
+        else if let Some(native) = self.native_functions.get(fname) {
+            Some(CallTarget::Function(native.arity))
+        }
+        else if let Some(builtin) = self.builtin_functions.get(fname) {
+            match fname {
+                "-" => Some(CallTarget::Sub),
+                "*" => Some(CallTarget::Mul),
+                "==" => Some(CallTarget::Eq),
+                _ => Some(CallTarget::Function(builtin.args.len())),
+            }
+        }
+        else {
+            None
+        }
+    }
+
+    pub(crate) fn user_function(&self, fname: &str) -> Option<Rc<CodeObject>> {
+        self.functions.get(fname).cloned()
+    }
+
+    /**
+     * Defines a user function, replacing any function with the same name.
+     */
+    pub(crate) fn define_function(&mut self, fun: Rc<CodeObject>) {
+        self.functions.insert(fun.name.clone(), fun);
+    }
+
+    /**
+     * Calls a native or builtin function with arguments in the order they were passed.
+     */
+    pub(crate) fn call_external(&mut self, fname: &str, args: Vec<Atom>) -> Result<Atom, RuntimeError> {
+        if self.native_functions.contains_key(fname) {
+            if args.len() != self.native_functions[fname].arity {
+                return Err(format!("Function {} expects {} arguments but got {}",
+                                   fname, self.native_functions[fname].arity, args.len()).into());
+            }
+            (self.native_functions[fname].fun)(&args).map_err(RuntimeError::from)
+        }
+        else if self.builtin_functions.contains_key(fname) {
+            try!(self.check_capability(fname));
+            let fun = self.builtin_functions[fname].fun;
+            fun(BuiltinContext { args: args, interp: self })
+        }
+        else {
+            Err(format!("Function {} not found", fname).into())
+        }
+    }
+
+    /**
+     * Whether the named function is a builtin that calls back into rasp code, so that it can run code and collect
+     * garbage before it returns.
+     */
+    pub(crate) fn calls_back(&self, fname: &str) -> bool {
+        if self.functions.contains_key(fname) || self.native_functions.contains_key(fname) {
+            return false;
+        }
+        self.builtin_functions.get(fname).map(|builtin| builtin.calls_back).unwrap_or(false)
+    }
+
+    /**
+     * Calls a function from inside a builtin that's already running, returning its result. The function is named by
+     * a string, the same way a string at the head of a call names one. Unlike `call`, this doesn't start a new run, so
+     * the instruction count and limits carry on from the code that called the builtin.
+     */
+    pub fn call_value(&mut self, function: &Atom, args: Vec<Atom>) -> Result<Atom, RuntimeError> {
+        let fname = match function {
+            &Atom::StrLit(ref name) => name.clone(),
+            other => return Err(format!("Expected a function name, but got `{}'", self.heap.format(other)).into()),
+        };
+        let arity = if let Some(fun) = self.functions.get(&fname) {
+            fun.args.len()
+        }
+        else if let Some(native) = self.native_functions.get(&fname) {
+            native.arity
+        }
+        else if let Some(builtin) = self.builtin_functions.get(&fname[..]) {
+            builtin.args.len()
         }
         else {
-            let mut var_decls = self.decl_stack
-                .last_mut()
-                .unwrap();
-            var_decls.insert(label.to_string(), value);
+            return Err(format!("Function {} not found", fname).into());
+        };
+        if args.len() != arity {
+            return Err(format!("Function {} expects {} arguments but got {}", fname, arity, args.len()).into());
+        }
+        let stack_len = self.stack.len();
+        let decl_len = self.decl_stack.len();
+        self.stack.extend(args);
+        let result = self.handle_call(&fname);
+        let value = if self.stack.len() > stack_len { self.stack.pop() } else { None };
+        self.stack.truncate(stack_len);
+        self.decl_stack.truncate(decl_len);
+        try!(result);
+        match value {
+            Some(value) => Ok(value),
+            None => Err(format!("Function {} did not return a value", fname).into()),
+        }
+    }
+
+    /**
+     * Runs `f` with some values kept alive through any garbage collections that happen in the meantime, for builtins
+     * that hold on to heap values while they call back into rasp code. Anything `pin`ned while `f` runs is let go of
+     * when it returns, too.
+     */
+    pub(crate) fn with_pinned<F, T>(&mut self, values: &[Atom], f: F) -> T
+        where F: FnOnce(&mut Interpreter) -> T
+    {
+        let mark = self.pin_mark();
+        self.pinned.extend(values.iter().filter_map(Atom::heap_ref));
+        let result = f(self);
+        self.unpin_to(mark);
+        result
+    }
+
+    pub(crate) fn pin_mark(&self) -> usize {
+        self.pinned.len()
+    }
+
+    /**
+     * Lets go of everything pinned since `pin_mark` returned `mark`.
+     */
+    pub(crate) fn unpin_to(&mut self, mark: usize) {
+        self.pinned.truncate(mark);
+    }
+
+    /**
+     * Keeps a value alive until the innermost `with_pinned` returns.
+     */
+    pub(crate) fn pin(&mut self, value: &Atom) {
+        if let Some(r) = value.heap_ref() {
+            self.pinned.push(r);
+        }
+    }
+
+    /**
+     * Loads a function from a shared library and registers it under its own name.
+     */
+    pub(crate) fn load_extern(&mut self, decl: &ExternDecl) -> Result<(), String> {
+        if !self.capabilities.allows(Capability::Foreign) {
+            return Err(format!("&extern is disabled in this interpreter, so {} can't be loaded", decl.name));
+        }
+        let library = match self.libraries.get(&decl.library) {
+            Some(lib) => lib.clone(),
+            None => Rc::new(try!(Library::open(&decl.library))),
+        };
+        self.libraries.insert(decl.library.clone(), library.clone());
+        let foreign = try!(ForeignFunction::load(decl, library));
+        let native = NativeFunction {
+            name: decl.name.clone(),
+            arity: foreign.arity(),
+            fun: Box::new(move |args| foreign.call(args)),
+        };
+        self.native_functions.insert(decl.name.clone(), native);
+        Ok(())
+    }
+
+    pub(crate) fn global_var(&self, id: usize) -> Option<&Value> {
+        match self.global_decls.get(id) {
+            Some(&Some(ref value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_global_var(&mut self, id: usize, value: Value) {
+        if id >= self.global_decls.len() {
+            self.global_decls.resize(id + 1, None);
+        }
+        self.global_decls[id] = Some(value);
+    }
+
+    fn load_local(&mut self, slot: usize) -> Result<(), String> {
+        let value = match self.decl_stack.last() {
+            Some(locals) => locals[slot].clone(),
+            None => return Err("Local variable used outside of a function".to_string()),
+        };
+        match value {
+            Some(atom) => {
+                self.stack.push(atom);
+                Ok(())
+            },
+            None => Err("Local variable used before it was defined".to_string()),
+        }
+    }
+
+    fn store_local(&mut self, slot: usize) -> Result<(), String> {
+        let value = try!(self.pop());
+        match self.decl_stack.last_mut() {
+            Some(locals) => {
+                locals[slot] = Some(value);
+                Ok(())
+            },
+            None => Err("Local variable used outside of a function".to_string()),
+        }
+    }
+
+    /**
+     * Pops a value into a local variable that's already been defined, for `&set!`.
+     */
+    fn set_local(&mut self, slot: usize) -> Result<(), String> {
+        let value = try!(self.pop());
+        match self.decl_stack.last_mut() {
+            Some(locals) => match locals[slot] {
+                Some(ref mut local) => {
+                    *local = value;
+                    Ok(())
+                },
+                None => Err("Local variable set before it was defined".to_string()),
+            },
+            None => Err("Local variable used outside of a function".to_string()),
+        }
+    }
+
+    fn load_global(&mut self, id: usize) -> Result<(), String> {
+        let value = match self.global_var(id) {
+            Some(value) => value.to_atom(),
+            None => return Err(format!("Undefined variable: {}", self.symbols.name(id))),
+        };
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /**
+     * Pops a value into a global variable that's already been defined, for `&set!`.
+     */
+    fn set_existing_global(&mut self, id: usize) -> Result<(), String> {
+        let value = try!(self.pop());
+        if self.global_var(id).is_none() {
+            return Err(format!("Undefined variable: {}", self.symbols.name(id)));
         }
+        self.set_global_var(id, Value::from_atom(value));
+        Ok(())
     }
 
-    fn get_var(&self, label: &str) -> Option<&Atom> {
-        let var_decls = self.decl_stack
-            .last()
-            .unwrap();
-        match var_decls.get(label) {
-            Some(ref atom) => Some(atom),
-            None => self.global_decls.get(label)
+    /**
+     * Fails if the named builtin needs a capability that this interpreter doesn't allow.
+     */
+    fn check_capability(&self, fname: &str) -> Result<(), String> {
+        match self.builtin_functions[fname].capability {
+            Some(capability) if !self.capabilities.allows(capability) =>
+                Err(format!("{} is disabled in this interpreter", fname)),
+            _ => Ok(()),
         }
     }
 
     /**
      * Executes a user-defined function based on its name
      */
-    fn user_defined_function(&mut self, fname: &str) -> Result<(), String> {
+    fn user_defined_function(&mut self, fname: &str) -> Result<(), RuntimeError> {
         // Handle user-defined function
-        let code = match self.function_code.get(fname) {
-            Some(code) => code.clone(),
-            None => match self.functions.get(fname) {
-                Some(fun) => {
-                    self.generator = BytecodeGen::new(&fun.args);
-                    for body in &fun.body {
-                        self.generator.visit_expression(&body);
-                    }
-                    self.generator.bytecode.clone()
-                },
-                None => panic!("Function {} not found", fname), 
-            }
+        let function = match self.functions.get(fname) {
+            Some(fun) => fun.clone(),
+            None => panic!("Function {} not found", fname),
         };
-        // Insert the function call if it doesn't already exist in the cache
-        self.function_code
-            .entry(fname.to_string())
-            .or_insert(code.clone());
-        // Add a new var decl list to the decl stack
-        let locals = VarDecls::new();
+        try!(self.check_call_depth(self.decl_stack.len() + 1));
+        // Add a new frame of local slots to the decl stack
+        let locals = vec![None; function.locals.len()];
         self.decl_stack.push(locals);
-        // one clone of a string, and at least one and up to two clones of the code
-        if let Err(err) = self.interpret(&code) {
+        if let Some(mut hook) = self.hook.take() {
+            hook.call(self, &function);
+            self.hook = Some(hook);
+        }
+        let result = self.interpret(&function);
+        if let Some(mut hook) = self.hook.take() {
+            hook.ret(self, &function);
+            self.hook = Some(hook);
+        }
+        if let Err(err) = result {
             Err(err)
         }
         else {
@@ -87,8 +655,9 @@ impl Interpreter {
     /**
      * Executes a builtin function
      */
-    fn builtin_function(&mut self, fname: &str) -> Result<(), String> {
+    fn builtin_function(&mut self, fname: &str) -> Result<(), RuntimeError> {
         // Handle builtin function
+        try!(self.check_capability(fname));
         let argcount = self.builtin_functions
             .get(fname)
             .unwrap()
@@ -98,17 +667,13 @@ impl Interpreter {
         for _ in 0 .. argcount {
             match self.pop() {
                 Ok(atom) => args.push(atom),
-                Err(err) => return Err(err),
+                Err(err) => return Err(err.into()),
             }
         }
-        let locals = self.decl_stack
-            .iter()
-            .last();
-        let context = BuiltinContext { args: args, global_decls: &self.global_decls, var_decls: locals};
-        let func = self.builtin_functions
-            .get(fname)
-            .unwrap();
-        let result = (func.fun)(context);
+        // arguments come off of the stack last first
+        args.reverse();
+        let fun = self.builtin_functions[fname].fun;
+        let result = fun(BuiltinContext { args: args, interp: self });
         match result {
             Ok(atom) => {
                 self.stack.push(atom);
@@ -119,91 +684,174 @@ impl Interpreter {
     }
 
     /**
-     * Handles a call instruction
+     * Executes a native function, either registered by the embedder or loaded with &extern
      */
-    fn handle_call(&mut self, fname: &str) -> Result<(), String> {
-        /*
-        println!("  Function call stack ({}):", fname);
-        for s in &self.stack {
-            println!("      {:?}", s);
-        }
-        println!("  Var decls");
-        for s in &self.decl_stack {
-            println!("    {:?}", s);
+    fn native_function(&mut self, fname: &str) -> Result<(), String> {
+        let argcount = self.native_functions[fname].arity;
+        let mut args = Vec::new();
+        for _ in 0 .. argcount {
+            args.push(try!(self.pop()));
         }
-        */
+        // arguments come off of the stack backwards
+        args.reverse();
+        let result = try!((self.native_functions[fname].fun)(&args));
+        self.stack.push(result);
+        Ok(())
+    }
+
+    /**
+     * Handles a call instruction
+     */
+    fn handle_call(&mut self, fname: &str) -> Result<(), RuntimeError> {
         if self.functions.contains_key(fname) {
             self.user_defined_function(fname)
         }
+        else if self.native_functions.contains_key(fname) {
+            self.native_function(fname).map_err(RuntimeError::from)
+        }
+        else if fname == GC_BUILTIN {
+            let freed = self.collect_garbage();
+            self.stack.push(Atom::IntLit(freed as i64));
+            Ok(())
+        }
         else if self.builtin_functions.contains_key(fname) {
             self.builtin_function(fname)
         }
         else { 
-            Err(format!("Function {} not found", fname))
+            Err(format!("Function {} not found", fname).into())
         }
     }
 
-    pub fn interpret(&mut self, bytecode: &Vec<Bytecode>) -> Result<(), String> {
+    /**
+     * Frees every heap object that can't be reached from the value stack or from a variable, returning how many were
+     * freed. This is safe to do between instructions, since that's the only place live values can be.
+     */
+    pub fn collect_garbage(&mut self) -> usize {
+        self.collect_garbage_with(vec![])
+    }
+
+    /**
+     * Collects garbage with some extra roots, for values that are held somewhere other than the interpreter.
+     */
+    pub(crate) fn collect_garbage_with(&mut self, extra_roots: Vec<HeapRef>) -> usize {
+        let roots = {
+            let locals = self.decl_stack.iter().flat_map(|decls| decls.iter().filter_map(|v| v.as_ref()));
+            let globals = self.global_decls.iter().filter_map(|v| v.as_ref().and_then(Value::heap_ref));
+            self.stack.iter()
+                .chain(locals)
+                .filter_map(Atom::heap_ref)
+                .chain(globals)
+                .chain(self.pinned.iter().cloned())
+                .chain(extra_roots)
+                .collect::<Vec<_>>()
+        };
+        self.heap.collect(roots)
+    }
+
+    /**
+     * Checks whether a call to this function is a call to the `&gc` builtin, which has to be handled specially.
+     */
+    pub(crate) fn is_gc_call(&self, fname: &str) -> bool {
+        fname == GC_BUILTIN && !self.functions.contains_key(fname) && !self.native_functions.contains_key(fname)
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /**
+     * Gets the function and source line that the last runtime error happened in, if it's known. The line is 0 if the
+     * code has no line information. This is cleared by `run` and `call`.
+     */
+    pub fn error_location(&self) -> Option<(&str, usize)> {
+        self.error_location.as_ref().map(|&(ref function, line)| (&function[..], line))
+    }
+
+    /**
+     * Forgets where the last error happened, for when it's been caught and handled.
+     */
+    pub(crate) fn clear_error_location(&mut self) {
+        self.error_location = None;
+    }
+
+    pub fn interpret(&mut self, code_object: &CodeObject) -> Result<(), RuntimeError> {
         let mut index = 0;
+        let result = self.execute(code_object, &mut index);
+        // The innermost function that failed is the one that's interesting, and it gets here first
+        if result.is_err() && self.error_location.is_none() {
+            let line = code_object.lines.get(index).cloned().unwrap_or(0);
+            self.error_location = Some((code_object.name.clone(), line));
+        }
+        result
+    }
+
+    /**
+     * Runs bytecode, leaving `index` at the instruction that was running when an error happened.
+     */
+    fn execute(&mut self, code_object: &CodeObject, index: &mut usize) -> Result<(), RuntimeError> {
+        let ref bytecode = code_object.code;
         if bytecode.len() == 0 {
             return Ok(())
         }
-        /*
-        println!(" ---- BYTECODE DUMP ----");
-        for b in bytecode {
-            println!("{:?}", b);
-        }
-        */
-        // Get the label locations
-        let labels = Interpreter::get_labels(bytecode);
         loop {
-            let ref code = bytecode[index];
-            //println!("{:?}", code);
+            let ref code = bytecode[*index];
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
+            let stack_size = self.stack.len();
+            try!(self.tick(stack_size));
+            if let Some(mut hook) = self.hook.take() {
+                let result = hook.instruction(self, code_object, *index);
+                self.hook = Some(hook);
+                try!(result);
+            }
 
             match code {
                 &Bytecode::Nop => {}, // skip it
-                &Bytecode::Call(ref fname) => if let Err(err) = self.handle_call(fname) {
-                    return Err(err);
-                },
+                &Bytecode::Call(ref fname) => try!(self.handle_call(fname)),
                 &Bytecode::Push(ref v) => self.stack.push(v.clone()),
-                &Bytecode::FunDef(ref func) => { self.functions.insert(func.name.to_string(), func.clone()); },
-                &Bytecode::Label(ref lnum) => { /* ignore */ },
-                &Bytecode::Jump(ref lnum) => {
-                    assert!(labels.contains_key(lnum), "Unknown label found");
-                    index = labels[lnum];
+                &Bytecode::FunDef(ref func) => { self.functions.insert(func.name.clone(), func.clone()); },
+                &Bytecode::Extern(ref decl) => try!(self.load_extern(decl)),
+                &Bytecode::Label(_) => return Err("Bytecode must be linked before it is run".to_string().into()),
+                &Bytecode::Jump(target) => {
+                    *index = target as usize;
+                    if *index >= bytecode.len() {
+                        break;
+                    }
                     continue;
                 },
-                &Bytecode::JumpTrue(ref lnum) => {
-                    assert!(labels.contains_key(lnum), "Unknown label found");
+                &Bytecode::JumpTrue(target) => {
                     // Pop off the top item from the stack
-                    let result = self.pop();
-                    if let Err(err) = result {
-                        return Err(err);
-                    }
-
-                    let atom_val = result.unwrap();
-                    if let Atom::Identifier(_) = atom_val {
-                        return Err("Identifiers are invalid for truth values".to_string());
-                    }
-                    else if atom_val.is_true() {
-                        index = labels[lnum];
-                    }
-                },
-                &Bytecode::Pop(ref into) => if let &Some(ref label) = into {
-                        match self.pop() {
-                            Ok(atom) => self.set_var(label, atom),
-                            Err(err) => return Err(err), 
-                        }
-                    }
-                    else {
-                        match self.pop() {
-                            Ok(_) => {},
-                            Err(err) => return Err(err), 
+                    let atom_val = try!(self.pop());
+                    if atom_val.is_true() {
+                        *index = target as usize;
+                        if *index >= bytecode.len() {
+                            break;
                         }
+                        continue;
                     }
+                },
+                &Bytecode::Pop => { try!(self.pop()); },
+                &Bytecode::LoadLocal(slot) => try!(self.load_local(slot)),
+                &Bytecode::StoreLocal(slot) => try!(self.store_local(slot)),
+                &Bytecode::LoadGlobal(id) => try!(self.load_global(id)),
+                &Bytecode::StoreGlobal(id) => {
+                    let atom = try!(self.pop());
+                    self.set_global_var(id, Value::from_atom(atom));
+                },
+                &Bytecode::SetLocal(slot) => try!(self.set_local(slot)),
+                &Bytecode::SetGlobal(id) => try!(self.set_existing_global(id)),
             }
-            index += 1;
-            if index >= bytecode.len() {
+            *index += 1;
+            if *index >= bytecode.len() {
                 break;
             }
         }
@@ -220,17 +868,4 @@ impl Interpreter {
                 .unwrap())
         }
     }
-
-    fn get_labels(bytecode: &Vec<Bytecode>) -> HashMap<u64, usize> {
-        let mut the_map = HashMap::new();
-        let mut index = 0usize;
-        for b in bytecode {
-            match b {
-                &Bytecode::Label(ref lnum) => { the_map.insert(*lnum, index); },
-                _ => {  }
-            }
-            index += 1;
-        }
-        the_map
-    }
 }
\ No newline at end of file
@@ -0,0 +1,94 @@
+use std::fmt;
+
+/**
+ * Limits on how much a script can do before it's stopped. `None` means unlimited.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    pub max_instructions: Option<u64>, /* Instructions executed by one call to `run` or `call` */
+    pub max_call_depth: Option<usize>, /* Nested calls to user-defined functions */
+    pub max_stack: Option<usize>, /* Values on the value stack (or registers, for the register VM) */
+    pub max_heap_bytes: Option<usize>, /* Approximate size of the heap, after collecting garbage */
+}
+
+/**
+ * The default call depth limit. Every rasp call is a few native calls in the interpreter, so this keeps deep recursion
+ * from overflowing the host's stack.
+ */
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+impl Limits {
+    /**
+     * No limits at all. Infinite recursion will crash the host with this.
+     */
+    pub fn unlimited() -> Limits {
+        Limits {
+            max_instructions: None,
+            max_call_depth: None,
+            max_stack: None,
+            max_heap_bytes: None,
+        }
+    }
+}
+
+impl Default for Limits {
+    /**
+     * Only the call depth is limited by default.
+     */
+    fn default() -> Limits {
+        Limits { max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH), .. Limits::unlimited() }
+    }
+}
+
+/**
+ * A limit that a script ran into, along with the limit's value.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LimitExceeded {
+    Instructions(u64),
+    CallDepth(usize),
+    Stack(usize),
+    HeapBytes(usize),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &LimitExceeded::Instructions(max) => write!(f, "Instruction limit of {} exceeded", max),
+            &LimitExceeded::CallDepth(max) => write!(f, "Call depth limit of {} exceeded", max),
+            &LimitExceeded::Stack(max) => write!(f, "Stack size limit of {} exceeded", max),
+            &LimitExceeded::HeapBytes(max) => write!(f, "Heap size limit of {} bytes exceeded", max),
+        }
+    }
+}
+
+/**
+ * Everything that can stop a script while it's running. Limits get their own variant so that embedders can tell a
+ * script that was stopped apart from one that failed.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeError {
+    Error(String),
+    Limit(LimitExceeded),
+}
+
+impl From<String> for RuntimeError {
+    fn from(err: String) -> RuntimeError {
+        RuntimeError::Error(err)
+    }
+}
+
+impl From<LimitExceeded> for RuntimeError {
+    fn from(limit: LimitExceeded) -> RuntimeError {
+        RuntimeError::Limit(limit)
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &RuntimeError::Error(ref err) => write!(f, "{}", err),
+            &RuntimeError::Limit(ref limit) => write!(f, "{}", limit),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use internal::Bytecode;
+
+/**
+ * Resolves labels to absolute instruction offsets.
+ *
+ * `BytecodeGen` emits `Label` instructions and jumps that refer to them by number. Linking removes the labels and
+ * rewrites every `Jump` and `JumpTrue` so that its operand is the index of the instruction to jump to, so the
+ * interpreter never has to look labels up while running. `lines` holds the source line of each instruction, and is
+ * returned with the labels' lines taken out so that it still lines up with the code.
+ */
+pub fn link(bytecode: &Vec<Bytecode>, lines: &Vec<usize>) -> Result<(Vec<Bytecode>, Vec<usize>), String> {
+    assert_eq!(bytecode.len(), lines.len());
+    // First pass: figure out where each label will end up once the labels themselves are gone
+    let mut labels = HashMap::new();
+    let mut offset = 0u64;
+    for b in bytecode {
+        match b {
+            &Bytecode::Label(lnum) => if labels.insert(lnum, offset).is_some() {
+                return Err(format!("Label {} was defined more than once", lnum));
+            },
+            _ => offset += 1,
+        }
+    }
+
+    // Second pass: drop the labels and point the jumps at their targets
+    let mut linked = Vec::with_capacity(offset as usize);
+    let mut linked_lines = Vec::with_capacity(offset as usize);
+    for (b, line) in bytecode.iter().zip(lines) {
+        let code = match b {
+            &Bytecode::Label(_) => continue,
+            &Bytecode::Jump(lnum) => match labels.get(&lnum) {
+                Some(target) => Bytecode::Jump(*target),
+                None => return Err(format!("Jump to unknown label {}", lnum)),
+            },
+            &Bytecode::JumpTrue(lnum) => match labels.get(&lnum) {
+                Some(target) => Bytecode::JumpTrue(*target),
+                None => return Err(format!("Jump to unknown label {}", lnum)),
+            },
+            _ => b.clone(),
+        };
+        linked.push(code);
+        linked_lines.push(*line);
+    }
+    Ok((linked, linked_lines))
+}
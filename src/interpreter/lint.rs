@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::ops::Deref;
+use ast::visitor::MutVisitor;
+use ast::{Atom, Expression, IfElse, Span};
+
+/**
+ * Looks for common mistakes in parsed code: calls to functions that don't exist or with the wrong number of
+ * arguments, unused parameters, functions defined twice, `&if` branches that can never run and functions that hide a
+ * builtin.
+ *
+ * The linter only knows about the functions it's told about, plus the ones defined by the code it's checking. It
+ * doesn't stop anything from compiling; it only produces warnings.
+ */
+pub struct Linter {
+    functions: HashMap<String, usize>, /* Functions that are already defined, by arity */
+    builtins: HashMap<String, usize>,
+    defined: HashMap<String, usize>, /* Functions defined by the code being linted */
+    used: Option<HashSet<String>>, /* Variables used by the function being linted, if there is one */
+    warnings: Vec<(Span, String)>, /* Warnings with the code they're about */
+}
+
+impl Linter {
+    pub fn new() -> Linter {
+        Linter {
+            functions: HashMap::new(),
+            builtins: HashMap::new(),
+            defined: HashMap::new(),
+            used: None,
+            warnings: vec![],
+        }
+    }
+
+    /**
+     * Tells the linter about a function that's defined outside of the code it's checking.
+     */
+    pub fn define_function(&mut self, name: &str, arity: usize) {
+        self.functions.insert(name.to_string(), arity);
+    }
+
+    pub fn define_builtin(&mut self, name: &str, arity: usize) {
+        self.builtins.insert(name.to_string(), arity);
+    }
+
+    /**
+     * Checks a compile unit, returning its warnings in source order. Each one starts with the place it's about, as
+     * `source_name:line:column`.
+     */
+    pub fn lint(&mut self, exprs: &[Expression], source: &str, source_name: &str) -> Vec<String> {
+        self.check(exprs)
+            .into_iter()
+            .map(|(span, msg)| {
+                let (line, col) = position(source, span.start);
+                format!("{}:{}:{}: {}", source_name, line, col, msg)
+            })
+            .collect()
+    }
+
+    /**
+     * Checks a compile unit, returning its warnings in source order along with the spans they're about.
+     */
+    pub fn check(&mut self, exprs: &[Expression]) -> Vec<(Span, String)> {
+        self.defined.clear();
+        self.warnings.clear();
+        // Functions can be called before they're defined, so find them all first
+        for expr in exprs {
+            let (name, arity, span) = match expr {
+                &Expression::FunDef(ref fun) => (&fun.name, fun.args.len(), fun.span),
+                &Expression::Extern(ref decl) => (&decl.name, decl.args.len(), decl.span),
+                _ => continue,
+            };
+            if self.defined.contains_key(name) {
+                self.warn(span, format!("Function {} is defined more than once", name));
+            }
+            if self.builtins.contains_key(name) {
+                self.warn(span, format!("Function {} hides the builtin with the same name", name));
+            }
+            self.defined.insert(name.clone(), arity);
+        }
+        for expr in exprs {
+            self.visit_expression(expr);
+        }
+
+        let mut warnings: Vec<_> = self.warnings.drain(..).collect();
+        warnings.sort_by_key(|&(span, _)| span.start);
+        warnings
+    }
+
+    fn warn(&mut self, span: Span, msg: String) {
+        self.warnings.push((span, msg));
+    }
+
+    /**
+     * Gets the number of arguments that a function takes, if it's a function at all.
+     */
+    fn arity(&self, name: &str) -> Option<usize> {
+        // User-defined functions are found before builtins, so they win
+        self.defined.get(name)
+            .or_else(|| self.functions.get(name))
+            .or_else(|| self.builtins.get(name))
+            .cloned()
+    }
+
+    fn check_call(&mut self, name: &str, argc: usize, span: Span) {
+        match self.arity(name) {
+            Some(arity) if arity != argc => {
+                let plural = if arity == 1 { "" } else { "s" };
+                self.warn(span, format!("{} takes {} argument{}, but is called with {}", name, arity, plural, argc));
+            },
+            Some(_) => {},
+            None => self.warn(span, format!("Call to undefined function {}", name)),
+        }
+    }
+
+    fn visit_children(&mut self, children: &Vec<Expression>, span: Span) {
+        let args = match &children[0] {
+            &Expression::Atom(Atom::Identifier(ref name), _) => {
+                self.check_call(name, children.len() - 1, span);
+                &children[1 ..]
+            },
+            &Expression::Atom(Atom::StrLit(ref name), _) if children.len() > 1 => {
+                self.check_call(name, children.len() - 1, span);
+                &children[1 ..]
+            },
+            _ => &children[..],
+        };
+        for arg in args {
+            self.visit_expression(arg);
+        }
+    }
+}
+
+/**
+ * Finds the line and column, both starting from 1, of an offset into the source text.
+ */
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[.. offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start ..].chars().count() + 1)
+}
+
+impl MutVisitor<()> for Linter {
+    fn visit_expression(&mut self, expr: &Expression) {
+        match expr {
+            &Expression::Atom(ref a, _) => self.visit_atom(a),
+            &Expression::Children(ref c, span) => self.visit_children(c, span),
+            &Expression::Unit(_) => {},
+            &Expression::Dict(ref entries, _) => {
+                for &(ref key, ref value) in entries {
+                    self.visit_expression(key);
+                    self.visit_expression(value);
+                }
+            },
+            &Expression::Vector(ref items, _) => {
+                for item in items {
+                    self.visit_expression(item);
+                }
+            },
+            &Expression::FunDef(ref fun) => {
+                // Functions can't be defined inside other functions, but the compiler is the one that says so
+                let outer = mem::replace(&mut self.used, Some(HashSet::new()));
+                for body in &fun.body {
+                    self.visit_expression(body);
+                }
+                let used = mem::replace(&mut self.used, outer).unwrap();
+                for arg in &fun.args {
+                    if !used.contains(arg) && !arg.starts_with('_') {
+                        self.warn(fun.span, format!("Parameter {} of function {} is never used", arg, fun.name));
+                    }
+                }
+            },
+            &Expression::TestDef(ref test) => {
+                for body in &test.body {
+                    self.visit_expression(body);
+                }
+            },
+            &Expression::IfElse(ref ifelse) => {
+                let span = ifelse.span;
+                if let Expression::Atom(ref condition, _) = ifelse.condition {
+                    let always = match condition {
+                        &Atom::Identifier(_) | &Atom::Ref(_) => None,
+                        literal => Some(literal.is_true()),
+                    };
+                    match (always, &ifelse.if_false) {
+                        (Some(false), _) => self.warn(span, "The condition is always false, so the first branch \
+                                                             can never run".to_string()),
+                        (Some(true), &Some(_)) => self.warn(span, "The condition is always true, so the &else \
+                                                                   branch can never run".to_string()),
+                        _ => {},
+                    }
+                }
+                self.visit_ifelse(ifelse.deref());
+            },
+            &Expression::Extern(_) => {},
+            &Expression::VarDef(ref def) | &Expression::VarSet(ref def) => self.visit_expression(&def.value),
+        }
+    }
+
+    fn visit_atom(&mut self, atom: &Atom) {
+        if let &Atom::Identifier(ref name) = atom {
+            if let Some(ref mut used) = self.used {
+                used.insert(name.clone());
+            }
+        }
+    }
+
+    fn visit_ifelse(&mut self, ifelse: &IfElse) {
+        self.visit_expression(&ifelse.condition);
+        self.visit_expression(&ifelse.if_true);
+        if let Some(ref if_false) = ifelse.if_false {
+            self.visit_expression(if_false);
+        }
+    }
+}
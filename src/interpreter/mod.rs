@@ -2,11 +2,34 @@
 //pub use interpreter::collector::FunctionCollector;
 
 mod bytecode_gen;
+mod eval;
+mod hook;
 mod interpreter;
+mod limits;
+mod lint;
+mod linker;
+mod optimizer;
+mod register_code;
+mod register_vm;
+mod sequence;
+mod symbols;
 pub use interpreter::bytecode_gen::BytecodeGen;
+pub use interpreter::eval::eval_builtins;
+pub use interpreter::hook::Hook;
 pub use interpreter::interpreter::Interpreter;
+pub use interpreter::limits::*;
+pub use interpreter::lint::Linter;
+pub use interpreter::linker::link;
+pub use interpreter::optimizer::Optimizer;
+pub use interpreter::register_code::{CallTarget, Instr, Reg, RegisterCode, Value};
+pub use interpreter::register_vm::{Backend, RegisterVm};
+pub use interpreter::sequence::sequence_builtins;
+pub use interpreter::symbols::SymbolTable;
 
 use ast::Atom;
-use std::collections::HashMap;
 
-pub type VarDecls = HashMap<String, Atom>;
\ No newline at end of file
+/**
+ * Variables, indexed by slot number. Locals get their slots from the function's code object, and globals get theirs
+ * from the interpreter's symbol table. A slot is `None` until its variable is defined.
+ */
+pub type VarDecls = Vec<Option<Atom>>;
\ No newline at end of file
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet};
+use ast::Atom;
+use internal::Bytecode;
+
+/**
+ * An optimization pipeline that runs over unlinked bytecode, before labels are resolved.
+ *
+ * Every pass has to leave the behavior of the program alone; the optimized and unoptimized programs should print the
+ * same things and fail in the same ways.
+ */
+pub struct Optimizer {
+    /* Functions that may be defined by the user, which means that builtins with the same names can't be folded */
+    shadowed: HashSet<String>,
+}
+
+/**
+ * An instruction along with the source line it came from.
+ */
+type Instruction = (Bytecode, usize);
+
+impl Optimizer {
+    pub fn new(shadowed: HashSet<String>) -> Optimizer {
+        Optimizer { shadowed: shadowed }
+    }
+
+    /**
+     * Marks a function name as possibly user-defined.
+     */
+    pub fn shadow(&mut self, name: &str) {
+        self.shadowed.insert(name.to_string());
+    }
+
+    /**
+     * Optimizes some bytecode and its line table, running every pass until none of them change anything. This fails if
+     * the code jumps to a label that it doesn't define.
+     */
+    pub fn optimize(&self, bytecode: &Vec<Bytecode>, lines: &Vec<usize>)
+        -> Result<(Vec<Bytecode>, Vec<usize>), String>
+    {
+        let mut code: Vec<Instruction> = bytecode.iter()
+            .cloned()
+            .zip(lines.iter().cloned())
+            .collect();
+        loop {
+            let mut changed = false;
+            changed |= self.remove_nops(&mut code);
+            changed |= self.fold_constants(&mut code);
+            changed |= try!(self.thread_jumps(&mut code));
+            changed |= self.remove_dead_code(&mut code);
+            if !changed {
+                break;
+            }
+        }
+        Ok(code.into_iter().unzip())
+    }
+
+    /**
+     * `Nop`s don't do anything, so they can go.
+     */
+    fn remove_nops(&self, code: &mut Vec<Instruction>) -> bool {
+        let before = code.len();
+        code.retain(|&(ref b, _)| match b { &Bytecode::Nop => false, _ => true });
+        code.len() != before
+    }
+
+    /**
+     * Evaluates a pure builtin on constant arguments, if it's safe to do so.
+     */
+    fn fold_call(&self, fname: &str, lhs: &Atom, rhs: &Atom) -> Option<Atom> {
+        if self.shadowed.contains(fname) {
+            return None;
+        }
+        let result = match fname {
+            "==" => Ok(lhs.equals(rhs)),
+            "-" => lhs.minus(rhs),
+            "*" => lhs.times(rhs),
+            _ => return None,
+        };
+        // Anything that fails gets left alone, so that it fails at runtime like it would have without optimizing
+        result.ok()
+    }
+
+    /**
+     * Replaces calls to pure builtins on literals with their results, and conditional jumps on literals with either an
+     * unconditional jump or nothing at all.
+     */
+    fn fold_constants(&self, code: &mut Vec<Instruction>) -> bool {
+        let mut changed = false;
+        let mut index = 0;
+        while index < code.len() {
+            if index + 2 < code.len() {
+                let folded = match (&code[index].0, &code[index + 1].0, &code[index + 2].0) {
+                    (&Bytecode::Push(ref lhs), &Bytecode::Push(ref rhs), &Bytecode::Call(ref fname)) =>
+                        self.fold_call(fname, lhs, rhs),
+                    _ => None,
+                };
+                if let Some(atom) = folded {
+                    let line = code[index + 2].1;
+                    code.splice(index .. index + 3, vec![(Bytecode::Push(atom), line)]);
+                    changed = true;
+                    // The result might be foldable with what came before it
+                    index = if index >= 2 { index - 2 } else { 0 };
+                    continue;
+                }
+            }
+            if index + 1 < code.len() {
+                let jump = match (&code[index].0, &code[index + 1].0) {
+                    (&Bytecode::Push(ref atom), &Bytecode::JumpTrue(lnum)) => match atom {
+                        &Atom::Identifier(_) => None,
+                        _ => Some((atom.is_true(), lnum)),
+                    },
+                    _ => None,
+                };
+                if let Some((taken, lnum)) = jump {
+                    let line = code[index + 1].1;
+                    let replacement = if taken { vec![(Bytecode::Jump(lnum), line)] } else { vec![] };
+                    code.splice(index .. index + 2, replacement);
+                    changed = true;
+                    continue;
+                }
+            }
+            index += 1;
+        }
+        changed
+    }
+
+    /**
+     * Points jumps that land on another jump at that jump's target, and removes jumps to the very next instruction.
+     */
+    fn thread_jumps(&self, code: &mut Vec<Instruction>) -> Result<bool, String> {
+        // Where each label goes to, skipping over other labels
+        let mut label_index = HashMap::new();
+        for (index, &(ref b, _)) in code.iter().enumerate() {
+            if let &Bytecode::Label(lnum) = b {
+                label_index.insert(lnum, index);
+            }
+        }
+        let landing = |lnum: u64| -> Result<Option<&Bytecode>, String> {
+            let mut index = match label_index.get(&lnum) {
+                Some(&index) => index,
+                None => return Err(format!("Jump to unknown label {}", lnum)),
+            };
+            while index < code.len() {
+                match code[index].0 {
+                    Bytecode::Label(_) => index += 1,
+                    ref b => return Ok(Some(b)),
+                }
+            }
+            Ok(None)
+        };
+
+        // Follow chains of unconditional jumps, stopping if they loop
+        let mut targets = HashMap::new();
+        for &lnum in label_index.keys() {
+            let mut target = lnum;
+            let mut seen = HashSet::new();
+            while seen.insert(target) {
+                match try!(landing(target)) {
+                    Some(&Bytecode::Jump(next)) => target = next,
+                    _ => break,
+                }
+            }
+            targets.insert(lnum, target);
+        }
+
+        let mut changed = false;
+        for &mut (ref mut b, _) in code.iter_mut() {
+            let lnum = match b {
+                &mut Bytecode::Jump(lnum) | &mut Bytecode::JumpTrue(lnum) => lnum,
+                _ => continue,
+            };
+            let target = match targets.get(&lnum) {
+                Some(&target) => target,
+                None => return Err(format!("Jump to unknown label {}", lnum)),
+            };
+            if target == lnum {
+                continue;
+            }
+            *b = match b {
+                &mut Bytecode::JumpTrue(_) => Bytecode::JumpTrue(target),
+                _ => Bytecode::Jump(target),
+            };
+            changed = true;
+        }
+
+        // A jump to a label that comes right after it (with only labels in between) does nothing
+        let mut index = 0;
+        while index < code.len() {
+            let next_label = match code[index].0 {
+                Bytecode::Jump(lnum) => {
+                    let mut next = index + 1;
+                    let mut found = false;
+                    while next < code.len() {
+                        match code[next].0 {
+                            Bytecode::Label(l) if l == lnum => { found = true; break; },
+                            Bytecode::Label(_) => next += 1,
+                            _ => break,
+                        }
+                    }
+                    found
+                },
+                _ => false,
+            };
+            if next_label {
+                code.remove(index);
+                changed = true;
+            }
+            else {
+                index += 1;
+            }
+        }
+        Ok(changed)
+    }
+
+    /**
+     * Removes code that can never run, because it comes after an unconditional jump and before any label, and labels
+     * that nothing jumps to.
+     */
+    fn remove_dead_code(&self, code: &mut Vec<Instruction>) -> bool {
+        let mut used = HashSet::new();
+        for &(ref b, _) in code.iter() {
+            match b {
+                &Bytecode::Jump(lnum) | &Bytecode::JumpTrue(lnum) => { used.insert(lnum); },
+                _ => {},
+            }
+        }
+
+        let before = code.len();
+        let mut reachable = true;
+        let mut optimized = Vec::with_capacity(code.len());
+        for (b, line) in code.drain(..) {
+            match b {
+                Bytecode::Label(lnum) => if used.contains(&lnum) {
+                    reachable = true;
+                    optimized.push((b, line));
+                },
+                Bytecode::Jump(_) => if reachable {
+                    optimized.push((b, line));
+                    reachable = false;
+                },
+                _ => if reachable {
+                    optimized.push((b, line));
+                },
+            }
+        }
+        *code = optimized;
+        code.len() != before
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use ast::{Atom, ExternDecl};
+use internal::{Bytecode, CodeObject, Heap, HeapRef};
+
+/**
+ * A value in the register VM. Unlike atoms, strings are reference-counted, so moving one between registers or passing
+ * it to a function doesn't copy it.
+ */
+#[derive(Clone, Debug)]
+pub enum Value {
+    Int(i64),
+    Double(f64),
+    Bool(bool),
+    Str(Rc<String>),
+    Symbol(Rc<String>), /* A name that `&read` gave back as data */
+    Ref(HeapRef),
+}
+
+impl Value {
+    pub fn from_atom(atom: Atom) -> Value {
+        match atom {
+            Atom::IntLit(i) => Value::Int(i),
+            Atom::DubLit(d) => Value::Double(d),
+            Atom::BoolLit(b) => Value::Bool(b),
+            Atom::StrLit(s) => Value::Str(Rc::new(s)),
+            Atom::Identifier(i) => Value::Symbol(Rc::new(i)),
+            Atom::Ref(r) => Value::Ref(r),
+        }
+    }
+
+    pub fn heap_ref(&self) -> Option<HeapRef> {
+        match self {
+            &Value::Ref(r) => Some(r),
+            _ => None,
+        }
+    }
+
+    /**
+     * Converts the value to an atom, for handing to builtins, native functions and the stack machine. This is the
+     * only place that strings get copied.
+     */
+    pub fn to_atom(&self) -> Atom {
+        match self {
+            &Value::Int(i) => Atom::IntLit(i),
+            &Value::Double(d) => Atom::DubLit(d),
+            &Value::Bool(b) => Atom::BoolLit(b),
+            &Value::Str(ref s) => Atom::StrLit(s.as_ref().clone()),
+            &Value::Symbol(ref s) => Atom::Identifier(s.as_ref().clone()),
+            &Value::Ref(r) => Atom::Ref(r),
+        }
+    }
+
+    pub fn is_true(&self) -> bool {
+        match self {
+            &Value::Int(i) => i != 0,
+            &Value::Double(d) => d != 0.0,
+            &Value::Bool(b) => b,
+            &Value::Str(ref s) => s.len() > 0,
+            &Value::Symbol(_) => true,
+            &Value::Ref(_) => true,
+        }
+    }
+
+    /**
+     * The same as the `==` builtin, but strings are compared without being copied first.
+     */
+    pub fn equals(&self, other: &Value, heap: &Heap) -> Result<Value, String> {
+        match (self, other) {
+            (&Value::Str(ref lhs), &Value::Str(ref rhs)) => Ok(Value::Bool(Rc::ptr_eq(lhs, rhs) || lhs == rhs)),
+            (&Value::Str(_), _) | (_, &Value::Str(_)) => Ok(Value::Bool(false)),
+            (lhs, rhs) => heap.equals(&lhs.to_atom(), &rhs.to_atom()).map(Value::Bool),
+        }
+    }
+
+    pub fn minus(&self, other: &Value) -> Result<Value, String> {
+        self.to_scalar()
+            .and_then(|lhs| other.to_scalar().map(|rhs| (lhs, rhs)))
+            .ok_or_else(|| "Invalid operands for -".to_string())
+            .and_then(|(lhs, rhs)| lhs.minus(&rhs))
+            .map(Value::from_scalar)
+    }
+
+    pub fn times(&self, other: &Value) -> Result<Value, String> {
+        self.to_scalar()
+            .and_then(|lhs| other.to_scalar().map(|rhs| (lhs, rhs)))
+            .ok_or_else(|| "Invalid operands for *".to_string())
+            .and_then(|(lhs, rhs)| lhs.times(&rhs))
+            .map(Value::from_scalar)
+    }
+
+    /**
+     * Converts a number or bool to an atom, which doesn't need to allocate.
+     */
+    fn to_scalar(&self) -> Option<Atom> {
+        match self {
+            &Value::Str(_) | &Value::Symbol(_) | &Value::Ref(_) => None,
+            _ => Some(self.to_atom()),
+        }
+    }
+
+    fn from_scalar(atom: Atom) -> Value {
+        match atom {
+            Atom::IntLit(i) => Value::Int(i),
+            Atom::DubLit(d) => Value::Double(d),
+            Atom::BoolLit(b) => Value::Bool(b),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/**
+ * A register number, relative to the start of the current frame. A frame's local variables get the first registers,
+ * and the temporaries that the stack machine would have pushed get the rest.
+ */
+pub type Reg = usize;
+
+/**
+ * An instruction for the register VM. Jump targets are instruction indexes.
+ */
+#[derive(Clone, Debug)]
+pub enum Instr {
+    /** Loads a constant from the constant pool */
+    Const(Reg, usize),
+    Move(Reg, Reg),
+    /** Marks a range of registers as holding no value, for branches that leave nothing behind */
+    Clear(Reg, Reg),
+    LoadGlobal(Reg, usize),
+    StoreGlobal(usize, Reg),
+    /** Moves a value into a local variable, failing if it hasn't been defined yet */
+    SetLocal(Reg, Reg),
+    /** Stores a global variable, failing if it hasn't been defined yet */
+    SetGlobal(usize, Reg),
+    Sub(Reg, Reg, Reg),
+    Mul(Reg, Reg, Reg),
+    Eq(Reg, Reg, Reg),
+    /** Calls the named function with arguments in consecutive registers, putting the result in the first one */
+    Call(usize, Reg, usize),
+    FunDef(Rc<CodeObject>),
+    Extern(ExternDecl),
+    Jump(usize),
+    JumpTrue(Reg, usize),
+    JumpFalse(Reg, usize),
+    Fail(String),
+    Return(Option<Reg>),
+}
+
+/**
+ * A code object translated for the register VM.
+ */
+#[derive(Debug)]
+pub struct RegisterCode {
+    pub name: String,
+    pub arity: usize,
+    pub locals: usize, /* The number of registers used by local variables */
+    pub registers: usize, /* The total number of registers in a frame */
+    pub constants: Vec<Value>,
+    pub names: Vec<String>, /* Names of called functions */
+    pub code: Vec<Instr>,
+}
+
+/**
+ * What a call instruction refers to when it's translated. The builtin arithmetic gets its own instructions, as long as
+ * it hasn't been replaced by a user-defined function.
+ */
+#[derive(Clone, Copy, Debug)]
+pub enum CallTarget {
+    Sub,
+    Mul,
+    Eq,
+    Function(usize), /* Anything else, with its arity */
+}
+
+/**
+ * Translates stack bytecode to register code. The stack depth at each instruction is known ahead of time, so every
+ * stack slot becomes a register. Where branches leave different numbers of values behind, the shorter branch clears
+ * the extra registers so that both agree.
+ *
+ * `resolve` looks up what a called function is, which decides how many values the call consumes.
+ */
+pub fn translate<F>(code: &CodeObject, resolve: F) -> Result<RegisterCode, String>
+    where F: Fn(&str) -> Option<CallTarget>
+{
+    let ref bytecode = code.code;
+    let len = bytecode.len();
+    let base = code.locals.len();
+
+    // The number of values each instruction pops and pushes, or an error if it can't run
+    let mut effects = Vec::with_capacity(len);
+    for b in bytecode {
+        let effect = match b {
+            &Bytecode::Nop | &Bytecode::FunDef(_) | &Bytecode::Extern(_) | &Bytecode::Jump(_) => Ok((0, 0)),
+            &Bytecode::Push(_) | &Bytecode::LoadLocal(_) | &Bytecode::LoadGlobal(_) => Ok((0, 1)),
+            &Bytecode::Pop | &Bytecode::StoreLocal(_) | &Bytecode::StoreGlobal(_) | &Bytecode::JumpTrue(_) => Ok((1, 0)),
+            &Bytecode::SetLocal(_) | &Bytecode::SetGlobal(_) => Ok((1, 0)),
+            &Bytecode::Call(ref fname) => match resolve(fname) {
+                Some(CallTarget::Function(arity)) => Ok((arity, 1)),
+                Some(_) => Ok((2, 1)),
+                None => Err(format!("Function {} not found", fname)),
+            },
+            &Bytecode::Label(_) => return Err("Bytecode must be linked before it is run".to_string()),
+        };
+        effects.push(effect);
+    }
+    // The compiler only ever jumps forward, and that's all this handles
+    for (index, b) in bytecode.iter().enumerate() {
+        match b {
+            &Bytecode::Jump(target) | &Bytecode::JumpTrue(target) if target as usize <= index =>
+                return Err(format!("Can't translate the backward jump at {} in {}", index, code.name)),
+            _ => {},
+        }
+    }
+
+    // Work out the stack depth coming into each instruction. Jumps only go forward, so one pass is enough.
+    let mut depth_in: Vec<Option<usize>> = vec![None; len + 1];
+    depth_in[0] = Some(code.args.len());
+    let mut max_depth = code.args.len();
+    for index in 0 .. len {
+        let depth = match depth_in[index] {
+            Some(depth) => depth,
+            None => continue,
+        };
+        let out = match effects[index] {
+            Ok((pops, pushes)) if pops <= depth => depth - pops + pushes,
+            _ => continue, // this fails at runtime, so nothing after it is reached from here
+        };
+        if out > max_depth {
+            max_depth = out;
+        }
+        let (falls_through, target) = match bytecode[index] {
+            Bytecode::Jump(target) => (false, Some(target as usize)),
+            Bytecode::JumpTrue(target) => (true, Some(target as usize)),
+            _ => (true, None),
+        };
+        for successor in target.into_iter().chain(if falls_through { Some(index + 1) } else { None }) {
+            let successor = if successor > len { len } else { successor };
+            if depth_in[successor].map(|d| d < out).unwrap_or(true) {
+                depth_in[successor] = Some(out);
+            }
+        }
+    }
+
+    let mut constants = vec![];
+    let mut names: Vec<String> = vec![];
+    let mut name_index = HashMap::new();
+    let mut out = vec![];
+    let mut new_index = vec![0; len + 1];
+    let mut fixups = vec![]; /* Instructions whose jump targets are still stack bytecode indexes */
+
+    // Clears the registers between the depth an edge leaves and the depth its target expects
+    let pad = |out: &mut Vec<Instr>, from: usize, target: usize| {
+        let target = if target > len { len } else { target };
+        if let Some(to) = depth_in[target] {
+            if from < to {
+                out.push(Instr::Clear(base + from, base + to));
+            }
+        }
+    };
+
+    for index in 0 .. len {
+        new_index[index] = out.len();
+        let depth = match depth_in[index] {
+            Some(depth) => depth,
+            None => continue,
+        };
+        let (pops, pushes) = match effects[index] {
+            Ok((pops, _)) if pops > depth => {
+                out.push(Instr::Fail("Stack was empty but attempted to pop an item off".to_string()));
+                continue;
+            },
+            Ok(effect) => effect,
+            Err(ref err) => {
+                out.push(Instr::Fail(err.clone()));
+                continue;
+            },
+        };
+        let top = base + depth;
+        match bytecode[index] {
+            Bytecode::Nop | Bytecode::Pop | Bytecode::Label(_) => {},
+            Bytecode::Push(ref atom) => {
+                constants.push(Value::from_atom(atom.clone()));
+                out.push(Instr::Const(top, constants.len() - 1));
+            },
+            Bytecode::LoadLocal(slot) => out.push(Instr::Move(top, slot)),
+            Bytecode::StoreLocal(slot) => out.push(Instr::Move(slot, top - 1)),
+            Bytecode::LoadGlobal(id) => out.push(Instr::LoadGlobal(top, id)),
+            Bytecode::StoreGlobal(id) => out.push(Instr::StoreGlobal(id, top - 1)),
+            Bytecode::SetLocal(slot) => out.push(Instr::SetLocal(slot, top - 1)),
+            Bytecode::SetGlobal(id) => out.push(Instr::SetGlobal(id, top - 1)),
+            Bytecode::FunDef(ref fun) => out.push(Instr::FunDef(fun.clone())),
+            Bytecode::Extern(ref decl) => out.push(Instr::Extern(decl.clone())),
+            Bytecode::Call(ref fname) => {
+                let first = top - pops;
+                let instr = match resolve(fname) {
+                    Some(CallTarget::Sub) => Instr::Sub(first, first, first + 1),
+                    Some(CallTarget::Mul) => Instr::Mul(first, first, first + 1),
+                    Some(CallTarget::Eq) => Instr::Eq(first, first, first + 1),
+                    _ => {
+                        let id = *name_index.entry(fname.clone()).or_insert_with(|| {
+                            names.push(fname.clone());
+                            names.len() - 1
+                        });
+                        Instr::Call(id, first, pops)
+                    },
+                };
+                out.push(instr);
+            },
+            Bytecode::Jump(target) => {
+                pad(&mut out, depth, target as usize);
+                fixups.push(out.len());
+                out.push(Instr::Jump(target as usize));
+                continue;
+            },
+            Bytecode::JumpTrue(target) => {
+                let cond = top - 1;
+                let needs_pad = depth_in[if target as usize > len { len } else { target as usize }]
+                    .map(|to| depth - 1 < to)
+                    .unwrap_or(false);
+                if needs_pad {
+                    // Skip over the padding when the jump isn't taken
+                    let skip = out.len() + 3;
+                    out.push(Instr::JumpFalse(cond, skip));
+                    pad(&mut out, depth - 1, target as usize);
+                    fixups.push(out.len());
+                    out.push(Instr::Jump(target as usize));
+                }
+                else {
+                    fixups.push(out.len());
+                    out.push(Instr::JumpTrue(cond, target as usize));
+                }
+            },
+        }
+        pad(&mut out, depth - pops + pushes, index + 1);
+    }
+
+    new_index[len] = out.len();
+    let ret = match depth_in[len] {
+        Some(depth) if depth > 0 => Some(base + depth - 1),
+        _ => None,
+    };
+    out.push(Instr::Return(ret));
+
+    for at in fixups {
+        let patched = match out[at] {
+            Instr::Jump(target) => Instr::Jump(new_index[if target > len { len } else { target }]),
+            Instr::JumpTrue(cond, target) => Instr::JumpTrue(cond, new_index[if target > len { len } else { target }]),
+            _ => unreachable!(),
+        };
+        out[at] = patched;
+    }
+
+    Ok(RegisterCode {
+        name: code.name.clone(),
+        arity: code.args.len(),
+        locals: base,
+        registers: base + max_depth,
+        constants: constants,
+        names: names,
+        code: out,
+    })
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use ast::Atom;
+use internal::{Bytecode, CodeObject};
+use interpreter::{Interpreter, RuntimeError};
+use interpreter::register_code::{CallTarget, Instr, RegisterCode, Value, translate};
+
+/**
+ * Which virtual machine an interpreter runs code on.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /** The original stack machine, which runs bytecode directly */
+    Stack,
+    /** The register machine prototype, which translates bytecode to register code first */
+    Register,
+}
+
+/**
+ * A prototype register machine. Code objects are translated to register code the first time they run, and the
+ * translation is kept until a function is redefined, since that can change how calls are translated.
+ */
+pub struct RegisterVm {
+    registers: Vec<Option<Value>>, /* Every frame's registers, one after the other */
+    depth: usize, /* How many calls deep we are */
+    translated: HashMap<*const CodeObject, (Rc<CodeObject>, Rc<RegisterCode>)>,
+}
+
+impl RegisterVm {
+    pub fn new() -> RegisterVm {
+        RegisterVm {
+            registers: vec![],
+            depth: 0,
+            translated: HashMap::new(),
+        }
+    }
+
+    /**
+     * Runs top-level code, returning the value of the last expression if there was one.
+     */
+    pub fn run(&mut self, interp: &mut Interpreter, code: &CodeObject) -> Result<Option<Atom>, RuntimeError> {
+        // Functions defined by this code aren't known to the interpreter until it runs, so look for them here
+        let mut defined = HashMap::new();
+        for b in &code.code {
+            match b {
+                &Bytecode::FunDef(ref fun) => { defined.insert(fun.name.clone(), fun.args.len()); },
+                &Bytecode::Extern(ref decl) => { defined.insert(decl.name.clone(), decl.args.len()); },
+                _ => {},
+            }
+        }
+        let translated = try!(translate(code, |name| match defined.get(name) {
+            Some(&arity) => Some(CallTarget::Function(arity)),
+            None => interp.resolve_call(name),
+        }));
+        let frame = self.registers.len();
+        self.registers.resize(frame + translated.registers, None);
+        let result = self.execute(interp, &translated, frame);
+        self.registers.truncate(frame);
+        result.map(|value| value.map(|v| v.to_atom()))
+    }
+
+    /**
+     * Calls a function by name with the given arguments, returning its result.
+     */
+    pub fn call(&mut self, interp: &mut Interpreter, fname: &str, args: Vec<Atom>) -> Result<Atom, RuntimeError> {
+        let fun = match interp.user_function(fname) {
+            Some(fun) => fun,
+            None => return interp.call_external(fname, args).map_err(RuntimeError::from),
+        };
+        let frame = self.registers.len();
+        let mut values = vec![];
+        for arg in args {
+            values.push(Value::from_atom(arg));
+        }
+        let result = self.call_function(interp, &fun, values);
+        self.registers.truncate(frame);
+        result.map(|v| v.to_atom())
+    }
+
+    /**
+     * Forgets every translation, so that code is translated again against the functions that exist now.
+     */
+    pub fn forget(&mut self) {
+        self.translated.clear();
+    }
+
+    fn translation(&mut self, interp: &Interpreter, fun: &Rc<CodeObject>) -> Result<Rc<RegisterCode>, String> {
+        let key = &**fun as *const CodeObject;
+        if let Some(&(_, ref code)) = self.translated.get(&key) {
+            return Ok(code.clone());
+        }
+        let code = Rc::new(try!(translate(fun, |name| interp.resolve_call(name))));
+        // Keep the code object alive, so that its address isn't reused by another one while it's in the cache
+        self.translated.insert(key, (fun.clone(), code.clone()));
+        Ok(code)
+    }
+
+    fn call_function(&mut self, interp: &mut Interpreter, fun: &Rc<CodeObject>, args: Vec<Value>)
+        -> Result<Value, RuntimeError>
+    {
+        let code = try!(self.translation(interp, fun));
+        if args.len() != code.arity {
+            let err = format!("Function {} expects {} arguments but got {}", code.name, code.arity, args.len());
+            return Err(err.into());
+        }
+        try!(interp.check_call_depth(self.depth + 1));
+        let frame = self.registers.len();
+        self.registers.resize(frame + code.registers, None);
+        for (i, arg) in args.into_iter().enumerate() {
+            self.registers[frame + code.locals + i] = Some(arg);
+        }
+        self.depth += 1;
+        let result = self.execute(interp, &code, frame);
+        self.depth -= 1;
+        self.registers.truncate(frame);
+        match try!(result) {
+            Some(value) => Ok(value),
+            None => Err(format!("Function {} did not return a value", code.name).into()),
+        }
+    }
+
+    /**
+     * Collects garbage, with every frame's registers as extra roots.
+     */
+    fn collect_garbage(&self, interp: &mut Interpreter) -> usize {
+        let roots = self.registers.iter()
+            .filter_map(|r| r.as_ref().and_then(Value::heap_ref))
+            .collect();
+        interp.collect_garbage_with(roots)
+    }
+
+    fn get(&self, frame: usize, reg: usize) -> Result<&Value, String> {
+        match self.registers[frame + reg] {
+            Some(ref value) => Ok(value),
+            None => Err("Local variable used before it was defined".to_string()),
+        }
+    }
+
+    fn execute(&mut self, interp: &mut Interpreter, code: &RegisterCode, frame: usize)
+        -> Result<Option<Value>, RuntimeError>
+    {
+        let mut index = 0;
+        loop {
+            if interp.heap().should_collect() {
+                self.collect_garbage(interp);
+            }
+            try!(interp.tick(self.registers.len()));
+            match code.code[index] {
+                Instr::Const(dst, k) => self.registers[frame + dst] = Some(code.constants[k].clone()),
+                Instr::Move(dst, src) => {
+                    let value = try!(self.get(frame, src)).clone();
+                    self.registers[frame + dst] = Some(value);
+                },
+                Instr::Clear(from, to) => for reg in from .. to {
+                    self.registers[frame + reg] = None;
+                },
+                Instr::LoadGlobal(dst, id) => {
+                    let value = match interp.global_var(id) {
+                        Some(value) => value.clone(),
+                        None => return Err(format!("Undefined variable: {}", interp.symbols().name(id)).into()),
+                    };
+                    self.registers[frame + dst] = Some(value);
+                },
+                Instr::StoreGlobal(id, src) => {
+                    let value = try!(self.get(frame, src)).clone();
+                    interp.set_global_var(id, value);
+                },
+                Instr::SetLocal(dst, src) => {
+                    if self.registers[frame + dst].is_none() {
+                        return Err("Local variable set before it was defined".to_string().into());
+                    }
+                    let value = try!(self.get(frame, src)).clone();
+                    self.registers[frame + dst] = Some(value);
+                },
+                Instr::SetGlobal(id, src) => {
+                    if interp.global_var(id).is_none() {
+                        return Err(format!("Undefined variable: {}", interp.symbols().name(id)).into());
+                    }
+                    let value = try!(self.get(frame, src)).clone();
+                    interp.set_global_var(id, value);
+                },
+                Instr::Sub(dst, lhs, rhs) => {
+                    let value = try!(try!(self.get(frame, lhs)).minus(try!(self.get(frame, rhs))));
+                    self.registers[frame + dst] = Some(value);
+                },
+                Instr::Mul(dst, lhs, rhs) => {
+                    let value = try!(try!(self.get(frame, lhs)).times(try!(self.get(frame, rhs))));
+                    self.registers[frame + dst] = Some(value);
+                },
+                Instr::Eq(dst, lhs, rhs) => {
+                    let value = try!(try!(self.get(frame, lhs)).equals(try!(self.get(frame, rhs)), interp.heap()));
+                    self.registers[frame + dst] = Some(value);
+                },
+                Instr::Call(name, first, argc) => {
+                    let ref fname = code.names[name];
+                    let mut args = Vec::with_capacity(argc);
+                    for reg in first .. first + argc {
+                        args.push(try!(self.get(frame, reg)).clone());
+                    }
+                    let value = match interp.user_function(fname) {
+                        Some(fun) => try!(self.call_function(interp, &fun, args)),
+                        None if interp.is_gc_call(fname) => Value::Int(self.collect_garbage(interp) as i64),
+                        None => {
+                            let atoms = args.iter().map(|v| v.to_atom()).collect();
+                            // Registers aren't roots unless we say so, and builtins that call back can collect
+                            let live: Vec<Atom> = if interp.calls_back(fname) {
+                                self.registers.iter()
+                                    .filter_map(|r| r.as_ref().and_then(Value::heap_ref))
+                                    .map(Atom::Ref)
+                                    .collect()
+                            }
+                            else {
+                                vec![]
+                            };
+                            let result = interp.with_pinned(&live, |interp| interp.call_external(fname, atoms));
+                            Value::from_atom(try!(result))
+                        },
+                    };
+                    self.registers[frame + first] = Some(value);
+                },
+                Instr::FunDef(ref fun) => {
+                    // Code translated before now may call this function, either as something else or not at all
+                    interp.define_function(fun.clone());
+                    self.forget();
+                },
+                Instr::Extern(ref decl) => {
+                    try!(interp.load_extern(decl));
+                    self.forget();
+                },
+                Instr::Jump(target) => {
+                    index = target;
+                    continue;
+                },
+                Instr::JumpTrue(cond, target) => if try!(self.get(frame, cond)).is_true() {
+                    index = target;
+                    continue;
+                },
+                Instr::JumpFalse(cond, target) => if !try!(self.get(frame, cond)).is_true() {
+                    index = target;
+                    continue;
+                },
+                Instr::Fail(ref err) => return Err(err.clone().into()),
+                Instr::Return(reg) => return Ok(reg.and_then(|reg| self.registers[frame + reg].clone())),
+            }
+            index += 1;
+        }
+    }
+}
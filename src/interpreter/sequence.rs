@@ -0,0 +1,323 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use ast::Atom;
+use internal::{BuiltinContext, BuiltinFunction, Heap, Object, make_list};
+use interpreter::{LimitExceeded, RuntimeError};
+
+/**
+ * The longest list `&range` makes when the heap isn't limited, which already takes up over a gigabyte.
+ */
+const MAX_RANGE: u64 = 10_000_000;
+
+/**
+ * The builtins that work over sequences, which are lists and vectors. Most of them take a function to call on each
+ * item, named by a string like `"square"`. Sequences that are built from another one are the same kind as it. Like
+ * every builtin they take a fixed number of arguments, so sorting with a comparator is `&sort-by` instead of `&sort`.
+ */
+pub fn sequence_builtins() -> Vec<BuiltinFunction> {
+    vec![
+        BuiltinFunction::new("&map", vec!["fun", "seq"], map_builtin).calls_back(),
+        BuiltinFunction::new("&filter", vec!["pred", "seq"], filter_builtin).calls_back(),
+        BuiltinFunction::new("&reduce", vec!["fun", "seq"], reduce_builtin).calls_back(),
+        BuiltinFunction::new("&fold", vec!["fun", "init", "seq"], fold_builtin).calls_back(),
+        BuiltinFunction::new("&apply", vec!["fun", "args"], apply_builtin).calls_back(),
+        BuiltinFunction::new("&for-each", vec!["fun", "seq"], for_each_builtin).calls_back(),
+        BuiltinFunction::new("&range", vec!["start", "end"], range_builtin),
+        BuiltinFunction::new("&zip", vec!["lhs", "rhs"], zip_builtin),
+        BuiltinFunction::new("&sort", vec!["seq"], sort_builtin),
+        BuiltinFunction::new("&sort-by", vec!["less", "seq"], sort_by_builtin).calls_back(),
+        BuiltinFunction::new("&reverse", vec!["seq"], reverse_builtin),
+        BuiltinFunction::new("&any?", vec!["pred", "seq"], any_builtin).calls_back(),
+        BuiltinFunction::new("&all?", vec!["pred", "seq"], all_builtin).calls_back(),
+    ]
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    List,
+    Vector,
+}
+
+/**
+ * Gets the items in a list or a vector.
+ */
+fn items(heap: &Heap, seq: &Atom) -> Result<(Kind, Vec<Atom>), String> {
+    if let &Atom::Ref(r) = seq {
+        if let &Object::Vector(ref vector) = try!(heap.get(r)) {
+            return Ok((Kind::Vector, vector.clone()));
+        }
+    }
+    let mut items = vec![];
+    let mut seen = HashSet::new();
+    let mut rest = seq.clone();
+    loop {
+        let r = match rest {
+            // @nah is the empty list, and ends every proper one
+            Atom::BoolLit(false) => return Ok((Kind::List, items)),
+            Atom::Ref(r) => r,
+            _ => break,
+        };
+        match try!(heap.get(r)) {
+            &Object::Pair(ref car, ref cdr) if seen.insert(r) => {
+                items.push(car.clone());
+                rest = cdr.clone();
+            },
+            _ => break,
+        }
+    }
+    Err(format!("Expected a list or a vector, but got `{}'", heap.format(seq)))
+}
+
+fn make(context: &mut BuiltinContext, kind: Kind, items: Vec<Atom>) -> Atom {
+    match kind {
+        Kind::List => make_list(context.heap_mut(), items),
+        Kind::Vector => context.alloc(Object::Vector(items)),
+    }
+}
+
+/**
+ * Keeps the items taken out of a sequence alive while callbacks run. Nothing else does once a callback changes the
+ * sequence they came from, with `&vec-pop!` or `&set-cdr!` for instance.
+ */
+fn pin_items(context: &mut BuiltinContext, items: &[Atom]) {
+    for item in items {
+        context.pin(item);
+    }
+}
+
+fn map_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (kind, items) = try!(items(context.heap(), &context.args[1]));
+    let fun = context.args[0].clone();
+    let results = try!(context.with_pinned(|context| -> Result<_, RuntimeError> {
+        pin_items(context, &items);
+        let mut results = vec![];
+        for item in items {
+            let result = try!(context.call(&fun, vec![item]));
+            context.pin(&result);
+            results.push(result);
+        }
+        Ok(results)
+    }));
+    Ok(make(&mut context, kind, results))
+}
+
+fn filter_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (kind, items) = try!(items(context.heap(), &context.args[1]));
+    let pred = context.args[0].clone();
+    let kept = try!(context.with_pinned(|context| -> Result<_, RuntimeError> {
+        pin_items(context, &items);
+        let mut kept = vec![];
+        for item in items {
+            if try!(context.call(&pred, vec![item.clone()])).is_true() {
+                kept.push(item);
+            }
+        }
+        Ok(kept)
+    }));
+    Ok(make(&mut context, kind, kept))
+}
+
+/**
+ * Combines the items of a sequence from left to right, starting with `init`.
+ */
+fn fold(context: &mut BuiltinContext, fun: &Atom, init: Atom, items: Vec<Atom>) -> Result<Atom, RuntimeError> {
+    context.with_pinned(|context| {
+        pin_items(context, &items);
+        let mut acc = init;
+        for item in items {
+            acc = try!(context.call(fun, vec![acc, item]));
+            context.pin(&acc);
+        }
+        Ok(acc)
+    })
+}
+
+fn reduce_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (_, mut items) = try!(items(context.heap(), &context.args[1]));
+    if items.is_empty() {
+        return Err("Can't reduce an empty sequence".to_string().into());
+    }
+    let first = items.remove(0);
+    let fun = context.args[0].clone();
+    fold(&mut context, &fun, first, items)
+}
+
+fn fold_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (_, items) = try!(items(context.heap(), &context.args[2]));
+    let (fun, init) = (context.args[0].clone(), context.args[1].clone());
+    fold(&mut context, &fun, init, items)
+}
+
+/**
+ * Calls a function with the items of a sequence as its arguments.
+ */
+fn apply_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (_, items) = try!(items(context.heap(), &context.args[1]));
+    let fun = context.args[0].clone();
+    context.with_pinned(|context| context.call(&fun, items))
+}
+
+/**
+ * Calls a function on every item of a sequence for its side effects, returning the sequence.
+ */
+fn for_each_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (_, items) = try!(items(context.heap(), &context.args[1]));
+    let fun = context.args[0].clone();
+    try!(context.with_pinned(|context| -> Result<_, RuntimeError> {
+        pin_items(context, &items);
+        for item in items {
+            try!(context.call(&fun, vec![item]));
+        }
+        Ok(())
+    }));
+    Ok(context.args[1].clone())
+}
+
+/**
+ * Makes a list of the integers from `start` up to but not including `end`. Ranges too long to fit under the heap limit
+ * fail before any of the list is made.
+ */
+fn range_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    match (&context.args[0], &context.args[1]) {
+        (&Atom::IntLit(start), &Atom::IntLit(end)) => {
+            // The difference can overflow an i64, but always fits in a u64
+            let length = if end > start { end.wrapping_sub(start) as u64 } else { 0 };
+            match context.interp.limits().max_heap_bytes {
+                Some(max) if Heap::list_size(length) > max as u64 => return Err(LimitExceeded::HeapBytes(max).into()),
+                None if length > MAX_RANGE =>
+                    return Err(format!("Can't make a range of {} items, the most is {}", length, MAX_RANGE).into()),
+                _ => (),
+            }
+            let items = (start .. end).map(Atom::IntLit).collect();
+            Ok(make_list(context.heap_mut(), items))
+        },
+        (start, end) => {
+            let (start, end) = (context.heap().format(start), context.heap().format(end));
+            Err(format!("Expected two integers, but got `{}' and `{}'", start, end).into())
+        },
+    }
+}
+
+/**
+ * Pairs up the items of two sequences, stopping at the end of the shorter one.
+ */
+fn zip_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (kind, lhs) = try!(items(context.heap(), &context.args[0]));
+    let (_, rhs) = try!(items(context.heap(), &context.args[1]));
+    let pairs = lhs.into_iter()
+        .zip(rhs)
+        .map(|(l, r)| context.alloc(Object::Pair(l, r)))
+        .collect();
+    Ok(make(&mut context, kind, pairs))
+}
+
+/**
+ * Orders numbers by value and strings and booleans by themselves. Anything else can't be sorted without a comparator.
+ */
+fn compare(heap: &Heap, lhs: &Atom, rhs: &Atom) -> Result<Ordering, String> {
+    let ordering = match (lhs, rhs) {
+        (&Atom::IntLit(l), &Atom::IntLit(r)) => Some(l.cmp(&r)),
+        (&Atom::IntLit(l), &Atom::DubLit(r)) => (l as f64).partial_cmp(&r),
+        (&Atom::DubLit(l), &Atom::IntLit(r)) => l.partial_cmp(&(r as f64)),
+        (&Atom::DubLit(l), &Atom::DubLit(r)) => l.partial_cmp(&r),
+        (&Atom::StrLit(ref l), &Atom::StrLit(ref r)) => Some(l.cmp(r)),
+        (&Atom::BoolLit(l), &Atom::BoolLit(r)) => Some(l.cmp(&r)),
+        _ => None,
+    };
+    match ordering {
+        Some(ordering) => Ok(ordering),
+        None => Err(format!("Can't compare `{}' and `{}'", heap.format(lhs), heap.format(rhs))),
+    }
+}
+
+/**
+ * Sorts items with a comparison that can fail, stopping at the first failure. `less` says whether its first argument
+ * goes before its second. This is a merge sort rather than std's, which can panic when the comparison isn't a total
+ * order, and user comparators often aren't. The sort is stable.
+ */
+fn sort<F>(mut items: Vec<Atom>, less: &mut F) -> Result<Vec<Atom>, RuntimeError>
+    where F: FnMut(&Atom, &Atom) -> Result<bool, RuntimeError>
+{
+    if items.len() < 2 {
+        return Ok(items);
+    }
+    let rest = items.split_off(items.len() / 2);
+    let lhs = try!(sort(items, less));
+    let rhs = try!(sort(rest, less));
+    let mut merged = Vec::with_capacity(lhs.len() + rhs.len());
+    let mut lhs = lhs.into_iter().peekable();
+    let mut rhs = rhs.into_iter().peekable();
+    loop {
+        // Items from the left half go first unless the right one is strictly less, which keeps the sort stable
+        let right_first = match (lhs.peek(), rhs.peek()) {
+            (Some(l), Some(r)) => try!(less(r, l)),
+            _ => break,
+        };
+        merged.push(if right_first { rhs.next() } else { lhs.next() }.unwrap());
+    }
+    merged.extend(lhs);
+    merged.extend(rhs);
+    Ok(merged)
+}
+
+fn sort_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (kind, items) = try!(items(context.heap(), &context.args[0]));
+    let sorted = try!(sort(items, &mut |lhs, rhs| {
+        Ok(try!(compare(context.heap(), lhs, rhs)) == Ordering::Less)
+    }));
+    Ok(make(&mut context, kind, sorted))
+}
+
+/**
+ * Sorts a sequence with a function that says whether its first argument goes before its second. Builtins take a fixed
+ * number of arguments, so this is its own builtin rather than an optional argument to `&sort`.
+ */
+fn sort_by_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (kind, items) = try!(items(context.heap(), &context.args[1]));
+    let less = context.args[0].clone();
+    let sorted = try!(context.with_pinned(|context| {
+        pin_items(context, &items);
+        sort(items, &mut |lhs, rhs| Ok(try!(context.call(&less, vec![lhs.clone(), rhs.clone()])).is_true()))
+    }));
+    Ok(make(&mut context, kind, sorted))
+}
+
+fn reverse_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (kind, mut items) = try!(items(context.heap(), &context.args[0]));
+    items.reverse();
+    Ok(make(&mut context, kind, items))
+}
+
+/**
+ * Checks whether a predicate holds for any item of a sequence, stopping at the first one it holds for.
+ */
+fn any_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (_, items) = try!(items(context.heap(), &context.args[1]));
+    let pred = context.args[0].clone();
+    context.with_pinned(|context| {
+        pin_items(context, &items);
+        for item in items {
+            if try!(context.call(&pred, vec![item])).is_true() {
+                return Ok(Atom::BoolLit(true));
+            }
+        }
+        Ok(Atom::BoolLit(false))
+    })
+}
+
+/**
+ * Checks whether a predicate holds for every item of a sequence, stopping at the first one it doesn't hold for.
+ */
+fn all_builtin(mut context: BuiltinContext) -> Result<Atom, RuntimeError> {
+    let (_, items) = try!(items(context.heap(), &context.args[1]));
+    let pred = context.args[0].clone();
+    context.with_pinned(|context| {
+        pin_items(context, &items);
+        for item in items {
+            if !try!(context.call(&pred, vec![item])).is_true() {
+                return Ok(Atom::BoolLit(false));
+            }
+        }
+        Ok(Atom::BoolLit(true))
+    })
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+/**
+ * Interns global variable names, so that globals can be looked up by number at runtime instead of by name.
+ */
+#[derive(Clone, Debug)]
+pub struct SymbolTable {
+    ids: HashMap<String, usize>,
+    names: Vec<String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            ids: HashMap::new(),
+            names: vec![],
+        }
+    }
+
+    /**
+     * Gets the ID for a name, giving it a new one if it hasn't been seen before.
+     */
+    pub fn intern(&mut self, name: &str) -> usize {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = self.names.len();
+        self.ids.insert(name.to_string(), id);
+        self.names.push(name.to_string());
+        id
+    }
+
+    /**
+     * Gets the ID for a name, if it has one.
+     */
+    pub fn lookup(&self, name: &str) -> Option<usize> {
+        self.ids.get(name).cloned()
+    }
+
+    /**
+     * Gets the name that an ID was given to.
+     */
+    pub fn name(&self, id: usize) -> &str {
+        &self.names[id]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+}
@@ -0,0 +1,32 @@
+//! Rasp, a toy LISP interpreter.
+//!
+//! The simplest way to embed rasp is through `Engine`, which takes care of parsing, compiling and running source text.
+//! The lower-level pieces (`ast`, `interpreter` and `internal`) are public as well, for tools that need them.
+
+extern crate lalrpop_util;
+extern crate num;
+extern crate rustc_serialize;
+
+/**
+ * Like `try!`, for options.
+ */
+macro_rules! try_opt(
+    ($e:expr) => { match $e { Some(value) => value, None => return None } }
+);
+
+pub mod ast;
+mod rasp;
+pub mod util;
+pub mod internal;
+pub mod interpreter;
+mod engine;
+pub mod debugger;
+pub mod formatter;
+pub mod lsp;
+pub mod profiler;
+pub mod testing;
+pub mod tracer;
+
+pub use ast::Atom;
+pub use engine::*;
+pub use interpreter::Interpreter;
@@ -0,0 +1,498 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+use lalrpop_util::ParseError;
+use rustc_serialize::json::Json;
+use ast::visitor::MutVisitor;
+use ast::{Expression, Span, strip_comments};
+use engine::PRELUDE;
+use internal::make_builtin_functions;
+use interpreter::{BytecodeGen, Linter, SymbolTable};
+use rasp;
+
+/* Diagnostic severities */
+const ERROR: u64 = 1;
+const WARNING: u64 = 2;
+
+/* Completion item kind for functions */
+const FUNCTION: u64 = 3;
+
+/* JSON-RPC error codes */
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/**
+ * Where a function that the server knows about comes from.
+ */
+#[derive(Clone, Debug, PartialEq)]
+enum Origin {
+    Builtin,
+    Prelude,
+    Document(String, Span), /* The URI of the document that defines it, and its definition */
+}
+
+#[derive(Clone, Debug)]
+struct Signature {
+    name: String,
+    args: Vec<String>,
+    origin: Origin,
+}
+
+impl Signature {
+    fn describe(&self) -> String {
+        let call = if self.args.is_empty() {
+            format!("({})", self.name)
+        }
+        else {
+            format!("({} {})", self.name, self.args.join(" "))
+        };
+        let origin = match self.origin {
+            Origin::Builtin => "builtin",
+            Origin::Prelude => "prelude",
+            Origin::Document(_, _) => "user function",
+        };
+        format!("{} ; {}", call, origin)
+    }
+}
+
+/**
+ * A Language Server Protocol server for rasp. It keeps the text of every open document, and answers requests about
+ * them: diagnostics from the parser, compiler and linter, go-to-definition and hover for functions, and completion of
+ * function names.
+ *
+ * The server doesn't do any I/O itself; `handle` takes one message and returns the messages to send back, and
+ * `serve` runs it over a pair of streams.
+ */
+pub struct LanguageServer {
+    documents: HashMap<String, String>, /* The text of each open document, by URI */
+    library: Vec<Signature>, /* Builtins and prelude functions */
+    shut_down: bool,
+    exited: bool,
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+fn string(s: &str) -> Json {
+    Json::String(s.to_string())
+}
+
+fn error_response(id: Json, code: i64, msg: String) -> Json {
+    object(vec![
+        ("jsonrpc", string("2.0")),
+        ("id", id),
+        ("error", object(vec![("code", Json::I64(code)), ("message", Json::String(msg))])),
+    ])
+}
+
+/**
+ * Converts a byte offset into an LSP position, which counts lines and UTF-16 code units from 0.
+ */
+fn position(text: &str, offset: usize) -> Json {
+    let offset = if offset > text.len() { text.len() } else { offset };
+    let before = &text[.. offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let character: usize = before[line_start ..].chars().map(char::len_utf16).sum();
+    object(vec![
+        ("line", Json::U64(before.matches('\n').count() as u64)),
+        ("character", Json::U64(character as u64)),
+    ])
+}
+
+fn range(text: &str, span: Span) -> Json {
+    object(vec![("start", position(text, span.start)), ("end", position(text, span.end))])
+}
+
+/**
+ * Converts an LSP position into a byte offset, clamping it to the text.
+ */
+fn offset(text: &str, position: &Json) -> usize {
+    let line = position.find("line").and_then(Json::as_u64).unwrap_or(0) as usize;
+    let character = position.find("character").and_then(Json::as_u64).unwrap_or(0) as usize;
+    let mut line_start = 0;
+    for _ in 0 .. line {
+        match text[line_start ..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let mut units = 0;
+    for (i, c) in text[line_start ..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || "=+_&*?!-".contains(c)
+}
+
+/**
+ * Gets the identifier that the given offset is in or just after, along with its span.
+ */
+fn identifier_at(text: &str, offset: usize) -> Option<(String, Span)> {
+    let start = text[.. offset].rfind(|c: char| !is_identifier_char(c)).map(|i| i + 1).unwrap_or(0);
+    let end = text[offset ..].find(|c: char| !is_identifier_char(c)).map(|i| offset + i).unwrap_or(text.len());
+    if start < end { Some((text[start .. end].to_string(), Span::new(start, end))) } else { None }
+}
+
+/**
+ * Gets the span and a description of a parse error.
+ */
+fn describe_parse_error(err: ParseError<usize, (usize, &str), ()>, len: usize) -> (Span, String) {
+    match err {
+        ParseError::InvalidToken { location } =>
+            (Span::new(location, location + 1), "Invalid token".to_string()),
+        ParseError::UnrecognizedToken { token: Some((start, (_, tok), end)), expected } =>
+            (Span::new(start, end), format!("Unexpected token {}, expected one of: {}", tok, expected.join(" "))),
+        ParseError::UnrecognizedToken { token: None, .. } =>
+            (Span::new(len, len), "Unexpected end of file".to_string()),
+        ParseError::ExtraToken { token: (start, (_, tok), end) } =>
+            (Span::new(start, end), format!("Unexpected token {}", tok)),
+        ParseError::User { .. } => (Span::new(0, 0), "Could not parse".to_string()),
+    }
+}
+
+/**
+ * Gets the functions that parsed code defines.
+ */
+fn definitions(exprs: &[Expression], origin: &Fn(Span) -> Origin) -> Vec<Signature> {
+    exprs.iter()
+        .filter_map(|expr| match expr {
+            &Expression::FunDef(ref fun) =>
+                Some(Signature { name: fun.name.clone(), args: fun.args.clone(), origin: origin(fun.span) }),
+            &Expression::Extern(ref decl) =>
+                Some(Signature { name: decl.name.clone(), args: decl.args.clone(), origin: origin(decl.span) }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse(text: &str) -> Result<Vec<Expression>, (Span, String)> {
+    let (stripped, _) = strip_comments(text);
+    rasp::parse_CompileUnit(&stripped).map_err(|err| describe_parse_error(err, text.len()))
+}
+
+impl LanguageServer {
+    pub fn new() -> LanguageServer {
+        let mut library: Vec<Signature> = make_builtin_functions()
+            .into_iter()
+            .map(|(name, builtin)| Signature {
+                name: name.to_string(),
+                args: builtin.args.iter().map(|a| a.to_string()).collect(),
+                origin: Origin::Builtin,
+            })
+            .collect();
+        if let Ok(prelude) = parse(PRELUDE) {
+            library.extend(definitions(&prelude, &|_| Origin::Prelude));
+        }
+        library.sort_by(|a, b| a.name.cmp(&b.name));
+        LanguageServer {
+            documents: HashMap::new(),
+            library: library,
+            shut_down: false,
+            exited: false,
+        }
+    }
+
+    /**
+     * Whether the client has told the server to exit.
+     */
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /**
+     * Whether the client asked the server to shut down before it exited, which is how a clean exit goes.
+     */
+    pub fn shut_down(&self) -> bool {
+        self.shut_down
+    }
+
+    /**
+     * Handles one message from the client, returning the responses and notifications to send back.
+     */
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.find("method").and_then(Json::as_string).unwrap_or("").to_string();
+        let params = message.find("params").cloned().unwrap_or(Json::Null);
+        let id = match message.find("id") {
+            Some(id) => id.clone(),
+            // Notifications don't get a response
+            None => return self.notification(&method, &params),
+        };
+        let result = if self.shut_down && method != "shutdown" {
+            Err((INVALID_REQUEST, "The server has been shut down".to_string()))
+        }
+        else {
+            self.request(&method, &params)
+        };
+        let response = match result {
+            Ok(result) => object(vec![("jsonrpc", string("2.0")), ("id", id), ("result", result)]),
+            Err((code, msg)) => error_response(id, code, msg),
+        };
+        vec![response]
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        match method {
+            "initialize" => Ok(object(vec![
+                ("capabilities", object(vec![
+                    ("textDocumentSync", Json::U64(1)), // the whole document is sent on every change
+                    ("definitionProvider", Json::Boolean(true)),
+                    ("hoverProvider", Json::Boolean(true)),
+                    ("completionProvider", object(vec![])),
+                ])),
+                ("serverInfo", object(vec![("name", string("rasp"))])),
+            ])),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            },
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/hover" => Ok(self.hover(params)),
+            "textDocument/completion" => Ok(self.completion(params)),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params.find_path(&["textDocument", "uri"]).and_then(Json::as_string).unwrap_or("").to_string();
+        match method {
+            "exit" => {
+                self.exited = true;
+                vec![]
+            },
+            "textDocument/didOpen" => {
+                let text = params.find_path(&["textDocument", "text"]).and_then(Json::as_string).unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+                vec![self.diagnostics(&uri)]
+            },
+            "textDocument/didChange" => {
+                // With full syncing, the last change is the whole new text
+                let text = params.find("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.find("text"))
+                    .and_then(Json::as_string);
+                if let Some(text) = text {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                vec![self.diagnostics(&uri)]
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                // Clear out the closed document's diagnostics
+                vec![self.publish(&uri, vec![])]
+            },
+            _ => vec![],
+        }
+    }
+
+    fn publish(&self, uri: &str, diagnostics: Vec<Json>) -> Json {
+        object(vec![
+            ("jsonrpc", string("2.0")),
+            ("method", string("textDocument/publishDiagnostics")),
+            ("params", object(vec![("uri", string(uri)), ("diagnostics", Json::Array(diagnostics))])),
+        ])
+    }
+
+    /**
+     * Checks a document, returning a notification with everything that's wrong with it.
+     */
+    fn diagnostics(&self, uri: &str) -> Json {
+        let text = match self.documents.get(uri) {
+            Some(text) => text,
+            None => return self.publish(uri, vec![]),
+        };
+        let diagnostic = |span: Span, severity: u64, msg: &str| object(vec![
+            ("range", range(text, span)),
+            ("severity", Json::U64(severity)),
+            ("source", string("rasp")),
+            ("message", string(msg)),
+        ]);
+        let exprs = match parse(text) {
+            Ok(exprs) => exprs,
+            Err((span, msg)) => return self.publish(uri, vec![diagnostic(span, ERROR, &msg)]),
+        };
+
+        let mut diagnostics = vec![];
+        // The compiler's errors don't say where they are, so compile one form at a time to find out
+        let mut symbols = SymbolTable::new();
+        let mut gen = BytecodeGen::new(&mut symbols);
+        gen.set_source(text);
+        for expr in &exprs {
+            let before = gen.errors.len();
+            gen.visit_expression(expr);
+            for err in &gen.errors[before ..] {
+                diagnostics.push(diagnostic(expr.span(), ERROR, err));
+            }
+        }
+
+        let mut linter = Linter::new();
+        for signature in &self.library {
+            match signature.origin {
+                Origin::Builtin => linter.define_builtin(&signature.name, signature.args.len()),
+                _ => linter.define_function(&signature.name, signature.args.len()),
+            }
+        }
+        for (span, msg) in linter.check(&exprs) {
+            diagnostics.push(diagnostic(span, WARNING, &msg));
+        }
+        self.publish(uri, diagnostics)
+    }
+
+    /**
+     * Gets every function that's visible from a document: the library, plus what every open document defines.
+     */
+    fn functions(&self) -> Vec<Signature> {
+        let mut functions = self.library.clone();
+        for (uri, text) in &self.documents {
+            if let Ok(exprs) = parse(text) {
+                functions.extend(definitions(&exprs, &|span| Origin::Document(uri.clone(), span)));
+            }
+        }
+        functions
+    }
+
+    /**
+     * Finds the identifier that a request's position points at.
+     */
+    fn identifier(&self, params: &Json) -> Option<(String, String, Span)> {
+        let uri = try_opt!(params.find_path(&["textDocument", "uri"]).and_then(Json::as_string));
+        let text = try_opt!(self.documents.get(uri));
+        let offset = offset(text, try_opt!(params.find("position")));
+        let (name, span) = try_opt!(identifier_at(text, offset));
+        Some((uri.to_string(), name, span))
+    }
+
+    /**
+     * Finds the functions named by the identifier at a request's position. The ones in the requesting document come
+     * first, since they're the ones that will be called.
+     */
+    fn lookup(&self, params: &Json) -> (Option<(String, Span)>, Vec<Signature>) {
+        let (uri, name, span) = match self.identifier(params) {
+            Some(found) => found,
+            None => return (None, vec![]),
+        };
+        let mut found: Vec<_> = self.functions().into_iter().filter(|f| f.name == name).collect();
+        found.sort_by_key(|f| match f.origin {
+            Origin::Document(ref doc, _) if *doc == uri => 0,
+            Origin::Document(_, _) => 1,
+            _ => 2,
+        });
+        (Some((uri, span)), found)
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let (_, found) = self.lookup(params);
+        let locations = found.into_iter()
+            .filter_map(|f| match f.origin {
+                Origin::Document(uri, span) => {
+                    let range = range(&self.documents[&uri], span);
+                    Some(object(vec![("uri", Json::String(uri)), ("range", range)]))
+                },
+                _ => None,
+            })
+            .collect();
+        Json::Array(locations)
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let (place, found) = self.lookup(params);
+        let signature = match found.first() {
+            Some(signature) => signature,
+            None => return Json::Null,
+        };
+        let (uri, span) = place.unwrap();
+        object(vec![
+            ("contents", object(vec![
+                ("kind", string("markdown")),
+                ("value", Json::String(format!("```rasp\n{}\n```", signature.describe()))),
+            ])),
+            ("range", range(&self.documents[&uri], span)),
+        ])
+    }
+
+    fn completion(&self, _params: &Json) -> Json {
+        // Clients filter the list by what's been typed, so everything is offered
+        let mut seen = BTreeMap::new();
+        for f in self.functions() {
+            seen.entry(f.name.clone()).or_insert(f);
+        }
+        let items = seen.into_iter()
+            .map(|(name, f)| object(vec![
+                ("label", Json::String(name)),
+                ("kind", Json::U64(FUNCTION)),
+                ("detail", Json::String(f.describe())),
+            ]))
+            .collect();
+        Json::Array(items)
+    }
+}
+
+/**
+ * Reads one message, with its `Content-Length` header. Returns `None` at the end of the input. A message without a
+ * length or with a body that isn't JSON is skipped over and returned as an `InvalidData` error.
+ */
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if try!(input.read_line(&mut header)) == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        let mut parts = header.splitn(2, ':');
+        if parts.next().map(|name| name.trim().eq_ignore_ascii_case("content-length")) == Some(true) {
+            length = parts.next().and_then(|value| value.trim().parse::<usize>().ok());
+        }
+    }
+    let length = match length {
+        Some(length) => length,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "message without a Content-Length")),
+    };
+    let mut body = vec![0; length];
+    try!(input.read_exact(&mut body));
+    let body = try!(String::from_utf8(body).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)));
+    Json::from_str(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    try!(write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body));
+    output.flush()
+}
+
+/**
+ * Runs a server until the client tells it to exit, or the input ends. Returns whether it was shut down cleanly.
+ * Messages that can't be read are answered with a parse error, and the server carries on with the next one.
+ */
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<bool> {
+    let mut server = LanguageServer::new();
+    while !server.exited() {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(ref err) if err.kind() == io::ErrorKind::InvalidData => {
+                // There's no telling which request it was, so the error can't say either
+                let reply = error_response(Json::Null, PARSE_ERROR, format!("Could not read the message: {}", err));
+                try!(write_message(&mut output, &reply));
+                continue;
+            },
+            Err(err) => return Err(err),
+        };
+        for reply in server.handle(&message) {
+            try!(write_message(&mut output, &reply));
+        }
+    }
+    Ok(server.shut_down())
+}
@@ -1,18 +1,21 @@
-mod ast;
-mod rasp;
-mod util;
-mod internal;
-mod interpreter;
-
-extern crate lalrpop_util;
-extern crate num;
+extern crate rasp;
 
+use std::cell::RefCell;
 use std::env;
 use std::fs::File;
+use std::io::{self, BufReader};
 use std::io::prelude::*;
-use util::RaspParseError;
-use interpreter::{BytecodeGen, Interpreter};
-use ast::visitor::MutVisitor;
+use std::path::Path;
+use std::rc::Rc;
+use rasp::{Engine, Error, Program};
+use rasp::debugger::{Breakpoint, Debugger};
+use rasp::formatter::{FormatOptions, format_source};
+use rasp::internal::{Capabilities, is_code_file};
+use rasp::lsp;
+use rasp::interpreter::{Backend, Hook, Limits};
+use rasp::profiler::{Profile, Profiler};
+use rasp::testing;
+use rasp::tracer::Tracer;
 
 macro_rules! printerrln(
     ($($arg:tt)*) => { {
@@ -21,55 +24,474 @@ macro_rules! printerrln(
     } }
 );
 
-fn main() {
-    let mut error_occurred = false;
-    for arg in env::args().skip(1) {
-        let contents = if let Ok(mut fp) = File::open(&arg) {
-            printerrln!("Opened {}", &arg);
-            // read contents
-            let mut contents = String::new();
-            if fp.read_to_string(&mut contents).is_ok() {
-                contents
+/**
+ * Reads a whole file, printing an error if it can't be read.
+ */
+fn read_file(path: &str) -> Option<Vec<u8>> {
+    if let Ok(mut fp) = File::open(path) {
+        printerrln!("Opened {}", path);
+        // read contents
+        let mut contents = vec![];
+        if fp.read_to_end(&mut contents).is_ok() {
+            Some(contents)
+        }
+        else {
+            printerrln!("Could not read {}", path);
+            None
+        }
+    }
+    else {
+        printerrln!("No such file named {}", path);
+        None
+    }
+}
+
+/**
+ * Compiles source text, or loads it if it's already compiled, printing any errors or warnings.
+ */
+fn load_program(engine: &mut Engine, contents: &[u8], source_name: &str) -> Option<Program> {
+    let result = if is_code_file(contents) {
+        engine.load_compiled(contents)
+    }
+    else {
+        match String::from_utf8(contents.to_vec()) {
+            Ok(source) => engine.compile(&source, source_name),
+            Err(_) => {
+                printerrln!("{} is not valid UTF-8", source_name);
+                return None;
+            },
+        }
+    };
+    match result {
+        Ok(program) => {
+            for warn in &program.warnings {
+                printerrln!("WARN: {}", warn);
             }
-            else {
-                error_occurred = true;
-                printerrln!("Could not read {}", &arg);
-                continue;
+            Some(program)
+        },
+        Err(Error::Compile(errors)) => {
+            for err in errors {
+                printerrln!("ERR:  {}", err);
+            }
+            None
+        },
+        Err(Error::Load(err)) => {
+            printerrln!("ERR:  {}: {}", source_name, err);
+            None
+        },
+        Err(err) => {
+            printerrln!("{}", err);
+            None
+        },
+    }
+}
+
+/**
+ * Loads and runs a file's contents in the given engine. Returns true if everything went well.
+ */
+fn run_file(engine: &mut Engine, contents: &[u8], source_name: &str, dump_bytecode: bool) -> bool {
+    let program = match load_program(engine, contents, source_name) {
+        Some(program) => program,
+        None => return false,
+    };
+
+    if dump_bytecode {
+        print!("{}", engine.disassemble(&program));
+    }
+
+    // Interpret
+    if let Err(err) = engine.run(&program) {
+        printerrln!("ERR:  {}", err);
+        return false;
+    }
+    true
+}
+
+/**
+ * How the engines that run files should be set up.
+ */
+struct Options {
+    load_prelude: bool,
+    optimize: bool,
+    backend: Backend,
+    limits: Limits,
+    sandbox: bool,
+    trace: bool,
+    profile: Option<Rc<RefCell<Profile>>>, /* Shared by every engine, so that it covers all of the files */
+}
+
+/**
+ * Creates a fresh engine set up the way the options say.
+ */
+fn new_engine(options: &Options) -> Engine {
+    let mut engine = if options.load_prelude { Engine::new() } else { Engine::bare() };
+    engine.set_optimize(options.optimize);
+    engine.set_backend(options.backend);
+    // The prelude is trusted, so limits and the sandbox only apply to what comes after it
+    engine.set_limits(options.limits);
+    if options.sandbox {
+        engine.set_capabilities(Capabilities::none());
+    }
+    let mut hooks: Vec<Box<Hook>> = vec![];
+    if options.trace {
+        hooks.push(Box::new(Tracer::new(io::stderr())));
+    }
+    if let Some(ref profile) = options.profile {
+        hooks.push(Box::new(Profiler::new(profile.clone())));
+    }
+    if !hooks.is_empty() {
+        engine.interpreter().set_hook(Some(Box::new(hooks)));
+    }
+    engine
+}
+
+/**
+ * Gets the number that follows a flag, exiting if it's missing or isn't a number.
+ */
+fn number_arg<I: Iterator<Item=String>>(flag: &str, args: &mut I) -> usize {
+    match args.next().and_then(|n| n.parse().ok()) {
+        Some(n) => n,
+        None => {
+            printerrln!("{} needs a number", flag);
+            std::process::exit(1);
+        },
+    }
+}
+
+/**
+ * `rasp compile [ -O ] file.rasp [ -o file.raspc ]`
+ */
+fn compile_main(args: Vec<String>) {
+    let mut output = None;
+    let mut optimize = false;
+    let mut files = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-O" {
+            optimize = true;
+        }
+        else if arg == "-o" {
+            output = args.next();
+            if output.is_none() {
+                printerrln!("-o needs a file name");
+                std::process::exit(1);
             }
         }
         else {
-            error_occurred = true;
-            printerrln!("No such file named {}", &arg);
-            continue;
+            files.push(arg);
+        }
+    }
+    if files.len() != 1 {
+        printerrln!("Usage: rasp compile [ -O ] file.rasp [ -o file.raspc ]");
+        std::process::exit(1);
+    }
+    let ref input = files[0];
+    let output = output.unwrap_or_else(|| Path::new(input)
+        .with_extension("raspc")
+        .to_string_lossy()
+        .into_owned());
+
+    // Compiled files are run with the prelude, so they're checked against it, but it isn't part of them
+    let mut engine = Engine::new();
+    engine.set_optimize(optimize);
+    let program = match read_file(input).and_then(|contents| load_program(&mut engine, &contents, input)) {
+        Some(program) => program,
+        None => {
+            printerrln!("Error occurred; aborting");
+            std::process::exit(1);
+        },
+    };
+    let written = File::create(&output)
+        .and_then(|mut fp| engine.write_compiled(&program, &mut fp));
+    if let Err(err) = written {
+        printerrln!("Could not write {}: {}", output, err);
+        std::process::exit(1);
+    }
+}
+
+/**
+ * `rasp debug [ --no-prelude ] [ -b LINE|NAME ]... file.rasp`
+ */
+fn debug_main(args: Vec<String>) {
+    let mut load_prelude = true;
+    let mut breakpoints = vec![];
+    let mut files = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--no-prelude" {
+            load_prelude = false;
+        }
+        else if arg == "-b" {
+            match args.next() {
+                Some(place) => breakpoints.push(match place.parse() {
+                    Ok(line) => Breakpoint::Line(line),
+                    Err(_) => Breakpoint::Function(place),
+                }),
+                None => {
+                    printerrln!("-b needs a line number or a function name");
+                    std::process::exit(1);
+                },
+            }
+        }
+        else {
+            files.push(arg);
+        }
+    }
+    if files.len() != 1 {
+        printerrln!("Usage: rasp debug [ --no-prelude ] [ -b LINE|NAME ]... file.rasp");
+        std::process::exit(1);
+    }
+    let ref input = files[0];
+
+    // Hooks only run on the stack machine, which is what engines use unless they're told otherwise
+    let mut engine = if load_prelude { Engine::new() } else { Engine::bare() };
+    let (contents, program) = match read_file(input) {
+        Some(contents) => match load_program(&mut engine, &contents, input) {
+            Some(program) => (contents, program),
+            None => std::process::exit(1),
+        },
+        None => std::process::exit(1),
+    };
+    // Compiled files don't have any source to show, but they can still be stepped through
+    let source = if is_code_file(&contents) { String::new() } else { String::from_utf8_lossy(&contents).into_owned() };
+    let mut debugger = Debugger::new(&program.code, input, &source, BufReader::new(io::stdin()), io::stdout());
+    for breakpoint in breakpoints {
+        debugger.add_breakpoint(breakpoint);
+    }
+    engine.interpreter().set_hook(Some(Box::new(debugger)));
+    if let Err(err) = engine.run(&program) {
+        printerrln!("ERR:  {}", err);
+        std::process::exit(1);
+    }
+}
+
+/**
+ * `rasp fmt [ --check ] [ --indent N ] [ --width N ] file.rasp...`
+ */
+fn fmt_main(args: Vec<String>) {
+    let mut check = false;
+    let mut options = FormatOptions::default();
+    let mut files = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--indent" => options.indent = number_arg(&arg, &mut args),
+            "--width" => options.width = number_arg(&arg, &mut args),
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        printerrln!("Usage: rasp fmt [ --check ] [ --indent N ] [ --width N ] file.rasp...");
+        std::process::exit(1);
+    }
+
+    let mut failed = false;
+    for file in files {
+        let source = match read_file(&file).map(String::from_utf8) {
+            Some(Ok(source)) => source,
+            Some(Err(_)) => {
+                printerrln!("{} is not valid UTF-8", file);
+                failed = true;
+                continue;
+            },
+            None => {
+                failed = true;
+                continue;
+            },
         };
-        // eval
-        let result = rasp::parse_CompileUnit(&contents);
-        if let Err(err) = result {
-            printerrln!("{}", RaspParseError::new(err, &contents, &arg));
-            error_occurred = true;
+        let formatted = match format_source(&source, &file, &options) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                printerrln!("ERR:  {}", err);
+                failed = true;
+                continue;
+            },
+        };
+        if formatted == source {
             continue;
         }
+        if check {
+            println!("{} is not formatted", file);
+            failed = true;
+        }
+        else if let Err(err) = File::create(&file).and_then(|mut fp| fp.write_all(formatted.as_bytes())) {
+            printerrln!("Could not write {}: {}", file, err);
+            failed = true;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
 
-        let expr_list = result.unwrap();
-        let mut gen = BytecodeGen::new(&vec![]);
-        for ast in expr_list {
-            gen.visit_expression(&ast);
+fn test_main(args: Vec<String>) {
+    let mut format = "plain".to_string();
+    let mut paths = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = args.next().unwrap_or_default(),
+            _ => paths.push(arg),
         }
+    }
+    if format != "plain" && format != "tap" && format != "junit" {
+        printerrln!("Usage: rasp test [ --format plain|tap|junit ] [ path... ]");
+        std::process::exit(1);
+    }
+    if paths.is_empty() {
+        paths.push(".".to_string());
+    }
 
-        for warn in gen.warnings {
-            printerrln!("WARN: {}", warn);
+    let files = match testing::discover(&paths) {
+        Ok(files) => files,
+        Err(err) => {
+            printerrln!("Could not look for tests: {}", err);
+            std::process::exit(1);
+        },
+    };
+    if files.is_empty() {
+        printerrln!("No test files found; test files are named *{}", testing::TEST_SUFFIX);
+        std::process::exit(1);
+    }
+    let mut cases = vec![];
+    let mut failed = false;
+    for file in files {
+        let name = file.to_string_lossy().into_owned();
+        match read_file(&name).map(String::from_utf8) {
+            Some(Ok(source)) => cases.extend(testing::run_tests(&source, &name)),
+            Some(Err(_)) => {
+                printerrln!("{} is not valid UTF-8", name);
+                failed = true;
+            },
+            None => failed = true,
         }
+    }
 
-        if gen.errors.len() == 0 {
-            // Interpret
-            let mut interp = Interpreter::new();
-            if let Err(err) = interp.interpret(&gen.bytecode) {
-                printerrln!("ERR:  {}", err);
-            }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let written = match format.as_str() {
+        "tap" => testing::write_tap(&mut out, &cases),
+        "junit" => testing::write_junit(&mut out, &cases),
+        _ => testing::write_report(&mut out, &cases),
+    };
+    if let Err(err) = written {
+        printerrln!("Could not write the test report: {}", err);
+        failed = true;
+    }
+    if failed || cases.iter().any(|case| !case.passed()) {
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(|a| a.as_str()) == Some("compile") {
+        compile_main(args.into_iter().skip(1).collect());
+        return;
+    }
+    if args.first().map(|a| a.as_str()) == Some("lsp") {
+        // The protocol runs over stdin and stdout, so nothing else can be printed to stdout
+        match lsp::serve(BufReader::new(io::stdin()), io::stdout()) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(err) => {
+                printerrln!("rasp lsp: {}", err);
+                std::process::exit(1);
+            },
         }
-        else {
-            for err in gen.errors {
-                printerrln!("ERR:  {}", err);
+    }
+    if args.first().map(|a| a.as_str()) == Some("fmt") {
+        fmt_main(args.into_iter().skip(1).collect());
+        return;
+    }
+    if args.first().map(|a| a.as_str()) == Some("test") {
+        test_main(args.into_iter().skip(1).collect());
+        return;
+    }
+    if args.first().map(|a| a.as_str()) == Some("debug") {
+        debug_main(args.into_iter().skip(1).collect());
+        return;
+    }
+
+    let mut error_occurred = false;
+    let mut shared = false;
+    let mut dump_bytecode = false;
+    let mut options = Options {
+        load_prelude: true,
+        optimize: false,
+        backend: Backend::Stack,
+        limits: Limits::default(),
+        sandbox: false,
+        trace: false,
+        profile: None,
+    };
+    let mut profile_out = None;
+    let mut files = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--shared" => shared = true,
+            "--no-prelude" => options.load_prelude = false,
+            "--dump-bytecode" => dump_bytecode = true,
+            "-O" => options.optimize = true,
+            "--register-vm" => options.backend = Backend::Register,
+            "--sandbox" => options.sandbox = true,
+            "--trace" => options.trace = true,
+            "--profile" => options.profile = Some(Rc::new(RefCell::new(Profile::new()))),
+            "--profile-out" => {
+                profile_out = args.next();
+                if profile_out.is_none() {
+                    printerrln!("--profile-out needs a file name");
+                    std::process::exit(1);
+                }
+            },
+            "--max-instructions" => options.limits.max_instructions = Some(number_arg(&arg, &mut args) as u64),
+            "--max-call-depth" => options.limits.max_call_depth = Some(number_arg(&arg, &mut args)),
+            "--max-stack" => options.limits.max_stack = Some(number_arg(&arg, &mut args)),
+            "--max-heap" => options.limits.max_heap_bytes = Some(number_arg(&arg, &mut args)),
+            _ => files.push(arg),
+        }
+    }
+
+    if profile_out.is_some() && options.profile.is_none() {
+        options.profile = Some(Rc::new(RefCell::new(Profile::new())));
+    }
+    if options.backend == Backend::Register && (options.trace || options.profile.is_some()) {
+        printerrln!("--trace and --profile only work on the stack machine, not with --register-vm");
+        std::process::exit(1);
+    }
+
+    // In shared mode, every file runs in order in this one engine
+    let mut shared_engine = if shared { Some(new_engine(&options)) } else { None };
+    for arg in files {
+        let contents = match read_file(&arg) {
+            Some(contents) => contents,
+            None => {
+                error_occurred = true;
+                continue;
+            },
+        };
+        // eval
+        let ok = match shared_engine {
+            Some(ref mut engine) => run_file(engine, &contents, &arg, dump_bytecode),
+            None => run_file(&mut new_engine(&options), &contents, &arg, dump_bytecode),
+        };
+        if !ok {
+            error_occurred = true;
+        }
+    }
+
+    // Profilers finish their measurements when their engines are dropped
+    drop(shared_engine);
+    if let Some(ref profile) = options.profile {
+        let profile = profile.borrow();
+        let _ = profile.write_report(&mut io::stderr());
+        if let Some(ref path) = profile_out {
+            let written = File::create(path).and_then(|mut fp| profile.write_collapsed(&mut fp));
+            if let Err(err) = written {
+                printerrln!("Could not write {}: {}", path, err);
+                error_occurred = true;
             }
         }
     }
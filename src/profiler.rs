@@ -0,0 +1,184 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use internal::CodeObject;
+use interpreter::{Hook, Interpreter, RuntimeError};
+
+/**
+ * What the profiler found out about one function. A program's top-level code counts as a function named after the
+ * program.
+ */
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    pub inclusive: Duration, /* Time spent in the function and everything it called */
+    pub exclusive: Duration, /* Time spent in the function itself */
+}
+
+/**
+ * The results of profiling. One profile can be shared by several profilers, which all add to it.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub functions: HashMap<String, FunctionProfile>,
+    pub opcodes: HashMap<&'static str, u64>, /* How many times each kind of instruction ran */
+    pub stacks: HashMap<String, Duration>, /* Exclusive time for each call stack, as `outer;inner` */
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /**
+     * Writes a readable report, with the functions that took the most time to themselves first.
+     */
+    pub fn write_report(&self, out: &mut Write) -> io::Result<()> {
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+        try!(writeln!(out, "{:>10} {:>14} {:>14}  {}", "calls", "inclusive ms", "exclusive ms", "function"));
+        for (name, function) in functions {
+            try!(writeln!(out, "{:>10} {:>14.3} {:>14.3}  {}", function.calls, millis(function.inclusive),
+                          millis(function.exclusive), name));
+        }
+
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        try!(writeln!(out, ""));
+        try!(writeln!(out, "{:>10}  {}", "executed", "instruction"));
+        for (opcode, count) in opcodes {
+            try!(writeln!(out, "{:>10}  {}", count, opcode));
+        }
+        Ok(())
+    }
+
+    /**
+     * Writes the call stacks in the collapsed format that flame graph tools read: one stack per line, with its
+     * frames separated by semicolons, followed by the time spent in it in microseconds.
+     */
+    pub fn write_collapsed(&self, out: &mut Write) -> io::Result<()> {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        for (stack, duration) in stacks {
+            let micros = duration.as_secs() * 1_000_000 + duration.subsec_nanos() as u64 / 1_000;
+            try!(writeln!(out, "{} {}", stack, micros));
+        }
+        Ok(())
+    }
+}
+
+struct Frame {
+    name: String,
+    started: Instant,
+    path_len: usize, /* The length of the stack path before this frame was added to it */
+}
+
+/**
+ * Measures where a program spends its time, adding what it finds to a shared profile. Time spent in builtins counts
+ * towards the function that called them.
+ *
+ * Whatever is still running is recorded when the profiler is dropped, so detach it before reading the profile.
+ */
+pub struct Profiler {
+    profile: Rc<RefCell<Profile>>,
+    frames: Vec<Frame>,
+    path: String, /* The names of every running function, joined with semicolons */
+    active: HashMap<String, usize>, /* How many times each function is on the call stack */
+    last: Instant, /* When time was last charged to a function */
+}
+
+impl Profiler {
+    pub fn new(profile: Rc<RefCell<Profile>>) -> Profiler {
+        Profiler {
+            profile: profile,
+            frames: vec![],
+            path: String::new(),
+            active: HashMap::new(),
+            last: Instant::now(),
+        }
+    }
+
+    /**
+     * Charges the time since the last call or return to the function that's running.
+     */
+    fn charge(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+        if let Some(frame) = self.frames.last() {
+            let mut profile = self.profile.borrow_mut();
+            profile.functions.entry(frame.name.clone()).or_insert_with(FunctionProfile::default).exclusive += elapsed;
+            *profile.stacks.entry(self.path.clone()).or_insert_with(Duration::default) += elapsed;
+        }
+    }
+
+    fn enter(&mut self, name: &str) {
+        self.charge();
+        let path_len = self.path.len();
+        if !self.path.is_empty() {
+            self.path.push(';');
+        }
+        self.path.push_str(name);
+        self.frames.push(Frame { name: name.to_string(), started: self.last, path_len: path_len });
+        *self.active.entry(name.to_string()).or_insert(0) += 1;
+        self.profile.borrow_mut().functions.entry(name.to_string()).or_insert_with(FunctionProfile::default).calls += 1;
+    }
+
+    fn leave(&mut self) {
+        self.charge();
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        self.path.truncate(frame.path_len);
+        let active = {
+            let active = self.active.get_mut(&frame.name).unwrap();
+            *active -= 1;
+            *active
+        };
+        // Recursive calls are already counted in the time of the outermost call
+        if active == 0 {
+            let mut profile = self.profile.borrow_mut();
+            profile.functions.get_mut(&frame.name).unwrap().inclusive += self.last - frame.started;
+        }
+    }
+}
+
+impl Hook for Profiler {
+    fn instruction(&mut self, _interp: &mut Interpreter, code: &CodeObject, index: usize) -> Result<(), RuntimeError> {
+        // Top-level code has no call to mark where it starts, so a new program starts whenever its code shows up
+        let new_program = match self.frames.len() {
+            0 => true,
+            1 => self.frames[0].name != code.name,
+            _ => false,
+        };
+        if new_program {
+            self.leave();
+            self.enter(&code.name);
+        }
+        *self.profile.borrow_mut().opcodes.entry(code.code[index].opcode()).or_insert(0) += 1;
+        Ok(())
+    }
+
+    fn call(&mut self, _interp: &mut Interpreter, function: &CodeObject) {
+        self.enter(&function.name);
+    }
+
+    fn ret(&mut self, _interp: &mut Interpreter, _function: &CodeObject) {
+        self.leave();
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        while !self.frames.is_empty() {
+            self.leave();
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use ast::Expression;
+use engine::{Engine, parse};
+
+/**
+ * Test files are the ones whose names end with this.
+ */
+pub const TEST_SUFFIX: &'static str = "_test.rasp";
+
+/**
+ * The result of running one `&deftest`.
+ */
+#[derive(Clone, Debug)]
+pub struct TestCase {
+    pub file: String,
+    pub name: String,
+    pub line: usize, /* The line the test is defined on */
+    pub failure: Option<Failure>, /* Why the test failed, if it did */
+}
+
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub message: String,
+    pub line: usize, /* The line the failure happened on, or 0 if it's unknown */
+    pub source: String, /* The text of that line */
+    pub output: String, /* Everything the test printed before it failed */
+}
+
+impl TestCase {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+
+    /**
+     * Gets where the test failed, as `file:line`, falling back to where it's defined.
+     */
+    pub fn location(&self) -> String {
+        match self.failure {
+            Some(ref failure) if failure.line != 0 => format!("{}:{}", self.file, failure.line),
+            _ => format!("{}:{}", self.file, self.line),
+        }
+    }
+}
+
+/**
+ * Finds the test files in the given paths. Directories are searched recursively, skipping hidden ones, and files
+ * that are named explicitly are always included. The files are returned in a stable order.
+ */
+pub fn discover(paths: &[String]) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            try!(discover_in(path, &mut files));
+        }
+        else {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+fn discover_in(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = vec![];
+    for entry in try!(fs::read_dir(dir)) {
+        entries.push(try!(entry).path());
+    }
+    entries.sort();
+    for path in entries {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("").to_string();
+        if path.is_dir() {
+            if !name.starts_with('.') {
+                try!(discover_in(&path, files));
+            }
+        }
+        else if name.ends_with(TEST_SUFFIX) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Runs every test in a file. Each test gets an engine of its own, which runs the rest of the file's top-level code
+ * before the test's body, so tests can't see each other's changes. A file that can't be parsed is reported as a
+ * single failed test named after the file.
+ */
+pub fn run_tests(source: &str, source_name: &str) -> Vec<TestCase> {
+    let exprs = match parse(source, source_name) {
+        Ok(exprs) => exprs,
+        Err(err) => return vec![TestCase {
+            file: source_name.to_string(),
+            name: source_name.to_string(),
+            line: 0,
+            failure: Some(Failure {
+                message: format!("{}", err),
+                line: 0,
+                source: String::new(),
+                output: String::new(),
+            }),
+        }],
+    };
+    let (tests, setup): (Vec<_>, Vec<_>) = exprs.into_iter().partition(|expr| match expr {
+        &Expression::TestDef(_) => true,
+        _ => false,
+    });
+    tests.iter()
+        .filter_map(|expr| match expr {
+            &Expression::TestDef(ref test) => Some(TestCase {
+                file: source_name.to_string(),
+                name: test.name.clone(),
+                line: line_of(source, test.span.start),
+                failure: run_test(&setup, &test.body, source, source_name).err(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn run_test(setup: &[Expression], body: &[Expression], source: &str, source_name: &str) -> Result<(), Failure> {
+    let mut engine = Engine::new();
+    let output = engine.capture_output();
+    let errors = engine.capture_errors();
+    let result = engine.compile_exprs(setup, source, source_name)
+        .and_then(|program| engine.run(&program))
+        .map_err(|err| format!("Setup failed: {}", err))
+        .and_then(|_| engine.compile_exprs(body, source, source_name).map_err(|err| format!("{}", err)))
+        .and_then(|program| engine.run(&program).map_err(|err| format!("{}", err)));
+    match result {
+        Ok(_) => Ok(()),
+        Err(message) => {
+            // Only code from this file has lines that mean something here; the prelude's would point elsewhere
+            let line = match engine.interpreter().error_location() {
+                Some((function, line)) if function == source_name || defines(setup, function) => line,
+                _ => 0,
+            };
+            let text = if line == 0 { "" } else { source.lines().nth(line - 1).unwrap_or("") };
+            Err(Failure {
+                message: message,
+                line: line,
+                source: text.trim().to_string(),
+                output: output.contents() + &errors.contents(),
+            })
+        },
+    }
+}
+
+fn defines(exprs: &[Expression], function: &str) -> bool {
+    exprs.iter().any(|expr| match expr {
+        &Expression::FunDef(ref fun) => fun.name == function,
+        _ => false,
+    })
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[.. offset].matches('\n').count() + 1
+}
+
+/**
+ * Writes a report for people to read: one line per test, then the details of every failure and a summary.
+ */
+pub fn write_report(out: &mut Write, cases: &[TestCase]) -> io::Result<()> {
+    for case in cases {
+        try!(writeln!(out, "test {}: {} ... {}", case.file, case.name, if case.passed() { "ok" } else { "FAILED" }));
+    }
+    let failed: Vec<_> = cases.iter().filter(|case| !case.passed()).collect();
+    if !failed.is_empty() {
+        try!(writeln!(out, "\nfailures:"));
+    }
+    for case in &failed {
+        let failure = case.failure.as_ref().unwrap();
+        try!(writeln!(out, "\n{}: {}", case.location(), case.name));
+        try!(writeln!(out, "    {}", failure.message));
+        if !failure.source.is_empty() {
+            try!(writeln!(out, "    in: {}", failure.source));
+        }
+        for line in failure.output.lines() {
+            try!(writeln!(out, "    | {}", line));
+        }
+    }
+    writeln!(out, "\n{} passed, {} failed", cases.len() - failed.len(), failed.len())
+}
+
+/**
+ * Writes a report in the Test Anything Protocol, version 13.
+ */
+pub fn write_tap(out: &mut Write, cases: &[TestCase]) -> io::Result<()> {
+    try!(writeln!(out, "TAP version 13"));
+    try!(writeln!(out, "1..{}", cases.len()));
+    for (number, case) in cases.iter().enumerate() {
+        let description = format!("{}: {}", case.file, case.name).replace('#', "\\#");
+        match case.failure {
+            None => try!(writeln!(out, "ok {} - {}", number + 1, description)),
+            Some(ref failure) => {
+                try!(writeln!(out, "not ok {} - {}", number + 1, description));
+                try!(writeln!(out, "  ---"));
+                try!(writeln!(out, "  message: {}", yaml_string(&failure.message)));
+                try!(writeln!(out, "  at: {}", yaml_string(&case.location())));
+                if !failure.source.is_empty() {
+                    try!(writeln!(out, "  source: {}", yaml_string(&failure.source)));
+                }
+                if !failure.output.is_empty() {
+                    try!(writeln!(out, "  output: {}", yaml_string(&failure.output)));
+                }
+                try!(writeln!(out, "  ..."));
+            },
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Writes a report as JUnit XML, with a test suite for each file.
+ */
+pub fn write_junit(out: &mut Write, cases: &[TestCase]) -> io::Result<()> {
+    let failures = cases.iter().filter(|case| !case.passed()).count();
+    try!(writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+    try!(writeln!(out, "<testsuites tests=\"{}\" failures=\"{}\">", cases.len(), failures));
+    let mut start = 0;
+    while start < cases.len() {
+        let file = &cases[start].file;
+        let suite: Vec<_> = cases[start ..].iter().take_while(|case| &case.file == file).collect();
+        start += suite.len();
+        let failures = suite.iter().filter(|case| !case.passed()).count();
+        try!(writeln!(out, "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">",
+                      xml_escape(file), suite.len(), failures));
+        for case in suite {
+            let attributes = format!("name=\"{}\" classname=\"{}\" file=\"{}\" line=\"{}\"",
+                                     xml_escape(&case.name), xml_escape(file), xml_escape(file), case.line);
+            match case.failure {
+                None => try!(writeln!(out, "    <testcase {}/>", attributes)),
+                Some(ref failure) => {
+                    try!(writeln!(out, "    <testcase {}>", attributes));
+                    try!(writeln!(out, "      <failure message=\"{}\">{}: {}</failure>",
+                                  xml_escape(&failure.message), xml_escape(&case.location()),
+                                  xml_escape(&failure.source)));
+                    if !failure.output.is_empty() {
+                        try!(writeln!(out, "      <system-out>{}</system-out>", xml_escape(&failure.output)));
+                    }
+                    try!(writeln!(out, "    </testcase>"));
+                },
+            }
+        }
+        try!(writeln!(out, "  </testsuite>"));
+    }
+    writeln!(out, "</testsuites>")
+}
+
+fn yaml_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,39 @@
+use std::io::Write;
+use internal::CodeObject;
+use interpreter::{Hook, Interpreter, RuntimeError};
+
+/**
+ * Logs every instruction as it runs, along with the call depth, the function it's in and the size of the value stack.
+ */
+pub struct Tracer {
+    depth: usize,
+    output: Box<Write>,
+}
+
+impl Tracer {
+    pub fn new<W: Write + 'static>(output: W) -> Tracer {
+        Tracer {
+            depth: 0,
+            output: Box::new(output),
+        }
+    }
+}
+
+impl Hook for Tracer {
+    fn instruction(&mut self, interp: &mut Interpreter, code: &CodeObject, index: usize) -> Result<(), RuntimeError> {
+        let instruction = code.format_instruction(index, interp.symbols());
+        let location = format!("{}:{}", code.name, index);
+        // Tracing is best-effort; a closed pipe shouldn't stop the program
+        let _ = writeln!(self.output, "{:>4} {:<24} stack {:<4} {}", self.depth, location, interp.stack().len(),
+                         instruction);
+        Ok(())
+    }
+
+    fn call(&mut self, _interp: &mut Interpreter, _function: &CodeObject) {
+        self.depth += 1;
+    }
+
+    fn ret(&mut self, _interp: &mut Interpreter, _function: &CodeObject) {
+        self.depth -= 1;
+    }
+}
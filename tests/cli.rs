@@ -0,0 +1,34 @@
+//! `rasp` exits with an error when a script it runs fails, or goes over a limit it was given.
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::process::Command;
+
+/**
+ * Runs a script with some extra arguments, returning whether it succeeded and what it wrote to stderr.
+ */
+fn run_with(args: &[&str], source: &str, name: &str) -> (bool, String) {
+    let path = env::temp_dir().join(name);
+    File::create(&path).and_then(|mut fp| fp.write_all(source.as_bytes())).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rasp")).args(args).arg(&path).output().unwrap();
+    (output.status.success(), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+fn run(source: &str, name: &str) -> bool {
+    run_with(&[], source, name).0
+}
+
+#[test]
+fn runtime_errors_fail_the_run() {
+    assert!(run("(&print 1)", "rasp_cli_ok.rasp"));
+    assert!(!run("(&print (- \"one\" 1))", "rasp_cli_error.rasp"));
+}
+
+#[test]
+fn max_heap_stops_a_growing_vector() {
+    let source = format!("(&def v [])\n(&fun grow () (&vec-push! v \"{}\") (grow))\n(grow)", "x".repeat(1000));
+    let (succeeded, errors) = run_with(&["--max-heap", "100000"], &source, "rasp_cli_max_heap.rasp");
+    assert!(!succeeded);
+    assert!(errors.contains("Heap size limit of 100000 bytes exceeded"), "{}", errors);
+}
@@ -0,0 +1,125 @@
+//! Compiled `.raspc` files: programs survive a round trip, and damaged or foreign files are turned away.
+
+extern crate rasp;
+
+use std::rc::Rc;
+use rasp::{Engine, Error, OutputBuffer, Program};
+use rasp::internal::{Bytecode, CodeFileError, CodeObject, FORMAT_VERSION, MAX_FUNCTION_DEPTH, MAGIC,
+                     write_code_file};
+use rasp::interpreter::SymbolTable;
+
+const SOURCE: &'static str = "\
+(&fun factorial (x)
+    (&if (== x 0) 1
+     &else (* x (factorial (- x 1)))))
+
+(&extern \"libm.so.6\" pow (double double) double)
+(&def greeting \"Hello, world!\")
+(&print greeting)
+(&print (factorial 20))
+(&print [1.5 @tru {\"k\" \"v\"}])
+";
+
+fn compiled() -> (Engine, Program, Vec<u8>) {
+    let mut engine = Engine::new();
+    let program = engine.compile(SOURCE, "roundtrip.rasp").unwrap();
+    let mut bytes = vec![];
+    engine.write_compiled(&program, &mut bytes).unwrap();
+    (engine, program, bytes)
+}
+
+fn load_error(bytes: &[u8]) -> CodeFileError {
+    match Engine::new().load_compiled(bytes) {
+        Err(Error::Load(err)) => err,
+        Err(other) => panic!("expected a load error, but got {}", other),
+        Ok(_) => panic!("expected a load error, but the file loaded"),
+    }
+}
+
+/**
+ * Gets the extern declarations in a program, in order.
+ */
+fn externs(code: &CodeObject) -> Vec<(String, usize, usize)> {
+    code.code.iter()
+        .filter_map(|b| match b {
+            &Bytecode::Extern(ref decl) => Some((decl.name.clone(), decl.span.start, decl.span.end)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn round_trips_code() {
+    let (engine, program, bytes) = compiled();
+    let mut loader = Engine::new();
+    let loaded = loader.load_compiled(&bytes).unwrap();
+    assert_eq!(loader.disassemble(&loaded), engine.disassemble(&program));
+    assert_eq!(externs(&loaded.code), externs(&program.code));
+    assert!(externs(&loaded.code)[0].1 > 0, "the extern's span should be kept");
+}
+
+#[test]
+fn loaded_code_runs_the_same() {
+    let (mut engine, program, bytes) = compiled();
+    let expected = engine.capture_output();
+    engine.run(&program).unwrap();
+
+    let mut loader = Engine::new();
+    let output = loader.capture_output();
+    let loaded = loader.load_compiled(&bytes).unwrap();
+    loader.run(&loaded).unwrap();
+    assert_eq!(output.contents(), expected.contents());
+    assert_eq!(output.contents(), "Hello, world!\n2432902008176640000\n[1.5 true {k v}]\n");
+}
+
+#[test]
+fn rejects_bad_magic() {
+    let (_, _, mut bytes) = compiled();
+    bytes[0] = b'X';
+    assert_eq!(load_error(&bytes), CodeFileError::NotCompiled);
+    assert_eq!(load_error(SOURCE.as_bytes()), CodeFileError::NotCompiled);
+}
+
+#[test]
+fn rejects_other_versions() {
+    let (_, _, mut bytes) = compiled();
+    let found = FORMAT_VERSION + 1;
+    bytes[MAGIC.len()] = found as u8;
+    bytes[MAGIC.len() + 1] = (found >> 8) as u8;
+    assert_eq!(load_error(&bytes), CodeFileError::VersionMismatch { found: found, expected: FORMAT_VERSION });
+}
+
+#[test]
+fn rejects_corrupt_payloads() {
+    let (_, _, mut bytes) = compiled();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    assert_eq!(load_error(&bytes), CodeFileError::ChecksumMismatch);
+}
+
+#[test]
+fn rejects_truncated_files() {
+    let (_, _, bytes) = compiled();
+    assert_eq!(load_error(&bytes[.. bytes.len() - 1]), CodeFileError::Truncated);
+    assert_eq!(load_error(&bytes[.. MAGIC.len() + 1]), CodeFileError::Truncated);
+}
+
+#[test]
+fn rejects_deeply_nested_functions() {
+    // Hand-built, since the compiler won't nest functions like this
+    let nested = |depth: usize| {
+        let mut code = CodeObject::new("f".to_string(), vec![], vec![], vec![], vec![]);
+        for _ in 0 .. depth {
+            let inner = Rc::new(code);
+            code = CodeObject::new("f".to_string(), vec![], vec![], vec![Bytecode::FunDef(inner)], vec![0]);
+        }
+        let mut bytes = vec![];
+        write_code_file(&code, &SymbolTable::new(), &mut bytes).unwrap();
+        bytes
+    };
+    assert!(Engine::new().load_compiled(&nested(MAX_FUNCTION_DEPTH)).is_ok());
+    match load_error(&nested(MAX_FUNCTION_DEPTH + 1)) {
+        CodeFileError::Corrupt(ref msg) if msg.contains("nested") => {},
+        other => panic!("expected the nesting to be rejected, but got {:?}", other),
+    }
+}
@@ -0,0 +1,93 @@
+//! The debugger stops where it's asked to, and can look around while it's stopped. Commands are read from a string.
+
+extern crate rasp;
+
+use std::io::Cursor;
+use rasp::{Engine, OutputBuffer};
+use rasp::debugger::{Breakpoint, Debugger};
+
+const SOURCE: &'static str = "\
+(&fun square (x)
+    (* x x))
+(&fun sum-squares (a b)
+    (- (square a) (- 0 (square b))))
+(&print (sum-squares 3 4))
+(&print 1)
+";
+
+/**
+ * Runs `SOURCE` under the debugger, returning what the debugger wrote and what the program printed.
+ */
+fn debug(commands: &str, breakpoints: Vec<Breakpoint>) -> (String, String) {
+    let mut engine = Engine::new();
+    let printed = engine.capture_output();
+    let program = engine.compile(SOURCE, "test.rasp").unwrap();
+    let output = OutputBuffer::new();
+    let mut debugger = Debugger::new(&program.code, "test.rasp", SOURCE, Cursor::new(commands.to_string()),
+                                     output.clone());
+    for breakpoint in breakpoints {
+        debugger.add_breakpoint(breakpoint);
+    }
+    engine.interpreter().set_hook(Some(Box::new(debugger)));
+    let _ = engine.run(&program);
+    (output.contents(), printed.contents())
+}
+
+/**
+ * Gets the places the debugger stopped, like `4 in sum-squares`.
+ */
+fn stops(output: &str) -> Vec<String> {
+    output.split("(rasp) ")
+        .flat_map(|chunk| chunk.lines())
+        .filter(|line| line.starts_with("test.rasp:"))
+        .map(|line| line.splitn(3, ':').nth(1).unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn step_goes_into_calls() {
+    let (output, printed) = debug("s\ns\ns\ns\nc\n", vec![]);
+    assert_eq!(stops(&output), ["1 in test.rasp", "3 in test.rasp", "5 in test.rasp", "4 in sum-squares",
+                                "2 in square"]);
+    assert_eq!(printed, "25\n1\n");
+}
+
+#[test]
+fn next_goes_over_calls() {
+    let (output, printed) = debug("n\nn\nn\nc\n", vec![]);
+    assert_eq!(stops(&output), ["1 in test.rasp", "3 in test.rasp", "5 in test.rasp", "6 in test.rasp"]);
+    assert_eq!(printed, "25\n1\n");
+}
+
+#[test]
+fn finish_stops_in_the_caller() {
+    let (output, _) = debug("s\ns\ns\ns\nf\nf\nc\n", vec![]);
+    assert_eq!(stops(&output), ["1 in test.rasp", "3 in test.rasp", "5 in test.rasp", "4 in sum-squares",
+                                "2 in square", "4 in sum-squares", "5 in test.rasp"]);
+}
+
+#[test]
+fn breaks_at_a_line() {
+    let (output, printed) = debug("b 4\nc\nc\n", vec![]);
+    assert!(output.contains("Breakpoint 1 set"), "{}", output);
+    assert_eq!(stops(&output), ["1 in test.rasp", "4 in sum-squares"]);
+    assert_eq!(printed, "25\n1\n");
+}
+
+#[test]
+fn breaks_when_a_function_is_called() {
+    let (output, _) = debug("c\nc\nc\n", vec![Breakpoint::Function("square".to_string())]);
+    assert_eq!(stops(&output), ["1 in test.rasp", "2 in square", "2 in square"]);
+}
+
+#[test]
+fn prints_in_the_paused_frame() {
+    let (output, printed) = debug("c\np x\np (* x 10)\nlocals\nbt\nq\n",
+                                  vec![Breakpoint::Function("square".to_string())]);
+    assert!(output.contains("(rasp) 3\n"), "{}", output);
+    assert!(output.contains("(rasp) 30\n"), "{}", output);
+    assert!(output.contains("(rasp) x = 3\n"), "{}", output);
+    assert!(output.contains("#2 square at line 2\n#1 sum-squares at line 4\n#0 test.rasp at line 5\n"), "{}", output);
+    // Quitting stops the program where it is
+    assert_eq!(printed, "");
+}
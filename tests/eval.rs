@@ -0,0 +1,96 @@
+//! Code read as data means the same thing on both machines, and `&eval` compiles data without writing it out as text.
+
+extern crate rasp;
+
+use rasp::{Atom, Engine};
+use rasp::interpreter::Backend;
+
+fn run_with(engine: &mut Engine, source: &str) -> String {
+    let output = engine.capture_output();
+    match engine.eval(source) {
+        Ok(_) => output.contents(),
+        Err(err) => format!("{}error: {}", output.contents(), err),
+    }
+}
+
+fn run(source: &str, backend: Backend) -> String {
+    let mut engine = Engine::new();
+    engine.set_backend(backend);
+    run_with(&mut engine, source)
+}
+
+/**
+ * Runs source on both machines, checking that they agree, and returns what it printed.
+ */
+fn run_both(source: &str) -> String {
+    let stack = run(source, Backend::Stack);
+    assert_eq!(run(source, Backend::Register), stack, "the machines disagree on:\n{}", source);
+    stack
+}
+
+#[test]
+fn symbols_are_true() {
+    assert_eq!(run_both("(&print (&if (&car (&read \"(a)\")) 1 &else 2))"), "1\n");
+    assert_eq!(run_both("(&assert (&car (&read \"(a)\")))\n(&print \"ok\")"), "ok\n");
+    assert_eq!(run_both("(&print (&filter \"&car\" (&read \"((a) (b))\")))"), "((a) (b))\n");
+}
+
+#[test]
+fn symbols_compare_by_name() {
+    assert_eq!(run_both("(&print (== (&car (&read \"(a)\")) (&car (&read \"(a b)\"))))"), "true\n");
+    assert_eq!(run_both("(&print (== (&car (&read \"(a)\")) (&car (&read \"(b)\"))))"), "false\n");
+    assert_eq!(run_both("(&print (== (&car (&read \"(a)\")) \"a\"))"), "false\n");
+    assert_eq!(run_both("(&assert-equal (&read \"(f x)\") (&read \"(f x)\"))\n(&print \"ok\")"), "ok\n");
+}
+
+#[test]
+fn evaluates_every_kind_of_form() {
+    let source = "(&eval (&read \"(&fun square (x) (* x x))\"))\n\
+                  (&eval (&read \"(&def answer (square 6))\"))\n\
+                  (&eval (&read \"(&set! answer (- answer 2))\"))\n\
+                  (&print (&eval (&read \"(&if (== answer 34) [answer {1 2}] &else @nah)\")))\n\
+                  (&eval (&read \"(&fun nothing () 0)\"))\n\
+                  (&print (&eval (&read \"(nothing)\")))";
+    assert_eq!(run_both(source), "[34 {1 2}]\n0\n");
+}
+
+#[test]
+fn evaluates_strings_that_source_text_cant_hold() {
+    for text in &["two\nlines", "a \"quoted\" word"] {
+        let mut engine = Engine::new();
+        engine.set_global("text", Atom::StrLit(text.to_string()));
+        let source = "(&def code (&read \"(&print 0)\"))\n(&set-car! (&cdr code) text)\n(&eval code)";
+        assert_eq!(run_with(&mut engine, source), format!("{}\n", text));
+    }
+}
+
+#[test]
+fn evaluates_negative_numbers() {
+    let times = "(&def code (&read \"(* 0 2)\"))\n(&set-car! (&cdr code) (- 0 5))\n(&print (&eval code))";
+    assert_eq!(run_both(times), "-10\n");
+    assert_eq!(run_both("(&print (&eval (- 0 5)))"), "-5\n");
+}
+
+#[test]
+fn rejects_malformed_forms() {
+    let bad_if = run_both("(&eval (&cons (&car (&read \"(&if 1 2)\")) @nah))");
+    assert!(bad_if.contains("isn't a well-formed &if form"), "{}", bad_if);
+    let keyword = run_both("(&eval (&car (&read \"(&if 1 2)\")))");
+    assert!(keyword.contains("it's a keyword"), "{}", keyword);
+    let bad_name = run_both("(&eval (&read \"(&def \\\"x\\\" 1)\"))");
+    assert!(bad_name.starts_with("error:"), "{}", bad_name);
+    let cyclic = run_both("(&def code (&read \"(f 1)\"))\n(&set-cdr! (&cdr code) code)\n(&eval code)");
+    assert!(cyclic.contains("contains itself"), "{}", cyclic);
+    let boxed = run_both("(&eval (&box 1))");
+    assert!(boxed.contains("boxes aren't code"), "{}", boxed);
+}
+
+#[test]
+fn evaluates_long_lists() {
+    // A function whose body is the numbers up to 100000, built as data rather than read from text
+    let source = "(&def code (&read \"(&fun last () 0)\"))\n\
+                  (&set-cdr! (&cdr (&cdr code)) (&range 0 100000))\n\
+                  (&eval code)\n\
+                  (&print (&eval (&read \"(last)\")))";
+    assert_eq!(run_both(source), "99999\n");
+}
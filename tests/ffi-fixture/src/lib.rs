@@ -0,0 +1,35 @@
+//! A small C library for the `&extern` tests to load. `tests/ffi.rs` builds it before it runs.
+
+use std::os::raw::{c_double, c_int, c_long};
+
+static mut LAST: c_long = 0;
+
+#[no_mangle]
+pub extern "C" fn add3(a: c_long, b: c_long, c: c_long) -> c_long {
+    a + b + c
+}
+
+#[no_mangle]
+pub extern "C" fn add_ints(a: c_int, b: c_int) -> c_int {
+    a.wrapping_add(b)
+}
+
+#[no_mangle]
+pub extern "C" fn sum4(a: c_double, b: c_double, c: c_double, d: c_double) -> c_double {
+    a + b + c + d
+}
+
+#[no_mangle]
+pub extern "C" fn to_long(x: c_double) -> c_long {
+    x as c_long
+}
+
+#[no_mangle]
+pub extern "C" fn remember(x: c_long) {
+    unsafe { LAST = x; }
+}
+
+#[no_mangle]
+pub extern "C" fn last() -> c_long {
+    unsafe { LAST }
+}
@@ -0,0 +1,129 @@
+//! Calls into a shared library with `&extern`. The library is the `cdylib` in `tests/ffi-fixture`, which is built the
+//! first time a test needs it.
+
+extern crate rasp;
+
+use std::env;
+use std::process::Command;
+use std::sync::Once;
+use rasp::{Atom, Engine, Error};
+
+static BUILD: Once = Once::new();
+
+/**
+ * Builds the fixture library if it hasn't been built yet, returning its path.
+ */
+fn fixture() -> String {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let target_dir = format!("{}/target/ffi-fixture", root);
+    BUILD.call_once(|| {
+        let cargo = env::var("CARGO").unwrap_or("cargo".to_string());
+        let status = Command::new(cargo)
+            .arg("build")
+            .arg("--manifest-path").arg(format!("{}/tests/ffi-fixture/Cargo.toml", root))
+            .arg("--target-dir").arg(&target_dir)
+            .status()
+            .expect("could not run cargo to build the fixture library");
+        assert!(status.success(), "the fixture library failed to build");
+    });
+    format!("{}/debug/libffi_fixture.so", target_dir)
+}
+
+/**
+ * Creates an engine with some of the fixture's functions declared.
+ */
+fn engine(decls: &str) -> Engine {
+    let mut engine = Engine::new();
+    let source = decls.replace("LIB", &fixture());
+    engine.eval(&source).unwrap();
+    engine
+}
+
+fn eval(engine: &mut Engine, source: &str) -> Atom {
+    engine.eval(source).unwrap().expect("expected a value")
+}
+
+fn runtime_error(source: &str) -> String {
+    let mut engine = Engine::new();
+    match engine.eval(&source.replace("LIB", &fixture())) {
+        Err(Error::Runtime(msg)) => msg,
+        other => panic!("expected a runtime error, but got {:?}", other),
+    }
+}
+
+#[test]
+fn calls_with_ints() {
+    let mut engine = engine("(&extern \"LIB\" add3 (long long long) long)");
+    match eval(&mut engine, "(add3 1 2 39)") {
+        Atom::IntLit(42) => {},
+        other => panic!("expected 42, but got {}", other),
+    }
+}
+
+#[test]
+fn calls_with_32_bit_ints() {
+    let mut engine = engine("(&extern \"LIB\" add_ints (int int) int)");
+    match eval(&mut engine, "(add_ints 1 (- 0 5))") {
+        Atom::IntLit(-4) => {},
+        other => panic!("expected -4, but got {}", other),
+    }
+    match eval(&mut engine, "(add_ints 2147483647 1)") {
+        Atom::IntLit(-2147483648) => {},
+        other => panic!("expected the sum to wrap around at 32 bits, but got {}", other),
+    }
+}
+
+#[test]
+fn rejects_ints_that_do_not_fit() {
+    let msg = runtime_error("(&extern \"LIB\" add_ints (int int) int)\n(add_ints 4294967296 1)");
+    assert!(msg.contains("doesn't fit in a C int"), "{}", msg);
+}
+
+#[test]
+fn calls_with_doubles_and_converts_ints() {
+    let mut engine = engine("(&extern \"LIB\" sum4 (double double double double) double)");
+    match eval(&mut engine, "(sum4 1 2.5 3 3.5)") {
+        Atom::DubLit(d) if d == 10.0 => {},
+        other => panic!("expected 10.0, but got {}", other),
+    }
+}
+
+#[test]
+fn returns_a_different_type_than_it_takes() {
+    let mut engine = engine("(&extern \"LIB\" to_long (double) long)");
+    match eval(&mut engine, "(to_long 3.75)") {
+        Atom::IntLit(3) => {},
+        other => panic!("expected 3, but got {}", other),
+    }
+}
+
+#[test]
+fn calls_void_and_nullary_functions() {
+    let mut engine = engine("(&extern \"LIB\" remember (long) void)\n(&extern \"LIB\" last () long)");
+    match eval(&mut engine, "(remember 7)") {
+        Atom::IntLit(0) => {},
+        other => panic!("expected a void call to give 0, but got {}", other),
+    }
+    match eval(&mut engine, "(last)") {
+        Atom::IntLit(7) => {},
+        other => panic!("expected 7, but got {}", other),
+    }
+}
+
+#[test]
+fn rejects_too_many_arguments() {
+    let msg = runtime_error("(&extern \"LIB\" add3 (long long long long long) long)");
+    assert!(msg.contains("at most 4"), "{}", msg);
+}
+
+#[test]
+fn rejects_mixed_argument_types() {
+    let msg = runtime_error("(&extern \"LIB\" add3 (long double long) long)");
+    assert!(msg.contains("must all be the same type"), "{}", msg);
+}
+
+#[test]
+fn rejects_missing_symbols() {
+    let msg = runtime_error("(&extern \"LIB\" not_there () long)");
+    assert!(msg.contains("Could not find symbol not_there"), "{}", msg);
+}
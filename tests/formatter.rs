@@ -0,0 +1,75 @@
+//! Formatting code that's already formatted leaves it alone.
+
+extern crate rasp;
+
+use std::fs::File;
+use std::io::Read;
+use rasp::formatter::{FormatOptions, format_source};
+
+fn format(source: &str, options: &FormatOptions) -> String {
+    format_source(source, "<test>", options).unwrap()
+}
+
+/**
+ * Formats source twice, checking that the second pass doesn't change anything, and returns the first pass.
+ */
+fn assert_idempotent_with(source: &str, options: &FormatOptions) -> String {
+    let once = format(source, options);
+    let twice = format(&once, options);
+    assert_eq!(twice, once, "formatting again changed the code");
+    once
+}
+
+fn assert_idempotent(source: &str) -> String {
+    assert_idempotent_with(source, &FormatOptions::default())
+}
+
+fn read(path: &str) -> String {
+    let mut source = String::new();
+    File::open(path).and_then(|mut fp| fp.read_to_string(&mut source)).unwrap();
+    source
+}
+
+#[test]
+fn example_files_are_already_formatted() {
+    for path in &["factorial.rasp", "src/prelude.rasp", "benches/recursion.rasp", "benches/strings.rasp"] {
+        let source = read(path);
+        assert_eq!(assert_idempotent(&source), source, "{} isn't formatted", path);
+    }
+}
+
+#[test]
+fn narrow_layouts_are_stable() {
+    let narrow = FormatOptions { indent: 2, width: 30 };
+    for path in &["factorial.rasp", "benches/recursion.rasp", "benches/strings.rasp"] {
+        assert_idempotent_with(&read(path), &narrow);
+    }
+}
+
+#[test]
+fn messy_code_is_stable() {
+    let once = assert_idempotent("(&fun   f (x)(&if (== x 0) 1 &else\n\n(* x (f (- x 1)))))   (&print   (f 5))");
+    assert!(once.contains("&else"), "{}", once);
+}
+
+#[test]
+fn trailing_comments_are_stable() {
+    let once = assert_idempotent("(&print 1) ; one\n(&print 2)    ;two\n; last\n");
+    assert!(once.contains("; one") && once.contains(";two") && once.contains("; last"), "{}", once);
+}
+
+#[test]
+fn comments_inside_forms_are_stable() {
+    let source = "(&fun f (x) ; takes x\n    ; says hello\n    (&print \"hello\") ; hi\n    (* x ; times\n 2))\n";
+    let once = assert_idempotent(source);
+    for comment in &["; takes x", "; says hello", "; hi", "; times"] {
+        assert!(once.contains(comment), "lost `{}' in:\n{}", comment, once);
+    }
+}
+
+#[test]
+fn runs_of_blank_lines_are_stable() {
+    let once = assert_idempotent("(&print 1)\n\n\n\n(&print 2)\n\n; comment\n\n\n\n(&print 3)\n\n\n");
+    assert!(!once.contains("\n\n\n"), "{:?}", once);
+    assert!(once.contains("\n\n"), "{:?}", once);
+}
@@ -0,0 +1,159 @@
+//! Heap values print the LISP way, comparing them follows their structure, including values that contain
+//! themselves, and errors print them the way `&print` does.
+
+extern crate rasp;
+
+use rasp::{Atom, Engine};
+
+fn eval(source: &str) -> bool {
+    match Engine::new().eval(source).unwrap() {
+        Some(Atom::BoolLit(b)) => b,
+        other => panic!("Expected a boolean, but got {:?}", other),
+    }
+}
+
+#[test]
+fn compares_lists_by_contents() {
+    assert!(eval("(== (&cons 1 (&cons 2 @nah)) (&cons 1 (&cons 2 @nah)))"));
+    assert!(!eval("(== (&cons 1 (&cons 2 @nah)) (&cons 1 (&cons 3 @nah)))"));
+    assert!(!eval("(== (&cons 1 @nah) (&cons 1 (&cons 2 @nah)))"));
+}
+
+#[test]
+fn compares_cyclic_lists() {
+    let setup = "(&def a (&cons 1 (&cons 2 @nah)))\n(&set-cdr! (&cdr a) a)\n\
+                 (&def b (&cons 1 (&cons 2 @nah)))\n(&set-cdr! (&cdr b) b)\n\
+                 (&def c (&cons 1 (&cons 3 @nah)))\n(&set-cdr! (&cdr c) c)\n";
+    assert!(eval(&format!("{}(== a b)", setup)));
+    assert!(!eval(&format!("{}(== a c)", setup)));
+    assert!(!eval(&format!("{}(== a (&cons 1 (&cons 2 @nah)))", setup)));
+}
+
+#[test]
+fn compares_cyclic_containers() {
+    let vectors = "(&def v [1 2])\n(&vec-set! v 1 v)\n(&def w [1 2])\n(&vec-set! w 1 w)\n(== v w)";
+    assert!(eval(vectors));
+    let dicts = "(&def d {\"a\" 1})\n(&put d \"a\" d)\n(&def e {\"a\" 1})\n(&put e \"a\" e)\n(== d e)";
+    assert!(eval(dicts));
+    let boxes = "(&def x (&box 0))\n(&set-box! x x)\n(&def y (&box 0))\n(&set-box! y y)\n(== x y)";
+    assert!(eval(boxes));
+}
+
+#[test]
+fn assert_equal_on_cyclic_lists_fails_cleanly() {
+    let source = "(&def a (&cons 1 @nah))\n(&set-cdr! a a)\n(&def b (&cons 2 @nah))\n(&set-cdr! b b)\n\
+                  (&assert-equal a b)";
+    assert!(Engine::new().eval(source).is_err());
+}
+
+#[test]
+fn compares_long_lists() {
+    assert!(eval("(== (&range 0 200000) (&range 0 200000))"));
+}
+
+fn error(source: &str) -> String {
+    format!("{}", Engine::new().eval(source).unwrap_err())
+}
+
+#[test]
+fn errors_print_heap_values() {
+    assert!(error("(&vec-ref {\"a\" 1} 0)").contains("Expected a vector, but got `{a 1}'"));
+    assert!(error("(&vec-ref [1 2] [3])").contains("Expected an index, but got `[3]'"));
+    assert!(error("(&car [1 2])").contains("Expected a pair, but got `[1 2]'"));
+    assert!(error("(&unbox (&cons 1 @nah))").contains("Expected a box, but got `(1)'"));
+    assert!(error("(&put {} [1] 2)").contains("but got `[1]'"));
+    let sorting = error("(&sort [[1] [2]])");
+    assert!(sorting.contains("Can't compare") && sorting.contains("`[1]'") && sorting.contains("`[2]'"), "{}", sorting);
+}
+
+#[test]
+fn sequence_items_outlive_callbacks_that_drop_them() {
+    let setup = "(&def v [(&cons 1 @nah) (&cons 2 @nah) (&cons 3 @nah)])\n\
+                 (&fun take (x) (&vec-pop! v) (&gc) (&car x))\n\
+                 (&fun keep (x) (&vec-pop! v) (&gc) (&pair? x))\n";
+    for call in &["(&map \"take\" v)", "(&filter \"keep\" v)", "(&all? \"keep\" v)", "(&for-each \"take\" v)"] {
+        let source = format!("{}{}", setup, call);
+        if let Err(err) = Engine::new().eval(&source) {
+            panic!("{} failed: {}", call, err);
+        }
+    }
+    assert!(eval(&format!("{}(== (&map \"take\" v) [1 2 3])", setup)));
+}
+
+#[test]
+fn prints_proper_lists_without_a_tail() {
+    let mut engine = Engine::new();
+    let output = engine.capture_output();
+    engine.eval("(&print (&cons 1 (&cons 2 @nah)))\n(&print (&cons 1 2))\n(&print (&cons (&cons 1 @nah) @nah))")
+        .unwrap();
+    assert_eq!(output.contents(), "(1 2)\n(1 . 2)\n((1))\n");
+}
+
+#[test]
+fn prints_long_lists() {
+    let mut engine = Engine::new();
+    let output = engine.capture_output();
+    engine.eval("(&print (&range 0 200000))").unwrap();
+    let printed = output.contents();
+    assert!(printed.starts_with("(0 1 2 "), "{}", &printed[.. 20]);
+    assert!(printed.ends_with(" 199998 199999)\n"));
+}
+
+#[test]
+fn cuts_off_values_that_contain_themselves() {
+    let mut engine = Engine::new();
+    let output = engine.capture_output();
+    engine.eval("(&def a (&cons 1 (&cons 2 @nah)))\n(&set-cdr! (&cdr a) a)\n(&print a)\n\
+                 (&def v [1 2])\n(&vec-set! v 1 [v v])\n(&print v)")
+        .unwrap();
+    assert_eq!(output.contents(), "(1 2 . ...)\n[1 [... ...]]\n");
+}
+
+#[test]
+fn changing_objects_changes_the_heap_size() {
+    let mut engine = Engine::new();
+    engine.eval("(&def v [])\n(&def d {})\n(&def b (&box 0))\n(&def p (&cons 0 0))").unwrap();
+    let before = engine.gc_stats().bytes;
+    let text = "x".repeat(1000);
+    engine.eval(&format!("(&vec-push! v \"{}\")\n(&put d \"{}\" 1)\n(&set-box! b \"{}\")\n(&set-car! p \"{}\")",
+                         text, text, text, text))
+        .unwrap();
+    let grown = engine.gc_stats().bytes;
+    assert!(grown >= before + 4000, "{} bytes before, {} after", before, grown);
+    engine.eval(&format!("(&vec-pop! v)\n(&remove d \"{}\")\n(&set-box! b 0)\n(&set-car! p 0)", text)).unwrap();
+    let shrunk = engine.gc_stats().bytes;
+    assert!(shrunk < before + 1000, "{} bytes before, {} after shrinking", before, shrunk);
+}
+
+#[test]
+fn sorts_with_comparators_that_arent_orders() {
+    let mut engine = Engine::new();
+    engine.eval("(&fun always (a b) @tru)").unwrap();
+    engine.eval("(&def sorted (&sort-by \"always\" (&range 0 100)))").unwrap();
+    match engine.eval("(&fold \"+\" 0 sorted)").unwrap() {
+        Some(Atom::IntLit(sum)) => assert_eq!(sum, 4950),
+        other => panic!("Expected a number, but got {:?}", other),
+    }
+    engine.eval("(&fun broken (a b) (&car a))").unwrap();
+    let err = format!("{}", engine.eval("(&sort-by \"broken\" [3 1 2])").unwrap_err());
+    assert!(err.contains("Expected a pair"), "{}", err);
+}
+
+#[test]
+fn sorts_stably() {
+    // Zeros go before everything else, and the rest are all equal
+    let source = "(&fun by-car (a b) (&if (== (&car a) 0) (&if (== (&car b) 0) @nah &else @tru) &else @nah))\n\
+                  (== (&sort-by \"by-car\" [(&cons 1 \"b\") (&cons 0 \"x\") (&cons 1 \"a\")])\n\
+                      [(&cons 0 \"x\") (&cons 1 \"b\") (&cons 1 \"a\")])";
+    assert!(eval(source));
+    assert!(eval("(== (&sort [3 1.5 2 0.5]) [0.5 1.5 2 3])"));
+}
+
+#[test]
+fn huge_ranges_fail_before_allocating() {
+    let err = error("(&range 0 9223372036854775807)");
+    assert!(err.contains("Can't make a range of 9223372036854775807 items"), "{}", err);
+    let err = error("(&range (- 0 9223372036854775807) 9223372036854775807)");
+    assert!(err.contains("Can't make a range"), "{}", err);
+    assert!(eval("(== (&range 5 0) @nah)"));
+}
@@ -0,0 +1,32 @@
+//! The linter warns about suspicious code without getting in the way of the compiler's errors.
+
+extern crate rasp;
+
+use rasp::{Engine, Error, parse};
+use rasp::interpreter::Linter;
+
+fn lint(source: &str) -> Vec<String> {
+    Linter::new().lint(&parse(source, "test.rasp").unwrap(), source, "test.rasp")
+}
+
+#[test]
+fn warns_about_unused_parameters() {
+    assert_eq!(lint("(&fun f (x y) x)"), ["test.rasp:1:1: Parameter y of function f is never used"]);
+}
+
+#[test]
+fn nested_functions_are_checked_separately() {
+    let warnings = lint("(&fun f (x) (&fun g (y) 1) x)");
+    assert_eq!(warnings, ["test.rasp:1:13: Parameter y of function g is never used"]);
+}
+
+#[test]
+fn nested_functions_are_compile_errors() {
+    match Engine::new().eval("(&fun f (x) (&fun g (y) y) x)") {
+        Err(Error::Compile(errors)) => {
+            let errors = errors.join("\n");
+            assert!(errors.contains("Function definitions are not yet allowed below top level"), "{}", errors);
+        },
+        other => panic!("expected a compile error, but got {:?}", other),
+    }
+}
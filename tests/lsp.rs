@@ -0,0 +1,207 @@
+//! The language server, driven one JSON-RPC message at a time, and over a stream the way an editor runs it.
+
+extern crate rasp;
+extern crate rustc_serialize;
+
+use std::io::Cursor;
+use rasp::lsp::{LanguageServer, read_message, serve};
+use rustc_serialize::json::Json;
+
+const URI: &'static str = "file:///test.rasp";
+
+fn json(text: &str) -> Json {
+    Json::from_str(text).unwrap()
+}
+
+fn open(server: &mut LanguageServer, text: &str) -> Vec<Json> {
+    let message = format!(
+        r#"{{"jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": {{"textDocument": {{"uri": "{}", "text": {}}}}}}}"#,
+        URI, Json::String(text.to_string()));
+    server.handle(&json(&message))
+}
+
+fn request(server: &mut LanguageServer, method: &str, params: &str) -> Json {
+    let message = format!(r#"{{"jsonrpc": "2.0", "id": 1, "method": "{}", "params": {}}}"#, method, params);
+    let mut replies = server.handle(&json(&message));
+    assert_eq!(replies.len(), 1);
+    replies.remove(0)
+}
+
+fn at(line: u64, character: u64) -> String {
+    format!(r#"{{"textDocument": {{"uri": "{}"}}, "position": {{"line": {}, "character": {}}}}}"#, URI, line, character)
+}
+
+fn diagnostics(notification: &Json) -> Vec<(u64, String)> {
+    notification.find_path(&["params", "diagnostics"]).unwrap().as_array().unwrap().iter()
+        .map(|d| {
+            let message = d.find("message").and_then(Json::as_string).unwrap().to_string();
+            (d.find("severity").and_then(Json::as_u64).unwrap(), message)
+        })
+        .collect()
+}
+
+fn error_code(response: &Json) -> i64 {
+    response.find_path(&["error", "code"]).and_then(Json::as_i64).unwrap()
+}
+
+#[test]
+fn initialize_lists_capabilities() {
+    let mut server = LanguageServer::new();
+    let response = request(&mut server, "initialize", "{}");
+    assert_eq!(response.find("id"), Some(&Json::U64(1)));
+    let capabilities = response.find_path(&["result", "capabilities"]).unwrap();
+    assert_eq!(capabilities.find("hoverProvider"), Some(&Json::Boolean(true)));
+    assert_eq!(capabilities.find("definitionProvider"), Some(&Json::Boolean(true)));
+}
+
+#[test]
+fn clean_documents_have_no_diagnostics() {
+    let mut server = LanguageServer::new();
+    let replies = open(&mut server, "(&fun double (x) (* 2 x))\n(&print (double 2))\n");
+    assert_eq!(replies.len(), 1);
+    assert_eq!(diagnostics(&replies[0]), vec![]);
+}
+
+#[test]
+fn parse_errors_are_reported() {
+    let mut server = LanguageServer::new();
+    let replies = open(&mut server, "(&print 1");
+    let found = diagnostics(&replies[0]);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].0, 1);
+}
+
+#[test]
+fn lint_warnings_are_reported() {
+    let mut server = LanguageServer::new();
+    let replies = open(&mut server, "(nowhere 1)\n");
+    let found = diagnostics(&replies[0]);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].0, 2);
+    assert!(found[0].1.contains("nowhere"), "{}", found[0].1);
+}
+
+#[test]
+fn nested_functions_are_reported() {
+    let mut server = LanguageServer::new();
+    let replies = open(&mut server, "(&fun f (x) (&fun g (y) y) x)\n");
+    let found = diagnostics(&replies[0]);
+    assert!(found.iter().any(|&(severity, ref message)| severity == 1
+                             && message.contains("Function definitions are not yet allowed below top level")),
+            "{:?}", found);
+}
+
+#[test]
+fn changes_replace_the_document() {
+    let mut server = LanguageServer::new();
+    open(&mut server, "(&print 1");
+    let change = format!(
+        r#"{{"jsonrpc": "2.0", "method": "textDocument/didChange",
+            "params": {{"textDocument": {{"uri": "{}"}}, "contentChanges": [{{"text": "(&print 1)"}}]}}}}"#,
+        URI);
+    let replies = server.handle(&json(&change));
+    assert_eq!(diagnostics(&replies[0]), vec![]);
+}
+
+#[test]
+fn finds_definitions() {
+    let mut server = LanguageServer::new();
+    open(&mut server, "(&fun double (x) (* 2 x))\n(double 2)\n");
+    let response = request(&mut server, "textDocument/definition", &at(1, 3));
+    let locations = response.find("result").unwrap().as_array().unwrap();
+    assert_eq!(locations.len(), 1);
+    assert_eq!(locations[0].find("uri").and_then(Json::as_string), Some(URI));
+    assert_eq!(locations[0].find_path(&["range", "start", "line"]).and_then(Json::as_u64), Some(0));
+}
+
+#[test]
+fn hover_shows_parameters() {
+    let mut server = LanguageServer::new();
+    open(&mut server, "(&fun double (x) (* 2 x))\n(double 2)\n");
+    let response = request(&mut server, "textDocument/hover", &at(1, 3));
+    let value = response.find_path(&["result", "contents", "value"]).and_then(Json::as_string).unwrap();
+    assert!(value.contains("(double x)"), "{}", value);
+    let response = request(&mut server, "textDocument/hover", &at(1, 9));
+    assert_eq!(response.find("result"), Some(&Json::Null));
+}
+
+#[test]
+fn completes_every_kind_of_function() {
+    let mut server = LanguageServer::new();
+    open(&mut server, "(&fun double (x) (* 2 x))\n");
+    let response = request(&mut server, "textDocument/completion", &at(0, 0));
+    let labels: Vec<_> = response.find("result").unwrap().as_array().unwrap().iter()
+        .map(|item| item.find("label").and_then(Json::as_string).unwrap().to_string())
+        .collect();
+    for name in &["&car", "+", "double"] {
+        assert!(labels.iter().any(|label| label == name), "{} wasn't offered", name);
+    }
+}
+
+#[test]
+fn unknown_methods_are_errors() {
+    let mut server = LanguageServer::new();
+    assert_eq!(error_code(&request(&mut server, "textDocument/rename", "{}")), -32601);
+}
+
+#[test]
+fn shutdown_then_exit() {
+    let mut server = LanguageServer::new();
+    assert_eq!(request(&mut server, "shutdown", "null").find("result"), Some(&Json::Null));
+    assert!(server.shut_down());
+    assert_eq!(error_code(&request(&mut server, "initialize", "{}")), -32600);
+    assert!(server.handle(&json(r#"{"jsonrpc": "2.0", "method": "exit"}"#)).is_empty());
+    assert!(server.exited());
+}
+
+fn frame(body: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn replies(output: Vec<u8>) -> Vec<Json> {
+    let mut output = Cursor::new(output);
+    let mut replies = vec![];
+    while let Some(reply) = read_message(&mut output).unwrap() {
+        replies.push(reply);
+    }
+    replies
+}
+
+#[test]
+fn serves_a_session() {
+    let input = [
+        frame(r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}"#),
+        frame(r#"{"jsonrpc": "2.0", "id": 2, "method": "shutdown"}"#),
+        frame(r#"{"jsonrpc": "2.0", "method": "exit"}"#),
+    ].concat();
+    let mut output = vec![];
+    assert!(serve(Cursor::new(input), &mut output).unwrap());
+    let replies = replies(output);
+    assert_eq!(replies.len(), 2);
+    assert_eq!(replies[1].find("id"), Some(&Json::U64(2)));
+}
+
+#[test]
+fn exiting_without_a_shutdown_is_unclean() {
+    let mut output = vec![];
+    assert!(!serve(Cursor::new(frame(r#"{"jsonrpc": "2.0", "method": "exit"}"#)), &mut output).unwrap());
+}
+
+#[test]
+fn malformed_messages_get_parse_errors() {
+    let input = [
+        frame("{not json"),
+        "X-Whatever: 1\r\n\r\n".to_string(),
+        frame(r#"{"jsonrpc": "2.0", "id": 1, "method": "shutdown"}"#),
+        frame(r#"{"jsonrpc": "2.0", "method": "exit"}"#),
+    ].concat();
+    let mut output = vec![];
+    assert!(serve(Cursor::new(input), &mut output).unwrap());
+    let replies = replies(output);
+    assert_eq!(replies.len(), 3);
+    assert_eq!(error_code(&replies[0]), -32700);
+    assert_eq!(replies[0].find("id"), Some(&Json::Null));
+    assert_eq!(error_code(&replies[1]), -32700);
+    assert_eq!(replies[2].find("id"), Some(&Json::U64(1)));
+}
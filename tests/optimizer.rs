@@ -0,0 +1,108 @@
+//! The optimizer must not change what a program does: every program here is run with and without `-O`, and has to
+//! print the same things, give back the same value and fail the same way either way.
+
+extern crate rasp;
+
+use std::collections::HashSet;
+use rasp::Engine;
+use rasp::internal::Bytecode;
+use rasp::interpreter::Optimizer;
+
+const FACTORIAL: &'static str = include_str!("../factorial.rasp");
+const RECURSION: &'static str = include_str!("../benches/recursion.rasp");
+const STRINGS: &'static str = include_str!("../benches/strings.rasp");
+
+/**
+ * Runs a program, returning what it printed and what it gave back or how it failed.
+ */
+fn run(source: &str, optimize: bool) -> (String, String) {
+    let mut engine = Engine::new();
+    engine.set_optimize(optimize);
+    let output = engine.capture_output();
+    let result = match engine.eval(source) {
+        Ok(Some(value)) => format!("value {}", engine.interpreter().heap().format(&value)),
+        Ok(None) => "no value".to_string(),
+        Err(err) => format!("error {}", err),
+    };
+    (output.contents(), result)
+}
+
+fn assert_same(source: &str) {
+    assert_eq!(run(source, true), run(source, false), "the optimizer changed what this does:\n{}", source);
+}
+
+#[test]
+fn factorial_is_the_same() {
+    assert_same(FACTORIAL);
+}
+
+#[test]
+fn benchmarks_are_the_same() {
+    for call in &["(fib 15)", "(factorial 20)", "(countdown 500)"] {
+        assert_same(&format!("{}\n(&print {})", RECURSION, call));
+    }
+    for call in &["(pass-along \"rasp\" 50)", "(compare \"a\" \"a\" 50)", "(pick \"a\" \"b\" 51)"] {
+        assert_same(&format!("{}\n(&print {})", STRINGS, call));
+    }
+}
+
+#[test]
+fn folded_arithmetic_is_the_same() {
+    assert_same("(&print (* 6 7))");
+    assert_same("(&print (- 2 (* 3 4)))");
+    assert_same("(&print (== (* 2 3) 6))");
+    assert_same("(&print (* 1.5 2))");
+}
+
+#[test]
+fn folded_errors_are_the_same() {
+    assert_same("(&print (- \"a\" 1))");
+    assert_same("(&print (* @tru 2))");
+}
+
+#[test]
+fn constant_conditions_are_the_same() {
+    assert_same("(&if @tru (&print \"yes\") &else (&print \"no\"))");
+    assert_same("(&if @nah (&print \"yes\") &else (&print \"no\"))");
+    assert_same("(&if (== 1 1) (&print \"yes\"))");
+    assert_same("(&if (== 1 2) (&print \"yes\"))");
+    assert_same("(&if \"\" (&print \"empty strings are true\") &else (&print \"empty strings are false\"))");
+    assert_same("(&fun f (x) (&if @tru (&if @nah 1 &else x) &else 2))\n(&print (f 3))");
+}
+
+#[test]
+fn units_are_the_same() {
+    assert_same("()\n(&print 1)\n()");
+    assert_same("(&fun f () () 5)\n(&print (f))");
+}
+
+#[test]
+fn redefined_builtins_are_not_folded() {
+    assert_same("(&fun * (a b) (- a b))\n(&print (* 6 7))");
+    assert_same("(&fun == (a b) @tru)\n(&if (== 1 2) (&print \"redefined\") &else (&print \"builtin\"))");
+}
+
+#[test]
+fn folds_constants() {
+    let optimizer = Optimizer::new(HashSet::new());
+    let code = vec![Bytecode::Push(rasp::Atom::IntLit(6)), Bytecode::Push(rasp::Atom::IntLit(7)),
+                    Bytecode::Call("*".to_string())];
+    let (optimized, lines) = optimizer.optimize(&code, &vec![1, 1, 1]).unwrap();
+    match &optimized[..] {
+        &[Bytecode::Push(rasp::Atom::IntLit(42))] => {},
+        other => panic!("expected a single push of 42, but got {:?}", other),
+    }
+    assert_eq!(lines, vec![1]);
+}
+
+#[test]
+fn rejects_jumps_to_missing_labels() {
+    let optimizer = Optimizer::new(HashSet::new());
+    let code = vec![Bytecode::Label(0), Bytecode::Jump(1)];
+    match optimizer.optimize(&code, &vec![1, 1]) {
+        Err(ref msg) if msg.contains("unknown label 1") => {},
+        other => panic!("expected an unknown label error, but got {:?}", other),
+    }
+    let code = vec![Bytecode::Push(rasp::Atom::BoolLit(true)), Bytecode::JumpTrue(3)];
+    assert!(optimizer.optimize(&code, &vec![1, 1]).is_err());
+}
@@ -0,0 +1,70 @@
+//! Printed output and error output go to the interpreter's writers, which embedders can swap out.
+
+extern crate rasp;
+
+use std::io::Write;
+use rasp::{Atom, Engine, Interpreter, OutputBuffer};
+
+#[test]
+fn captures_printed_output() {
+    let mut engine = Engine::new();
+    let output = engine.capture_output();
+    let errors = engine.capture_errors();
+    engine.eval("(&print \"answer:\")\n(&print 42)").unwrap();
+    assert_eq!(output.contents(), "answer:\n42\n");
+    assert_eq!(errors.contents(), "");
+}
+
+#[test]
+fn captures_error_output_separately() {
+    let mut engine = Engine::new();
+    let output = engine.capture_output();
+    let errors = engine.capture_errors();
+    engine.eval("(&eprint \"something broke\")\n(&print \"fine\")").unwrap();
+    assert_eq!(errors.contents(), "something broke\n");
+    assert_eq!(output.contents(), "fine\n");
+}
+
+#[test]
+fn prints_heap_values() {
+    let mut engine = Engine::new();
+    let output = engine.capture_output();
+    engine.eval("(&print (&cons 1 (&cons \"two\" @nah)))\n(&print [3 4])\n(&print {\"five\" 5})").unwrap();
+    assert_eq!(output.contents(), "(1 two)\n[3 4]\n{five 5}\n");
+}
+
+#[test]
+fn take_empties_the_buffer() {
+    let mut engine = Engine::new();
+    let output = engine.capture_output();
+    engine.eval("(&print 1)").unwrap();
+    assert_eq!(output.take(), "1\n");
+    engine.eval("(&print 2)").unwrap();
+    assert_eq!(output.take(), "2\n");
+    assert_eq!(output.contents(), "");
+}
+
+#[test]
+fn set_output_gives_back_the_old_writer() {
+    let first = OutputBuffer::new();
+    let second = OutputBuffer::new();
+    let mut engine = Engine::new();
+    engine.interpreter().set_output(first.clone());
+    engine.eval("(&print \"first\")").unwrap();
+    let mut old = engine.interpreter().set_output(second.clone());
+    engine.eval("(&print \"second\")").unwrap();
+    old.write_all(b"written through the old writer\n").unwrap();
+    assert_eq!(first.contents(), "first\nwritten through the old writer\n");
+    assert_eq!(second.contents(), "second\n");
+}
+
+#[test]
+fn with_writers_sets_both_writers() {
+    let output = OutputBuffer::new();
+    let errors = OutputBuffer::new();
+    let mut interp = Interpreter::with_writers(output.clone(), errors.clone());
+    interp.call("&print", vec![Atom::StrLit("out".to_string())]).unwrap();
+    interp.call("&eprint", vec![Atom::StrLit("err".to_string())]).unwrap();
+    assert_eq!(output.contents(), "out\n");
+    assert_eq!(errors.contents(), "err\n");
+}
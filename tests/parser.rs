@@ -0,0 +1,16 @@
+//! Source text is split into expressions the way it reads.
+
+extern crate rasp;
+
+use rasp::parse;
+
+#[test]
+fn strings_end_at_the_next_quote() {
+    let exprs = parse("(&print \"a\") (&print \"b\")", "<test>").unwrap();
+    assert_eq!(exprs.len(), 2);
+}
+
+#[test]
+fn strings_stay_on_one_line() {
+    assert!(parse("(&print \"a\n\")", "<test>").is_err());
+}
@@ -0,0 +1,88 @@
+//! The profiler counts calls and keeps track of call stacks, and the tracer logs every instruction.
+
+extern crate rasp;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use rasp::{Engine, OutputBuffer};
+use rasp::interpreter::Hook;
+use rasp::profiler::{Profile, Profiler};
+use rasp::tracer::Tracer;
+
+const SOURCE: &'static str = "\
+(&fun square (x) (* x x))
+(&fun sum-squares (a b) (- (square a) (- 0 (square b))))
+(&fun fib (n) (&if (== n 0) 0 &else (&if (== n 1) 1 &else (- (fib (- n 1)) (- 0 (fib (- n 2)))))))
+(&print (sum-squares 3 4))
+(&print (fib 5))
+";
+
+/**
+ * Runs `SOURCE`, named `prog`, with a hook attached, and gives the hook back once the program is done.
+ */
+fn run_with(hook: Box<Hook>) -> Box<Hook> {
+    let mut engine = Engine::new();
+    let printed = engine.capture_output();
+    let program = engine.compile(SOURCE, "prog").unwrap();
+    engine.interpreter().set_hook(Some(hook));
+    engine.run(&program).unwrap();
+    assert_eq!(printed.contents(), "25\n5\n");
+    engine.interpreter().set_hook(None).unwrap()
+}
+
+fn profile() -> Profile {
+    let profile = Rc::new(RefCell::new(Profile::new()));
+    // Dropping the profiler finishes off the frames that were still running
+    drop(run_with(Box::new(Profiler::new(profile.clone()))));
+    let profile = profile.borrow();
+    profile.clone()
+}
+
+#[test]
+fn counts_calls() {
+    let profile = profile();
+    let calls = |name: &str| profile.functions[name].calls;
+    assert_eq!(calls("prog"), 1);
+    assert_eq!(calls("sum-squares"), 1);
+    assert_eq!(calls("square"), 2);
+    assert_eq!(calls("fib"), 15);
+    assert!(profile.opcodes["Call"] > 0);
+}
+
+#[test]
+fn writes_collapsed_stacks() {
+    let mut out = vec![];
+    profile().write_collapsed(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let stacks: Vec<&str> = out.lines().map(|line| line.rsplitn(2, ' ').nth(1).unwrap()).collect();
+    assert!(stacks.contains(&"prog"), "{}", out);
+    assert!(stacks.contains(&"prog;sum-squares;square"), "{}", out);
+    assert!(stacks.contains(&"prog;fib;fib;fib;fib;fib"), "{}", out);
+    assert!(!stacks.contains(&"prog;fib;fib;fib;fib;fib;fib"), "{}", out);
+    // Every line ends in a number of microseconds
+    assert!(out.lines().all(|line| line.rsplitn(2, ' ').next().unwrap().parse::<u64>().is_ok()), "{}", out);
+}
+
+#[test]
+fn writes_a_report() {
+    let mut out = vec![];
+    profile().write_report(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("     calls   inclusive ms   exclusive ms  function\n"), "{}", out);
+    let fib = out.lines().find(|line| line.ends_with("  fib")).unwrap();
+    assert_eq!(fib.split_whitespace().next(), Some("15"));
+    assert!(out.contains("  executed  instruction\n"), "{}", out);
+}
+
+#[test]
+fn traces_every_instruction() {
+    let trace = OutputBuffer::new();
+    run_with(Box::new(Tracer::new(trace.clone())));
+    let trace = trace.contents();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert!(lines[0].starts_with("   0 prog:0 "), "{}", lines[0]);
+    // Each line starts with the call depth
+    assert!(lines.iter().any(|line| line.starts_with("   2 square:")), "{}", trace);
+    assert!(lines.iter().any(|line| line.starts_with("   5 fib:")), "{}", trace);
+    assert!(!lines.iter().any(|line| line.starts_with("   6 ")), "{}", trace);
+}
@@ -0,0 +1,58 @@
+//! The register machine has to agree with the stack machine, including when functions are defined while code runs.
+
+extern crate rasp;
+
+use std::rc::Rc;
+use rasp::{Engine, Program};
+use rasp::internal::{Bytecode, CodeObject};
+use rasp::interpreter::Backend;
+
+fn run(source: &str, backend: Backend) -> String {
+    let mut engine = Engine::new();
+    engine.set_backend(backend);
+    let output = engine.capture_output();
+    match engine.eval(source) {
+        Ok(_) => output.contents(),
+        Err(err) => format!("{}error: {}", output.contents(), err),
+    }
+}
+
+fn assert_same(source: &str) {
+    assert_eq!(run(source, Backend::Register), run(source, Backend::Stack), "the machines disagree on:\n{}", source);
+}
+
+#[test]
+fn sees_functions_defined_after_a_caller_was_translated() {
+    let source = "(&fun a () (b))\n(&try \"a\" \"&print\")\n(&fun b () 1)\n(&print (a))";
+    assert_same(source);
+    assert_eq!(run(source, Backend::Register), "Function b not found\n1\n");
+}
+
+#[test]
+fn sees_redefined_functions() {
+    assert_same("(&fun f () 1)\n(&fun g () (f))\n(&print (g))\n(&fun f () 2)\n(&print (g))");
+}
+
+#[test]
+fn agrees_on_recursion_and_branches() {
+    assert_same(include_str!("../factorial.rasp"));
+    assert_same("(&fun f (n) (&if (== n 0) \"done\" &else (f (- n 1))))\n(&print (f 100))");
+    assert_same("(&fun g (x) (&if x (&if (== x 2) \"two\" &else \"other\") &else \"none\"))\n\
+                 (&print (g 2))\n(&print (g 3))\n(&print (g @nah))");
+}
+
+#[test]
+fn rejects_backward_jumps() {
+    // The compiler never makes these, but a compiled file could have them
+    let code = vec![Bytecode::Nop, Bytecode::Jump(0)];
+    let program = Program {
+        code: Rc::new(CodeObject::new("<loop>".to_string(), vec![], vec![], code, vec![1, 1])),
+        warnings: vec![],
+    };
+    let mut engine = Engine::new();
+    engine.set_backend(Backend::Register);
+    match engine.run(&program) {
+        Err(err) => assert!(format!("{}", err).contains("backward jump"), "{}", err),
+        Ok(value) => panic!("expected the backward jump to be rejected, but got {:?}", value),
+    }
+}
@@ -0,0 +1,99 @@
+//! Scripts can be kept from reaching outside of the interpreter, and stopped when they go over a limit.
+
+extern crate rasp;
+
+use rasp::{Engine, Error};
+use rasp::internal::Capabilities;
+use rasp::interpreter::{Backend, LimitExceeded, Limits};
+
+fn sandboxed() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_capabilities(Capabilities::none());
+    engine
+}
+
+#[test]
+fn sandbox_turns_off_load() {
+    let err = sandboxed().eval("(&load \"factorial.rasp\")").unwrap_err();
+    assert!(format!("{}", err).contains("&load is disabled"), "{}", err);
+}
+
+#[test]
+fn sandbox_turns_off_extern() {
+    let err = sandboxed().eval("(&extern \"libm.so.6\" cos (double) double)").unwrap_err();
+    assert!(format!("{}", err).contains("&extern is disabled"), "{}", err);
+}
+
+#[test]
+fn sandbox_leaves_pure_code_alone() {
+    assert!(sandboxed().eval("(&fun double (x) (* 2 x))\n(double 21)").is_ok());
+}
+
+/**
+ * Runs a script under some limits on both machines, checking that each one stops it with the same limit.
+ */
+fn stopped_by(limits: Limits, source: &str) -> LimitExceeded {
+    let mut stopped = vec![];
+    for &backend in &[Backend::Stack, Backend::Register] {
+        let mut engine = Engine::new();
+        engine.set_backend(backend);
+        engine.set_limits(limits);
+        match engine.eval(source) {
+            Err(Error::Limit(limit)) => stopped.push(limit),
+            other => panic!("expected {:?} to stop the script, but got {:?}", backend, other),
+        }
+    }
+    assert_eq!(stopped[0], stopped[1]);
+    stopped[0]
+}
+
+const FOREVER: &'static str = "(&fun forever (n) (forever (- n 1)))\n(forever 0)";
+
+#[test]
+fn instruction_limit_stops_a_script() {
+    let limits = Limits { max_instructions: Some(1000), .. Limits::default() };
+    assert_eq!(stopped_by(limits, FOREVER), LimitExceeded::Instructions(1000));
+}
+
+#[test]
+fn call_depth_limit_stops_a_script() {
+    let limits = Limits { max_call_depth: Some(50), .. Limits::default() };
+    assert_eq!(stopped_by(limits, FOREVER), LimitExceeded::CallDepth(50));
+}
+
+#[test]
+fn stack_limit_stops_a_script() {
+    // Every call leaves `n` behind, waiting for the call to come back
+    let source = "(&fun deeper (n) (- n (deeper n)))\n(deeper 0)";
+    let limits = Limits { max_stack: Some(100), .. Limits::default() };
+    assert_eq!(stopped_by(limits, source), LimitExceeded::Stack(100));
+}
+
+#[test]
+fn heap_limit_stops_a_script() {
+    let source = "(&fun grow (list) (grow (&cons \"some string\" list)))\n(grow @nah)";
+    let limits = Limits { max_heap_bytes: Some(4096), .. Limits::default() };
+    assert_eq!(stopped_by(limits, source), LimitExceeded::HeapBytes(4096));
+}
+
+#[test]
+fn limits_leave_small_scripts_alone() {
+    let limits = Limits {
+        max_instructions: Some(1000),
+        max_call_depth: Some(50),
+        max_stack: Some(100),
+        max_heap_bytes: Some(4096),
+    };
+    for &backend in &[Backend::Stack, Backend::Register] {
+        let mut engine = Engine::new();
+        engine.set_backend(backend);
+        engine.set_limits(limits);
+        assert!(engine.eval("(&fun double (x) (* 2 x))\n(double (&car (&cons 21 @nah)))").is_ok());
+    }
+}
+
+#[test]
+fn heap_limit_stops_a_huge_range_before_it_starts() {
+    let limits = Limits { max_heap_bytes: Some(1 << 20), .. Limits::default() };
+    assert_eq!(stopped_by(limits, "(&range 0 9223372036854775807)"), LimitExceeded::HeapBytes(1 << 20));
+}
@@ -0,0 +1,126 @@
+//! `&deftest` files run with `rasp test`, and their results are reported for people, TAP consumers and JUnit readers.
+
+extern crate rasp;
+
+use rasp::testing::{TestCase, run_tests, write_junit, write_report, write_tap};
+use std::io::{self, Write};
+
+const SOURCE: &'static str = "\
+(&fun double (x) (* 2 x))
+
+(&deftest \"doubles\"
+    (&assert-equal 4 (double 2)))
+
+(&deftest \"prints then fails\"
+    (&print \"checking\")
+    (&assert-equal 7 (double 3)))
+
+(&deftest \"asserts <things> & more\"
+    (&assert (== (double 1) 3)))
+";
+
+fn cases() -> Vec<TestCase> {
+    run_tests(SOURCE, "math_test.rasp")
+}
+
+fn written(write: fn(&mut Write, &[TestCase]) -> io::Result<()>) -> String {
+    let mut out = vec![];
+    write(&mut out, &cases()).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn runs_each_test() {
+    let cases = cases();
+    let names: Vec<&str> = cases.iter().map(|case| case.name.as_str()).collect();
+    assert_eq!(names, ["doubles", "prints then fails", "asserts <things> & more"]);
+    let lines: Vec<usize> = cases.iter().map(|case| case.line).collect();
+    assert_eq!(lines, [3, 6, 10]);
+    assert!(cases[0].passed());
+    assert!(!cases[1].passed());
+    assert!(!cases[2].passed());
+}
+
+#[test]
+fn failures_show_expected_and_actual_values() {
+    let cases = cases();
+    let failure = cases[1].failure.as_ref().unwrap();
+    assert_eq!(failure.message, "Expected 7, but got 6");
+    assert_eq!(failure.line, 8);
+    assert_eq!(failure.source, "(&assert-equal 7 (double 3)))");
+    assert_eq!(failure.output, "checking\n");
+    assert_eq!(cases[1].location(), "math_test.rasp:8");
+}
+
+#[test]
+fn unparseable_files_fail_as_one_test() {
+    let cases = run_tests("(&deftest \"broken\"", "broken_test.rasp");
+    assert_eq!(cases.len(), 1);
+    assert_eq!(cases[0].name, "broken_test.rasp");
+    assert!(!cases[0].passed());
+}
+
+#[test]
+fn writes_a_plain_report() {
+    assert_eq!(written(write_report), "\
+test math_test.rasp: doubles ... ok
+test math_test.rasp: prints then fails ... FAILED
+test math_test.rasp: asserts <things> & more ... FAILED
+
+failures:
+
+math_test.rasp:8: prints then fails
+    Expected 7, but got 6
+    in: (&assert-equal 7 (double 3)))
+    | checking
+
+math_test.rasp:11: asserts <things> & more
+    Assertion failed
+    in: (&assert (== (double 1) 3)))
+
+1 passed, 2 failed
+");
+}
+
+#[test]
+fn writes_tap() {
+    assert_eq!(written(write_tap), "\
+TAP version 13
+1..3
+ok 1 - math_test.rasp: doubles
+not ok 2 - math_test.rasp: prints then fails
+  ---
+  message: \"Expected 7, but got 6\"
+  at: \"math_test.rasp:8\"
+  source: \"(&assert-equal 7 (double 3)))\"
+  output: \"checking\\n\"
+  ...
+not ok 3 - math_test.rasp: asserts <things> & more
+  ---
+  message: \"Assertion failed\"
+  at: \"math_test.rasp:11\"
+  source: \"(&assert (== (double 1) 3)))\"
+  ...
+");
+}
+
+#[test]
+fn writes_junit() {
+    assert_eq!(written(write_junit), "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<testsuites tests=\"3\" failures=\"2\">
+  <testsuite name=\"math_test.rasp\" tests=\"3\" failures=\"2\">
+    <testcase name=\"doubles\" classname=\"math_test.rasp\" file=\"math_test.rasp\" line=\"3\"/>
+    <testcase name=\"prints then fails\" classname=\"math_test.rasp\" file=\"math_test.rasp\" line=\"6\">
+      <failure message=\"Expected 7, but got 6\">math_test.rasp:8: (&amp;assert-equal 7 (double 3)))</failure>
+      <system-out>checking
+</system-out>
+    </testcase>
+    <testcase name=\"asserts &lt;things&gt; &amp; more\" classname=\"math_test.rasp\" \
+              file=\"math_test.rasp\" line=\"10\">
+      <failure message=\"Assertion failed\">math_test.rasp:11: (&amp;assert (== (double 1) 3)))</failure>
+    </testcase>
+  </testsuite>
+</testsuites>
+");
+}
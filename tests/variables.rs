@@ -0,0 +1,85 @@
+//! Definitions and assignments give back the value they store, wherever they appear, on both machines and with the
+//! optimizer, and embedders can set and read the same globals from outside.
+
+extern crate rasp;
+
+use rasp::{Atom, Engine};
+use rasp::interpreter::Backend;
+
+fn run(source: &str, backend: Backend, optimize: bool) -> String {
+    let mut engine = Engine::new();
+    engine.set_backend(backend);
+    engine.set_optimize(optimize);
+    let output = engine.capture_output();
+    match engine.eval(source) {
+        Ok(_) => output.contents(),
+        Err(err) => format!("{}error: {}", output.contents(), err),
+    }
+}
+
+fn assert_prints(source: &str, expected: &str) {
+    for &backend in &[Backend::Stack, Backend::Register] {
+        for &optimize in &[false, true] {
+            let output = run(source, backend, optimize);
+            assert_eq!(output, expected, "{:?}, optimized: {}, on:\n{}", backend, optimize, source);
+        }
+    }
+}
+
+#[test]
+fn set_passes_its_value_as_an_argument() {
+    assert_prints("(&def x 1)\n(&print (&set! x 2))\n(&print x)", "2\n2\n");
+    assert_prints("(&fun add (a b) (- a (- 0 b)))\n(&def x 1)\n(&print (add (&set! x 5) x))", "10\n");
+}
+
+#[test]
+fn set_can_be_returned() {
+    let source = "(&fun bump (x) (&set! x (* x 2)))\n(&print (bump 4))";
+    assert_prints(source, "8\n");
+}
+
+#[test]
+fn set_of_an_undefined_variable_still_fails() {
+    let output = run("(&print (&set! nowhere 1))", Backend::Stack, false);
+    assert!(output.starts_with("error:"), "{}", output);
+}
+
+#[test]
+fn def_passes_its_value_as_an_argument() {
+    assert_prints("(&print (&def x 3))\n(&print x)", "3\n3\n");
+    assert_prints("(&fun twice (a) (&print (&def b (* a 2))) b)\n(&print (twice 4))", "8\n8\n");
+}
+
+#[test]
+fn def_can_be_returned() {
+    let source = "(&fun double (x) (&def y (* x 2)))\n(&print (double 4))";
+    assert_prints(source, "8\n");
+}
+
+fn int(value: Option<Atom>) -> i64 {
+    match value {
+        Some(Atom::IntLit(n)) => n,
+        other => panic!("Expected a number, but got {:?}", other),
+    }
+}
+
+#[test]
+fn embedders_can_set_and_read_globals() {
+    for &backend in &[Backend::Stack, Backend::Register] {
+        let mut engine = Engine::new();
+        engine.set_backend(backend);
+        engine.eval("(&fun scaled (n) (* n factor))").unwrap();
+        engine.set_global("factor", Atom::IntLit(3));
+        assert_eq!(int(engine.eval("(scaled 2)").unwrap()), 6, "{:?}", backend);
+        engine.set_global("factor", Atom::IntLit(5));
+        assert_eq!(int(engine.eval("(scaled 2)").unwrap()), 10, "{:?}", backend);
+        engine.eval("(&set! factor (* factor 2))").unwrap();
+        assert_eq!(int(engine.get_global("factor")), 10, "{:?}", backend);
+        engine.set_global("greeting", Atom::StrLit("hi".to_string()));
+        match engine.get_global("greeting") {
+            Some(Atom::StrLit(ref s)) if s == "hi" => (),
+            other => panic!("{:?}: expected \"hi\", but got {:?}", backend, other),
+        }
+        assert!(engine.get_global("missing").is_none(), "{:?}", backend);
+    }
+}